@@ -1,65 +1,105 @@
-use async_nats::connect;
+use async_nats::jetstream::{self, consumer::pull, message::Acker, AckKind};
+use async_nats::{ConnectOptions, Event, Subscriber};
 use bytes::Bytes;
-use ethers::signers::LocalWallet;
-use futures::{future::BoxFuture, StreamExt};
-use hyperliquid_rust_sdk::messages::ExchangeMessage;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::H160;
+use futures::stream::SelectAll;
+use futures::StreamExt;
+use hyperliquid_rust_sdk::messages::{peek_header, prefixed_subject, AckResponse, MessageError};
 use hyperliquid_rust_sdk::{
     messages::{
-        ApproveAgentRequest, ApproveBuilderFeeRequest, CancelOrderRequest, ClassTransferRequest,
-        MessageHeader, MessageType, OrderRequest, SetReferrerRequest, TransferRequest,
+        ApproveAgentRequest, ApproveAgentResponse, ApproveBuilderFeeRequest, AssetPrecision,
+        BatchOrderRequest, BatchOrderResponse, BatchResponse, BatchUpdateLeverageRequest,
+        BulkCancelRequest, BulkCancelResponse, CancelAllRequest, CancelOrderRequest,
+        ClassTransferRequest, ClosePositionRequest, FlattenRequest, FlattenResponse,
+        HeartbeatRequest, ItemResult, MarketKind, MessageType, ModifyOrderRequest, OrderRequest,
+        OrderResponse, OrderStage, OrderStatus, PositionQueryRequest, PositionQueryResponse,
+        PositionSummary, ResolveCloidRequest, ResolveCloidResponse, ScheduleCancelRequest,
+        SetReferrerRequest, SpotTransferRequest, StatusEvent, TransferRequest,
         UpdateIsolatedMarginRequest, UpdateLeverageRequest, VaultTransferRequest, WithdrawRequest,
     },
-    BaseUrl, ClientCancelRequest, ClientCancelRequestCloid, ClientLimit, ClientOrder,
-    ClientOrderRequest, ExchangeClient, MarketOrderParams,
+    BaseUrl, BuilderInfo, ClientCancelRequest, ClientCancelRequestCloid, ClientOrderRequest,
+    DispatchError, DispatchFailure, ExchangeClient, ExchangeDataStatus, ExchangeResponseStatus,
+    HandlerError, HandlerFuture, InfoClient, MarketCloseParams, MarketOrderParams,
+    MessageContext, MessageDispatcher, PartitionKey, PriorityQueue, QueryFuture, Turn,
 };
-use log::{error, info, LevelFilter};
-use lazy_static::lazy_static;
-use std::collections::HashMap;
+use log::{error, info, warn};
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinError, JoinSet};
 use uuid::Uuid;
 
-type HandlerFuture<'a> = BoxFuture<'a, Result<(), Box<dyn std::error::Error>>>;
-type HandlerFn = for<'a> fn(Bytes, &'a ExchangeClient) -> HandlerFuture<'a>;
-
-lazy_static! {
-    static ref HANDLERS: HashMap<MessageType, HandlerFn> = {
-        let mut m: HashMap<MessageType, HandlerFn> = HashMap::new();
-        m.insert(MessageType::Order, order_handler as HandlerFn);
-        m.insert(MessageType::CancelOrder, cancel_handler as HandlerFn);
-        m.insert(MessageType::ModifyOrder, modify_order_handler as HandlerFn);
-        m.insert(MessageType::UpdateLeverage, update_leverage_handler as HandlerFn);
-        m.insert(MessageType::Transfer, transfer_handler as HandlerFn);
-        m.insert(MessageType::Withdraw, withdraw_handler as HandlerFn);
-        m.insert(MessageType::ClassTransfer, class_transfer_handler as HandlerFn);
-        m.insert(
-            MessageType::UpdateIsolatedMargin,
-            update_isolated_margin_handler as HandlerFn,
-        );
-        m.insert(MessageType::ApproveAgent, approve_agent_handler as HandlerFn);
-        m.insert(MessageType::SetReferrer, set_referrer_handler as HandlerFn);
-        m.insert(
-            MessageType::ApproveBuilderFee,
-            approve_builder_fee_handler as HandlerFn,
-        );
-        m
-    };
+fn build_dispatcher(secret: Option<&[u8]>, dedup: Option<(Duration, usize)>) -> MessageDispatcher {
+    let mut dispatcher = MessageDispatcher::new();
+    if let Some(secret) = secret {
+        dispatcher = dispatcher.with_secret(secret);
+    }
+    if let Some((window, capacity)) = dedup {
+        dispatcher = dispatcher.with_dedup(window, capacity);
+    }
+    dispatcher.register_query(MessageType::Order, order_handler);
+    dispatcher.register_query(MessageType::BatchOrder, batch_order_handler);
+    dispatcher.register(MessageType::CancelOrder, cancel_handler);
+    dispatcher.register(MessageType::CancelAll, cancel_all_handler);
+    dispatcher.register_query(MessageType::BulkCancel, bulk_cancel_handler);
+    dispatcher.register_query(MessageType::ClosePosition, close_position_handler);
+    dispatcher.register_query(MessageType::Flatten, flatten_handler);
+    dispatcher.register(MessageType::ScheduleCancel, schedule_cancel_handler);
+    dispatcher.register(MessageType::ModifyOrder, modify_order_handler);
+    dispatcher.register(MessageType::UpdateLeverage, update_leverage_handler);
+    dispatcher.register(
+        MessageType::BatchUpdateLeverage,
+        batch_update_leverage_handler,
+    );
+    dispatcher.register(MessageType::Transfer, transfer_handler);
+    dispatcher.register(MessageType::Withdraw, withdraw_handler);
+    dispatcher.register(MessageType::ClassTransfer, class_transfer_handler);
+    dispatcher.register(MessageType::VaultTransfer, vault_transfer_handler);
+    dispatcher.register(MessageType::SpotTransfer, spot_transfer_handler);
+    dispatcher.register(
+        MessageType::UpdateIsolatedMargin,
+        update_isolated_margin_handler,
+    );
+    dispatcher.register_query(MessageType::ApproveAgent, approve_agent_handler);
+    dispatcher.register(MessageType::SetReferrer, set_referrer_handler);
+    dispatcher.register(MessageType::ApproveBuilderFee, approve_builder_fee_handler);
+    dispatcher.register(MessageType::Heartbeat, heartbeat_handler);
+    dispatcher.register_query(MessageType::PositionQuery, position_query_handler);
+    dispatcher.register_query(MessageType::ResolveCloid, resolve_cloid_handler);
+    dispatcher
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // With `tracing`, log records become events inside the dispatcher's spans
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+    #[cfg(not(feature = "tracing"))]
     env_logger::Builder::new()
-        .filter_level(LevelFilter::Info)
+        .filter_level(log::LevelFilter::Info)
         .init();
 
     let nats_url = env::var("NATS_URL").unwrap_or_else(|_| "nats://localhost:4222".to_string());
     let subject = env::var("NATS_SUBJECT").unwrap_or_else(|_| "hyperliquid.orders".to_string());
+    let prefix = env::var("SUBJECT_PREFIX").unwrap_or_default();
+    let secret = env::var("NATS_HMAC_SECRET").ok().map(String::into_bytes);
 
     let priv_key = env::var("PRIVATE_KEY").unwrap_or_else(|_| {
         "e908f86dbb4d55ac876378565aafeabc187f6690f046459397b17d9b9a19688e".to_string()
     });
     let wallet: LocalWallet = priv_key.parse()?;
 
-    let base = match env::var("BASE_URL")
+    let mut base = match env::var("BASE_URL")
         .unwrap_or_else(|_| "mainnet".to_string())
         .to_lowercase()
         .as_str()
@@ -68,209 +108,2135 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "localhost" => BaseUrl::Localhost,
         _ => BaseUrl::Mainnet,
     };
+    // A local node need not listen on the SDK's default host and port
+    if let Ok(url) = env::var("HL_LOCALHOST_URL") {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("HL_LOCALHOST_URL must be an http(s) URL, got {}", url).into());
+        }
+        if matches!(base, BaseUrl::Localhost) {
+            info!("Using local node at {}", url);
+            base = BaseUrl::LocalhostAt(url);
+        }
+    }
 
-    let client = ExchangeClient::new(None, wallet, Some(base), None, None).await?;
+    #[cfg(feature = "metrics")]
+    {
+        let metrics_addr = env::var("METRICS_ADDR").unwrap_or_else(|_| "0.0.0.0:9090".to_string());
+        hyperliquid_rust_sdk::metrics::install_exporter(metrics_addr.parse()?)?;
+        info!("Serving Prometheus metrics on {}", metrics_addr);
+    }
+
+    let retry = RetryConfig::from_env()?;
+    let client = connect_exchange(&wallet, base.clone(), &retry).await?;
+    let accounts = match env::var("PRIVATE_KEYS") {
+        Ok(keys) => parse_private_keys(&keys)?,
+        Err(_) => HashMap::new(),
+    };
+    if !accounts.is_empty() {
+        let mut names: Vec<&String> = accounts.keys().collect();
+        names.sort();
+        info!("Configured accounts: {:?}", names);
+    }
+    let dedup = match env::var("NATS_DEDUP_WINDOW_SECS") {
+        Ok(secs) => {
+            let capacity = match env::var("NATS_DEDUP_CAPACITY") {
+                Ok(n) => n.parse()?,
+                Err(_) => 10_000,
+            };
+            info!("Dropping duplicate messages seen within {}s", secs);
+            Some((Duration::from_secs(secs.parse()?), capacity))
+        }
+        Err(_) => None,
+    };
+    let mut dispatcher = build_dispatcher(secret.as_deref(), dedup);
+    if let Ok(max) = env::var("NATS_MAX_PAYLOAD_BYTES") {
+        dispatcher = dispatcher.with_max_payload_size(max.parse()?);
+    }
+    if let Ok(ms) = env::var("NATS_HANDLER_TIMEOUT_MS") {
+        dispatcher = dispatcher.with_handler_timeout(Duration::from_millis(ms.parse()?));
+    }
+    if let Ok(spec) = env::var("NATS_HANDLER_TIMEOUTS") {
+        for (msg_type, timeout) in parse_handler_timeouts(&spec)? {
+            dispatcher = dispatcher.with_handler_timeout_for(msg_type, timeout);
+        }
+    }
+    if env::var("NATS_REJECT_EXPIRED").is_ok_and(|v| v.eq_ignore_ascii_case("false") || v == "0") {
+        warn!("NATS_REJECT_EXPIRED is false: expired messages are processed");
+        dispatcher = dispatcher.with_reject_expired(false);
+    }
+    if let Ok(secs) = env::var("MESSAGE_EXPIRY_SECS") {
+        dispatcher = dispatcher.with_reply_expiration(Duration::from_secs(secs.parse()?));
+    }
+    if let Ok(key) = env::var("NATS_ORDER_BY") {
+        let key = match key.as_str() {
+            "asset" => PartitionKey::Asset,
+            "account" => PartitionKey::Account,
+            _ => return Err(format!("NATS_ORDER_BY must be asset or account, got {}", key).into()),
+        };
+        info!("Handling messages in order per {:?}", key);
+        dispatcher = dispatcher.with_ordering(key);
+    }
+    if let Ok(rate) = env::var("NATS_RATE_LIMIT_PER_SEC") {
+        let rate: f64 = rate.parse()?;
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(format!("NATS_RATE_LIMIT_PER_SEC must be positive, got {}", rate).into());
+        }
+        let burst = match env::var("NATS_RATE_LIMIT_BURST") {
+            Ok(burst) => burst.parse()?,
+            Err(_) => rate.ceil() as u32,
+        };
+        let max_wait = match env::var("NATS_RATE_LIMIT_MAX_WAIT_MS") {
+            Ok(ms) => Duration::from_millis(ms.parse()?),
+            Err(_) => Duration::ZERO,
+        };
+        info!(
+            "Limiting each account to {} messages/s (burst {}, max wait {:?})",
+            rate, burst, max_wait
+        );
+        dispatcher = dispatcher.with_rate_limit(rate, burst, max_wait);
+    }
+    let limits = OrderLimits {
+        max_size: env::var("MAX_ORDER_SIZE").ok().map(|v| v.parse()).transpose()?,
+        max_notional: env::var("MAX_ORDER_NOTIONAL").ok().map(|v| v.parse()).transpose()?,
+    };
+    if let Some(max_size) = limits.max_size {
+        info!("Rejecting orders larger than {}", max_size);
+    }
+    if let Some(max_notional) = limits.max_notional {
+        info!("Rejecting orders with notional above {}", max_notional);
+    }
+    ORDER_LIMITS.get_or_init(|| limits);
+    let precision_mode = match env::var("ORDER_PRECISION").as_deref() {
+        Ok("off") | Err(_) => None,
+        Ok("round") => Some(PrecisionMode::Round),
+        Ok("reject") => Some(PrecisionMode::Reject),
+        Ok(other) => {
+            let message = format!("ORDER_PRECISION must be off, round or reject, got {}", other);
+            return Err(message.into());
+        }
+    };
+    if let Some(mode) = precision_mode {
+        let precisions = Precisions::load(mode, &client).await?;
+        info!(
+            "Applying {:?} to orders finer than their asset allows ({} assets)",
+            mode,
+            precisions.assets.len()
+        );
+        PRECISIONS.get_or_init(|| precisions);
+    }
+    let call_retry = CallRetry {
+        attempts: match env::var("EXCHANGE_CALL_ATTEMPTS") {
+            Ok(n) => n.parse()?,
+            Err(_) => 2,
+        },
+        backoff: match env::var("EXCHANGE_CALL_RETRY_MS") {
+            Ok(ms) => Duration::from_millis(ms.parse()?),
+            Err(_) => Duration::from_millis(250),
+        },
+    };
+    CALL_RETRY.get_or_init(|| call_retry);
+    if env::var("DRY_RUN").is_ok_and(|v| v.eq_ignore_ascii_case("true") || v == "1") {
+        DRY_RUN.store(true, Ordering::Relaxed);
+        warn!("DRY_RUN is set: messages are validated and logged, not sent to the exchange");
+    }
+    if env::var("AUTO_CLOID").is_ok_and(|v| v.eq_ignore_ascii_case("true") || v == "1") {
+        AUTO_CLOID.store(true, Ordering::Relaxed);
+    }
+    let heartbeat_window = match env::var("HEARTBEAT_WINDOW_SECS") {
+        Ok(secs) => Duration::from_secs(secs.parse::<u64>()?.max(1)),
+        Err(_) => Duration::from_secs(30),
+    };
+    HEARTBEATS.get_or_init(|| Heartbeats::new(heartbeat_window));
+    let cloid_cache_size = match env::var("CLOID_CACHE_SIZE") {
+        Ok(size) => size.parse()?,
+        Err(_) => 10_000,
+    };
+    if cloid_cache_size > 0 {
+        CLOIDS.get_or_init(|| CloidCache::new(cloid_cache_size));
+    }
 
     info!("Connecting to NATS server at {}", nats_url);
-    let nc = connect(&nats_url).await?;
+    let nc = connect_options().await?.connect(&nats_url).await?;
     info!("Connected to NATS server");
+    if let Ok(subject) = env::var("NATS_STATUS_SUBJECT") {
+        let subject = prefixed_subject(&prefix, &subject);
+        info!("Publishing order status events to {}", subject);
+        STATUS_EVENTS.get_or_init(|| StatusEvents {
+            nats: nc.clone(),
+            subject,
+        });
+    }
+
+    let mut routes = match env::var("NATS_ROUTES") {
+        Ok(spec) => parse_routes(&spec)?,
+        Err(_) => vec![Route {
+            pattern: subject,
+            allowed: None,
+        }],
+    };
+    for route in &mut routes {
+        route.pattern = prefixed_subject(&prefix, &route.pattern);
+    }
+    let queue_group = env::var("NATS_QUEUE_GROUP").ok();
 
-    let mut sub = nc.subscribe(subject.clone()).await?;
-    info!("Subscribed to {}", subject);
+    let mut source = match env::var("NATS_JETSTREAM_STREAM") {
+        Ok(stream) => {
+            let consumer = env::var("NATS_JETSTREAM_CONSUMER")
+                .unwrap_or_else(|_| "hyperliquid-nats-service".to_string());
+            let messages = jetstream::new(nc.clone())
+                .get_stream(&stream)
+                .await?
+                .get_or_create_consumer(
+                    &consumer,
+                    pull::Config {
+                        durable_name: Some(consumer.clone()),
+                        filter_subjects: routes.iter().map(|route| route.pattern.clone()).collect(),
+                        ..Default::default()
+                    },
+                )
+                .await?
+                .messages()
+                .await?;
+            info!("Consuming JetStream stream {} as durable consumer {}", stream, consumer);
+            MessageSource::JetStream(Box::new(messages))
+        }
+        Err(_) => {
+            let mut subs = SelectAll::new();
+            for route in &routes {
+                let sub = match &queue_group {
+                    Some(group) => {
+                        let sub = nc.queue_subscribe(route.pattern.clone(), group.clone()).await?;
+                        info!("Subscribed to {} in queue group {}", route.pattern, group);
+                        sub
+                    }
+                    None => {
+                        let sub = nc.subscribe(route.pattern.clone()).await?;
+                        info!("Subscribed to {}", route.pattern);
+                        sub
+                    }
+                };
+                subs.push(sub);
+            }
+            MessageSource::Core(subs)
+        }
+    };
+    if secret.is_some() {
+        info!("Message signature verification enabled");
+    }
     info!("NATS service started. Waiting for messages...");
 
-    while let Some(msg) = sub.next().await {
-        if let Err(e) = process_message(&msg, &client).await {
-            error!("Error processing message: {}", e);
+    let max_concurrency = match env::var("NATS_MAX_CONCURRENCY") {
+        Ok(n) => n.parse::<usize>()?.max(1),
+        Err(_) => 1,
+    };
+    info!("Processing up to {} messages concurrently", max_concurrency);
+    let when_full = match env::var("NATS_WHEN_FULL").as_deref() {
+        Ok("queue") | Err(_) => WhenFull::Queue,
+        Ok("reject") => WhenFull::Reject,
+        Ok(other) => {
+            return Err(format!("NATS_WHEN_FULL must be queue or reject, got {}", other).into())
+        }
+    };
+    let max_queued = match env::var("NATS_MAX_QUEUED") {
+        Ok(n) => n.parse::<usize>()?.max(1),
+        Err(_) => 64,
+    };
+    let semaphore = Arc::new(Semaphore::new(max_concurrency));
+    let service = Arc::new(Service {
+        routes,
+        dispatcher,
+        accounts: Accounts::new(wallet, client, base, accounts, retry),
+        nats: nc.clone(),
+    });
+    tokio::spawn(watch_heartbeats(service.clone()));
+    let mut tasks = JoinSet::new();
+    let mut queue: PriorityQueue<(async_nats::Message, Option<Acker>)> = PriorityQueue::new();
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        // Hand queued messages to free slots, highest priority first
+        while !queue.is_empty() {
+            let Ok(permit) = semaphore.clone().try_acquire_owned() else {
+                break;
+            };
+            let (msg, acker) = queue.pop().expect("queue is not empty");
+            let turn = service.dispatcher.claim_turn(&msg.payload);
+            tasks.spawn(handle_message(msg, acker, permit, turn, service.clone()));
+        }
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some(result) = tasks.join_next() => log_task_result(result),
+            // With the queue full, stop reading and leave the backlog with NATS
+            msg = source.next(), if queue.len() < max_queued => match msg {
+                Some((msg, acker)) => match when_full {
+                    WhenFull::Queue => {
+                        let priority = service.dispatcher.priority_of(&msg.payload);
+                        queue.push(priority, (msg, acker));
+                    }
+                    WhenFull::Reject => match semaphore.clone().try_acquire_owned() {
+                        Ok(permit) => {
+                            let turn = service.dispatcher.claim_turn(&msg.payload);
+                            tasks.spawn(handle_message(msg, acker, permit, turn, service.clone()));
+                        }
+                        Err(_) => reject_busy(&msg, acker, &service).await,
+                    },
+                },
+                None => {
+                    info!("All subscriptions closed");
+                    return Ok(());
+                }
+            },
+        }
+    }
+
+    info!(
+        "Shutdown requested with {} messages in flight and {} queued",
+        tasks.len(),
+        queue.len()
+    );
+    while let Some((msg, acker)) = queue.pop() {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let turn = service.dispatcher.claim_turn(&msg.payload);
+        tasks.spawn(handle_message(msg, acker, permit, turn, service.clone()));
+    }
+    // Stop the server from sending more, then finish whatever was already delivered.
+    // Unacknowledged JetStream messages are redelivered, so there is nothing to drain.
+    if let MessageSource::Core(subs) = &mut source {
+        for sub in subs.iter_mut() {
+            sub.unsubscribe().await?;
+        }
+        while let Some(msg) = subs.next().await {
+            let permit = semaphore.clone().acquire_owned().await?;
+            let turn = service.dispatcher.claim_turn(&msg.payload);
+            tasks.spawn(handle_message(msg, None, permit, turn, service.clone()));
         }
     }
+    let in_flight = tasks.len();
+    while let Some(result) = tasks.join_next().await {
+        log_task_result(result);
+    }
+    info!("Drained {} in-flight messages, shutting down", in_flight);
+    // Replies and status events may still be buffered in the client; draining
+    // sends them before the connection closes. The client is unusable after.
+    nc.drain().await?;
+    let duplicates = service.dispatcher.duplicates_dropped();
+    if duplicates > 0 {
+        info!("Dropped {} duplicate messages", duplicates);
+    }
     Ok(())
 }
 
+/// State shared by every message task
+struct Service {
+    routes: Vec<Route>,
+    dispatcher: MessageDispatcher,
+    accounts: Accounts,
+    /// Used to publish replies to requests that set a reply subject
+    nats: async_nats::Client,
+}
+
+/// Exchange clients for the default wallet and the named accounts from `PRIVATE_KEYS`
+struct Accounts {
+    default_wallet: LocalWallet,
+    base: BaseUrl,
+    wallets: HashMap<String, LocalWallet>,
+    retry: RetryConfig,
+    /// Clients keyed by account name, `None` being the default wallet. Named
+    /// accounts are built the first time they are used, and any client is
+    /// rebuilt after `retry.stale_after` consecutive transport errors.
+    clients: tokio::sync::Mutex<HashMap<Option<String>, CachedClient>>,
+}
+
+struct CachedClient {
+    client: Arc<ExchangeClient>,
+    transport_errors: u32,
+}
+
+impl Accounts {
+    fn new(
+        default_wallet: LocalWallet,
+        default_client: ExchangeClient,
+        base: BaseUrl,
+        wallets: HashMap<String, LocalWallet>,
+        retry: RetryConfig,
+    ) -> Self {
+        let default = CachedClient {
+            client: Arc::new(default_client),
+            transport_errors: 0,
+        };
+        Self {
+            default_wallet,
+            base,
+            wallets,
+            retry,
+            clients: tokio::sync::Mutex::new(HashMap::from([(None, default)])),
+        }
+    }
+
+    /// Client for the account named in a message header, or the default client
+    async fn client_for(&self, account: Option<&str>) -> Result<Arc<ExchangeClient>, HandlerError> {
+        let wallet = match account {
+            Some(account) => self
+                .wallets
+                .get(account)
+                .ok_or_else(|| HandlerError::Validation(format!("Unknown account {:?}", account)))?,
+            None => &self.default_wallet,
+        };
+        let key = account.map(str::to_string);
+        let mut clients = self.clients.lock().await;
+        if let Some(cached) = clients.get(&key) {
+            return Ok(cached.client.clone());
+        }
+        let client = Arc::new(connect_exchange(wallet, self.base.clone(), &self.retry).await?);
+        info!("Created exchange client for account {}", account.unwrap_or("<default>"));
+        clients.insert(
+            key,
+            CachedClient {
+                client: client.clone(),
+                transport_errors: 0,
+            },
+        );
+        Ok(client)
+    }
+
+    /// Track consecutive transport errors for an account's client, dropping the
+    /// client once it looks stale so the next message rebuilds it
+    async fn record_result(&self, account: Option<&str>, result: &Result<(), DispatchFailure>) {
+        let key = account.map(str::to_string);
+        let mut clients = self.clients.lock().await;
+        let Some(cached) = clients.get_mut(&key) else {
+            return;
+        };
+        match result {
+            Err(e) if is_transport_error(e.as_ref()) => cached.transport_errors += 1,
+            _ => cached.transport_errors = 0,
+        }
+        if cached.transport_errors >= self.retry.stale_after {
+            warn!(
+                "Rebuilding exchange client for account {} after {} consecutive transport errors",
+                account.unwrap_or("<default>"),
+                cached.transport_errors
+            );
+            clients.remove(&key);
+        }
+    }
+}
+
+/// Whether a handler failed because the exchange could not be reached
+fn is_transport_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    error
+        .downcast_ref::<DispatchError>()
+        .is_some_and(|dispatch| matches!(dispatch.error, HandlerError::Transport(_)))
+}
+
+/// Backoff for creating exchange clients, and when to consider one stale
+struct RetryConfig {
+    /// Attempts before giving up; 0 retries forever
+    attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    /// Consecutive transport errors after which a client is rebuilt
+    stale_after: u32,
+}
+
+impl RetryConfig {
+    /// Read `EXCHANGE_CONNECT_ATTEMPTS`, `EXCHANGE_RETRY_INITIAL_MS`,
+    /// `EXCHANGE_RETRY_MAX_MS` and `EXCHANGE_STALE_AFTER_ERRORS`
+    fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        fn var<T: std::str::FromStr>(
+            name: &str,
+            default: T,
+        ) -> Result<T, Box<dyn std::error::Error>>
+        where
+            T::Err: std::error::Error + 'static,
+        {
+            match env::var(name) {
+                Ok(value) => Ok(value.parse()?),
+                Err(_) => Ok(default),
+            }
+        }
+        Ok(Self {
+            attempts: var("EXCHANGE_CONNECT_ATTEMPTS", 0)?,
+            initial_backoff: Duration::from_millis(var("EXCHANGE_RETRY_INITIAL_MS", 500)?),
+            max_backoff: Duration::from_millis(var("EXCHANGE_RETRY_MAX_MS", 30_000)?),
+            stale_after: var("EXCHANGE_STALE_AFTER_ERRORS", 5)?.max(1),
+        })
+    }
+}
+
+/// Create an exchange client, retrying with exponential backoff while the
+/// exchange is unreachable
+async fn connect_exchange(
+    wallet: &LocalWallet,
+    base: BaseUrl,
+    retry: &RetryConfig,
+) -> Result<ExchangeClient, hyperliquid_rust_sdk::Error> {
+    let mut backoff = retry.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match ExchangeClient::new(None, wallet.clone(), Some(base.clone()), None, None).await {
+            Ok(client) => return Ok(client),
+            Err(e) if retry.attempts == 0 || attempt < retry.attempts => {
+                warn!(
+                    "Failed to create exchange client (attempt {}): {}; retrying in {:?}",
+                    attempt, e, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(retry.max_backoff);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Parse `PRIVATE_KEYS`, either a JSON object of account names to keys or
+/// comma-separated `name=key` entries
+fn parse_private_keys(
+    spec: &str,
+) -> Result<HashMap<String, LocalWallet>, Box<dyn std::error::Error>> {
+    let keys: HashMap<String, String> = if spec.trim_start().starts_with('{') {
+        serde_json::from_str(spec)?
+    } else {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .split_once('=')
+                    .map(|(name, key)| (name.trim().to_string(), key.trim().to_string()))
+                    .ok_or_else(|| format!("Invalid account {:?}, expected name=key", entry))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    keys.into_iter()
+        .map(|(name, key)| {
+            let wallet = key
+                .parse::<LocalWallet>()
+                .map_err(|e| format!("Invalid private key for account {}: {}", name, e))?;
+            Ok((name, wallet))
+        })
+        .collect()
+}
+
+/// Where the service reads messages from
+enum MessageSource {
+    /// Core NATS subscriptions, one per route
+    Core(SelectAll<Subscriber>),
+    /// A durable JetStream pull consumer; messages are acknowledged after they are handled
+    JetStream(Box<pull::Stream>),
+}
+
+impl MessageSource {
+    /// Next message, with its JetStream acknowledgement handle if it has one
+    async fn next(&mut self) -> Option<(async_nats::Message, Option<Acker>)> {
+        match self {
+            MessageSource::Core(subs) => subs.next().await.map(|msg| (msg, None)),
+            MessageSource::JetStream(messages) => loop {
+                match messages.next().await? {
+                    Ok(msg) => {
+                        let (msg, acker) = msg.split();
+                        return Some((msg, Some(acker)));
+                    }
+                    Err(e) => error!("Error receiving JetStream message: {}", e),
+                }
+            },
+        }
+    }
+}
+
+/// What to do with a message that arrives while `NATS_MAX_CONCURRENCY` messages
+/// are in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WhenFull {
+    /// Queue up to `NATS_MAX_QUEUED` messages, handed out highest header
+    /// priority first, then wait for a message to finish before reading more
+    Queue,
+    /// Turn the message away at once with `MessageError::Busy`
+    Reject,
+}
+
+/// Turn away a message that arrived while the service was at capacity.
+///
+/// A publisher waiting on a reply gets a failed `AckResponse`, and a JetStream
+/// message is negatively acknowledged so the server redelivers it.
+async fn reject_busy(msg: &async_nats::Message, acker: Option<Acker>, service: &Service) {
+    let error = MessageError::Busy;
+    warn!("Rejecting message on {}: {}", msg.subject, error);
+    if let (Some(reply), Ok(header)) = (&msg.reply, peek_header(&msg.payload)) {
+        match AckResponse::failure(&error).to_reply(&header) {
+            Ok(ack) => publish_reply(service, reply, ack).await,
+            Err(e) => error!("Error encoding reply: {}", e),
+        }
+    }
+    if let Some(acker) = acker {
+        if let Err(e) = acker.ack_with(AckKind::Nak(None)).await {
+            error!("Error negatively acknowledging message: {}", e);
+        }
+    }
+}
+
+/// Route a message on a spawned task, holding `permit` until it completes.
+///
+/// JetStream messages are acknowledged only once they were handled successfully,
+/// so a failure or crash before then leaves them to be redelivered.
+async fn handle_message(
+    msg: async_nats::Message,
+    acker: Option<Acker>,
+    permit: OwnedSemaphorePermit,
+    mut turn: Option<Turn>,
+    service: Arc<Service>,
+) {
+    if let Some(turn) = &mut turn {
+        turn.ready().await;
+    }
+    match route_message(&msg, &service).await {
+        Ok(()) => {
+            if let Some(acker) = acker {
+                if let Err(e) = acker.ack().await {
+                    error!("Error acknowledging message: {}", e);
+                }
+            }
+        }
+        Err(e) => error!("Error processing message: {}", e),
+    }
+    drop(turn);
+    drop(permit);
+}
+
+fn log_task_result(result: Result<(), JoinError>) {
+    if let Err(e) = result {
+        error!("Message task failed: {}", e);
+    }
+}
+
+/// A subscribed subject pattern and the message types accepted on it
+struct Route {
+    pattern: String,
+    /// `None` accepts every message type
+    allowed: Option<Vec<MessageType>>,
+}
+
+/// Parse `NATS_ROUTES`, e.g. `hyperliquid.orders=Order,CancelOrder;hyperliquid.transfers=Transfer`
+fn parse_routes(spec: &str) -> Result<Vec<Route>, Box<dyn std::error::Error>> {
+    let mut routes = Vec::new();
+    for entry in spec.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (pattern, types) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid route {:?}, expected subject=Type,Type", entry))?;
+        let allowed = types
+            .split(',')
+            .map(|t| t.trim().parse())
+            .collect::<Result<Vec<MessageType>, _>>()?;
+        routes.push(Route {
+            pattern: pattern.trim().to_string(),
+            allowed: Some(allowed),
+        });
+    }
+    if routes.is_empty() {
+        return Err("NATS_ROUTES is set but contains no routes".into());
+    }
+    Ok(routes)
+}
+
+/// Parse `NATS_HANDLER_TIMEOUTS`, e.g. `Order=2000,Transfer=30000` (milliseconds)
+fn parse_handler_timeouts(
+    spec: &str,
+) -> Result<Vec<(MessageType, Duration)>, Box<dyn std::error::Error>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (msg_type, ms) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid handler timeout {:?}, expected Type=ms", entry))?;
+            Ok((msg_type.trim().parse()?, Duration::from_millis(ms.trim().parse()?)))
+        })
+        .collect()
+}
+
+/// Match a NATS subject against a pattern containing `*` and `>` wildcards
+fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject_tokens = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject_tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (token, Some(actual)) if token == actual => {}
+            _ => return false,
+        }
+    }
+    subject_tokens.next().is_none()
+}
+
+/// Dispatch a message if its type is permitted on the subject it arrived on.
+///
+/// The message runs against the exchange client of the account named in its
+/// header. If the publisher set a reply subject, an `AckResponse` reporting the
+/// outcome is published to it.
+async fn route_message(
+    msg: &async_nats::Message,
+    service: &Service,
+) -> Result<(), DispatchFailure> {
+    let Some(route) = service
+        .routes
+        .iter()
+        .find(|route| subject_matches(&route.pattern, &msg.subject))
+    else {
+        warn!("Dropping message on unrouted subject {}", msg.subject);
+        return Ok(());
+    };
+    let allowed = route.allowed.as_deref();
+
+    // Unreadable headers are left for the dispatcher to reject
+    let header = peek_header(&msg.payload).ok();
+    let account = header.as_ref().and_then(|header| header.account.as_deref());
+    let client = match service.accounts.client_for(account).await {
+        Ok(client) => client,
+        Err(e) => {
+            if let (Some(reply), Some(header)) = (&msg.reply, &header) {
+                let ack = AckResponse::failure(&e).to_reply(header)?;
+                publish_reply(service, reply, ack).await;
+            }
+            return Err(e.into());
+        }
+    };
+
+    let result = match &msg.reply {
+        Some(reply) => {
+            let (result, ack) = service
+                .dispatcher
+                .dispatch_with_ack(msg.payload.clone(), &client, allowed)
+                .await;
+            if let Some(ack) = ack {
+                publish_reply(service, reply, ack).await;
+            }
+            result
+        }
+        None => match allowed {
+            Some(allowed) => {
+                service
+                    .dispatcher
+                    .dispatch_allowed(msg.payload.clone(), &client, allowed)
+                    .await
+            }
+            None => process_message(msg, &service.dispatcher, &client).await,
+        },
+    };
+    service.accounts.record_result(account, &result).await;
+    result
+}
+
+async fn publish_reply(service: &Service, reply: &async_nats::Subject, ack: Vec<u8>) {
+    if let Err(e) = service.nats.publish(reply.clone(), ack.into()).await {
+        error!("Error publishing reply to {}: {}", reply, e);
+    }
+}
+
+/// Resolve once the process receives Ctrl-C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+/// Build NATS connection options from `NATS_CREDS`, `NATS_TOKEN` and `NATS_ROOT_CA`
+async fn connect_options() -> Result<ConnectOptions, Box<dyn std::error::Error>> {
+    let mut opts = ConnectOptions::new();
+    if let Ok(creds) = env::var("NATS_CREDS") {
+        info!("Using NATS credentials file {}", creds);
+        opts = opts.credentials_file(creds).await?;
+    }
+    if let Ok(token) = env::var("NATS_TOKEN") {
+        opts = opts.token(token);
+    }
+    if let Ok(root_ca) = env::var("NATS_ROOT_CA") {
+        info!("Using NATS root CA {}", root_ca);
+        opts = opts.add_root_certificates(root_ca.into()).require_tls(true);
+    }
+    if let Ok(capacity) = env::var("NATS_SUBSCRIPTION_CAPACITY") {
+        let capacity: usize = capacity.parse()?;
+        info!("Buffering up to {} messages per subscription", capacity);
+        opts = opts.subscription_capacity(capacity);
+    }
+    // The client drops messages for a subscription whose buffer is full and
+    // only reports it through this callback
+    Ok(opts.event_callback(|event| async move {
+        match event {
+            Event::SlowConsumer(sid) => warn!(
+                "Subscription {} is a slow consumer: its buffer is full and NATS messages are \
+                 being dropped",
+                sid
+            ),
+            event => info!("NATS connection event: {}", event),
+        }
+    }))
+}
+
 async fn process_message(
     msg: &async_nats::Message,
+    dispatcher: &MessageDispatcher,
     client: &ExchangeClient,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let data = msg.payload.clone();
-    if data.len() < 4 {
-        return Err("message too short".into());
+) -> Result<(), DispatchFailure> {
+    dispatcher.dispatch(msg.payload.clone(), client).await
+}
+
+/// Per-order caps read from `MAX_ORDER_SIZE` and `MAX_ORDER_NOTIONAL`
+#[derive(Debug)]
+struct OrderLimits {
+    max_size: Option<f64>,
+    max_notional: Option<f64>,
+}
+
+/// Set once at startup; handlers are plain functions and cannot capture config
+static ORDER_LIMITS: OnceLock<OrderLimits> = OnceLock::new();
+
+impl OrderLimits {
+    /// Reject an order above either cap. Market orders have no price of their
+    /// own, so their notional is taken at the asset's current mid.
+    async fn check(
+        &self,
+        asset: &str,
+        sz: f64,
+        px: Option<f64>,
+        client: &ExchangeClient,
+    ) -> Result<(), HandlerError> {
+        if let Some(max_size) = self.max_size {
+            if sz > max_size {
+                return Err(HandlerError::Validation(format!(
+                    "Order size {} for {} exceeds MAX_ORDER_SIZE {}",
+                    sz, asset, max_size
+                )));
+            }
+        }
+        let Some(max_notional) = self.max_notional else {
+            return Ok(());
+        };
+        let px = match px {
+            Some(px) => px,
+            None => {
+                let info = InfoClient::new(None, Some(info_base_url(client))).await?;
+                info.all_mids()
+                    .await?
+                    .get(asset)
+                    .ok_or_else(|| HandlerError::Exchange(format!("No mid price for {}", asset)))?
+                    .parse::<f64>()?
+            }
+        };
+        let notional = sz * px;
+        if notional > max_notional {
+            return Err(HandlerError::Validation(format!(
+                "Order notional {} for {} exceeds MAX_ORDER_NOTIONAL {}",
+                notional, asset, max_notional
+            )));
+        }
+        Ok(())
     }
-    let header_len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
-    let header: MessageHeader = rmp_serde::from_slice(&data[4..4 + header_len])?;
+}
 
-    if let Some(handler) = HANDLERS.get(&header.msg_type) {
-        handler(data, client).await?
-    } else {
-        log::warn!("No handler registered for {:?}", header.msg_type);
+/// What to do with orders sized or priced finer than their asset allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PrecisionMode {
+    /// Round to the nearest allowed size and price
+    Round,
+    /// Reject the order, naming the nearest allowed values
+    Reject,
+}
+
+/// Each asset's precision, fetched once at startup when `ORDER_PRECISION` is set
+#[derive(Debug)]
+struct Precisions {
+    mode: PrecisionMode,
+    assets: HashMap<String, AssetPrecision>,
+}
+
+static PRECISIONS: OnceLock<Precisions> = OnceLock::new();
+
+impl Precisions {
+    /// Read perp precisions from the client's metadata and spot precisions,
+    /// by pair name and by `@<index>` name, from the exchange
+    async fn load(
+        mode: PrecisionMode,
+        client: &ExchangeClient,
+    ) -> Result<Self, hyperliquid_rust_sdk::Error> {
+        let mut assets: HashMap<_, _> = client
+            .meta
+            .universe
+            .iter()
+            .map(|asset| (asset.name.clone(), AssetPrecision::perp(asset.sz_decimals)))
+            .collect();
+        let spot_meta = &client.spot_meta;
+        let token = |index: Option<&usize>| {
+            index.and_then(|&index| spot_meta.tokens.iter().find(|token| token.index == index))
+        };
+        for asset in &spot_meta.universe {
+            let base = token(asset.tokens.first());
+            let (Some(base), Some(quote)) = (base, token(asset.tokens.get(1))) else {
+                continue;
+            };
+            let precision = AssetPrecision::spot(base.sz_decimals.into());
+            assets.insert(format!("{}/{}", base.name, quote.name), precision);
+            assets.insert(asset.name.clone(), precision);
+        }
+        Ok(Self { mode, assets })
+    }
+
+    /// Round or reject `req` if it is finer than its asset allows. Assets
+    /// missing from the metadata are left to the exchange.
+    fn apply(&self, req: &mut OrderRequest) -> Result<(), HandlerError> {
+        let Some(&precision) = self.assets.get(&req.asset) else {
+            return Ok(());
+        };
+        match self.mode {
+            PrecisionMode::Round => req.normalize(precision)?,
+            PrecisionMode::Reject => req.check_precision(precision)?,
+        }
+        Ok(())
+    }
+}
+
+fn apply_precision(req: &mut OrderRequest) -> Result<(), HandlerError> {
+    match PRECISIONS.get() {
+        Some(precisions) => precisions.apply(req),
+        None => Ok(()),
+    }
+}
+
+/// Set once at startup from `DRY_RUN`
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Set once at startup from `AUTO_CLOID`
+static AUTO_CLOID: AtomicBool = AtomicBool::new(false);
+
+/// Where order lifecycle events go, from `NATS_STATUS_SUBJECT`
+struct StatusEvents {
+    nats: async_nats::Client,
+    subject: String,
+}
+
+/// Set once at startup; handlers are plain functions and cannot capture config
+static STATUS_EVENTS: OnceLock<StatusEvents> = OnceLock::new();
+
+/// Publish `event` about the request in `context` to the status subject, if one
+/// is configured. Failures are logged and never fail the order.
+async fn publish_status(context: &MessageContext, event: StatusEvent) {
+    let Some(events) = STATUS_EVENTS.get() else {
+        return;
+    };
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return;
+    }
+    let payload = match event.to_event(&context.header) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("{} Error encoding status event: {}", context, e);
+            return;
+        }
+    };
+    if let Err(e) = events.nats.publish(events.subject.clone(), payload.into()).await {
+        error!("{} Error publishing status event to {}: {}", context, events.subject, e);
+    }
+}
+
+/// When each strategy last sent a `Heartbeat`, checked against the window from
+/// `HEARTBEAT_WINDOW_SECS`
+struct Heartbeats {
+    window: Duration,
+    /// Strategy id to the account its heartbeats named and when the last one arrived
+    last_seen: std::sync::Mutex<HashMap<String, (Option<String>, Instant)>>,
+}
+
+impl Heartbeats {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_seen: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a heartbeat from `strategy_id` for `account`, returning whether the
+    /// strategy was not already being watched
+    fn beat(&self, strategy_id: &str, account: Option<String>) -> bool {
+        let mut last_seen = self.last_seen.lock().unwrap();
+        last_seen
+            .insert(strategy_id.to_string(), (account, Instant::now()))
+            .is_none()
+    }
+
+    /// Stop watching the strategies whose last heartbeat is older than the
+    /// window, returning each with its account
+    fn take_overdue(&self, now: Instant) -> Vec<(String, Option<String>)> {
+        let mut last_seen = self.last_seen.lock().unwrap();
+        let overdue: Vec<String> = last_seen
+            .iter()
+            .filter(|(_, (_, seen))| now.duration_since(*seen) > self.window)
+            .map(|(strategy_id, _)| strategy_id.clone())
+            .collect();
+        overdue
+            .into_iter()
+            .filter_map(|strategy_id| {
+                let (account, _) = last_seen.remove(&strategy_id)?;
+                Some((strategy_id, account))
+            })
+            .collect()
+    }
+}
+
+/// Set once at startup; handlers are plain functions and cannot capture config
+static HEARTBEATS: OnceLock<Heartbeats> = OnceLock::new();
+
+/// Order IDs of resting orders placed with a cloid, answering
+/// `ResolveCloidRequest`s. Holds at most `capacity` orders, dropping the oldest
+/// first, and forgets orders once they are seen to fill or are cancelled.
+struct CloidCache {
+    capacity: usize,
+    entries: std::sync::Mutex<CloidEntries>,
+}
+
+#[derive(Default)]
+struct CloidEntries {
+    oids: HashMap<Uuid, u64>,
+    /// Cloids in the order they were recorded, oldest first
+    recorded: VecDeque<Uuid>,
+}
+
+impl CloidCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: std::sync::Mutex::new(CloidEntries::default()),
+        }
+    }
+
+    /// Record that the order placed with `cloid` rests on the exchange as `oid`
+    fn record(&self, cloid: Uuid, oid: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.oids.insert(cloid, oid).is_none() {
+            entries.recorded.push_back(cloid);
+        }
+        while entries.oids.len() > self.capacity {
+            let Some(oldest) = entries.recorded.pop_front() else {
+                break;
+            };
+            entries.oids.remove(&oldest);
+        }
+    }
+
+    fn resolve(&self, cloid: &Uuid) -> Option<u64> {
+        self.entries.lock().unwrap().oids.get(cloid).copied()
+    }
+
+    /// Forget the orders with any of `oids` or `cloids`
+    fn forget(&self, oids: &[u64], cloids: &[Uuid]) {
+        let mut entries = self.entries.lock().unwrap();
+        let CloidEntries { oids: known, recorded } = &mut *entries;
+        known.retain(|cloid, oid| !oids.contains(oid) && !cloids.contains(cloid));
+        recorded.retain(|cloid| known.contains_key(cloid));
+    }
+}
+
+/// Set once at startup from `CLOID_CACHE_SIZE`, unless it is `0`
+static CLOIDS: OnceLock<CloidCache> = OnceLock::new();
+
+/// Record the order ID of a resting order that has a cloid, or forget the
+/// cloid of an order that filled
+fn track_cloid(response: &OrderResponse) {
+    let Some(cache) = CLOIDS.get() else {
+        return;
+    };
+    let Some(cloid) = response.cloid.as_deref().and_then(|c| Uuid::parse_str(c).ok()) else {
+        return;
+    };
+    match (response.status, response.oid) {
+        (OrderStatus::Resting, Some(oid)) => cache.record(cloid, oid),
+        (OrderStatus::Filled, _) => cache.forget(&[], &[cloid]),
+        _ => {}
+    }
+}
+
+/// Forget the cloids of orders that were cancelled or are otherwise no longer
+/// open
+fn forget_orders(oids: &[u64], cloids: &[Uuid]) {
+    if let Some(cache) = CLOIDS.get() {
+        cache.forget(oids, cloids);
+    }
+}
+
+/// How far ahead the cancel for a missed heartbeat is scheduled; the exchange
+/// only accepts scheduled cancels at least 5 seconds away
+const MISSED_HEARTBEAT_CANCEL_DELAY: Duration = Duration::from_secs(5);
+
+/// Schedule a cancel of every open order on the account of each strategy that
+/// misses its heartbeat window. The strategy is watched again from its next
+/// heartbeat.
+async fn watch_heartbeats(service: Arc<Service>) {
+    let Some(heartbeats) = HEARTBEATS.get() else {
+        return;
+    };
+    let mut ticks = tokio::time::interval((heartbeats.window / 4).max(Duration::from_millis(250)));
+    loop {
+        ticks.tick().await;
+        for (strategy_id, account) in heartbeats.take_overdue(Instant::now()) {
+            let account = account.as_deref();
+            warn!(
+                "Strategy {} missed its {:?} heartbeat window, cancelling orders for account {}",
+                strategy_id,
+                heartbeats.window,
+                account.unwrap_or("<default>")
+            );
+            if let Err(e) = cancel_for_missed_heartbeat(&service, account).await {
+                error!("Error scheduling cancel for strategy {}: {}", strategy_id, e);
+            }
+        }
+    }
+}
+
+async fn cancel_for_missed_heartbeat(
+    service: &Service,
+    account: Option<&str>,
+) -> Result<(), HandlerError> {
+    let client = service.accounts.client_for(account).await?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let time_ms = (now + MISSED_HEARTBEAT_CANCEL_DELAY).as_millis() as u64;
+    if DRY_RUN.load(Ordering::Relaxed) {
+        info!("[DRY RUN] Would call schedule_cancel({:?})", Some(time_ms));
+        return Ok(());
+    }
+    match client.schedule_cancel(Some(time_ms), None).await? {
+        ExchangeResponseStatus::Ok(_) => Ok(()),
+        ExchangeResponseStatus::Err(e) => Err(HandlerError::Exchange(e)),
+    }
+}
+
+/// In dry-run mode, log the exchange call described by `call` and return
+/// `true` so the caller skips it. Read-only info requests are still made.
+fn skip_in_dry_run(context: &MessageContext, call: impl FnOnce() -> String) -> bool {
+    if !DRY_RUN.load(Ordering::Relaxed) {
+        return false;
+    }
+    info!("{} [DRY RUN] Would call {}", context, call());
+    true
+}
+
+/// Bounded retry of exchange calls that fail for a recoverable reason, read
+/// from `EXCHANGE_CALL_ATTEMPTS` and `EXCHANGE_CALL_RETRY_MS`
+#[derive(Debug)]
+struct CallRetry {
+    /// Attempts per call, including the first
+    attempts: u32,
+    /// Delay before the first retry, doubled after each one
+    backoff: Duration,
+}
+
+/// Set once at startup; handlers are plain functions and cannot capture config
+static CALL_RETRY: OnceLock<CallRetry> = OnceLock::new();
+
+/// Make the exchange call `call`, repeating it while it fails recoverably.
+///
+/// Every handler sends its exchange calls through here so they share one
+/// classification: only rate limiting and stale nonces, which the exchange
+/// rejects without executing anything, are retried. Other errors are returned
+/// at once, and transport errors are never retried since the request may have
+/// been executed.
+async fn with_retry<F, Fut>(
+    context: &MessageContext,
+    mut call: F,
+) -> Result<ExchangeResponseStatus, hyperliquid_rust_sdk::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<ExchangeResponseStatus, hyperliquid_rust_sdk::Error>>,
+{
+    let (attempts, mut backoff) = CALL_RETRY
+        .get()
+        .map_or((1, Duration::ZERO), |retry| (retry.attempts.max(1), retry.backoff));
+    let mut attempt = 1;
+    loop {
+        let result = call().await;
+        let error = match &result {
+            Err(e) if e.is_recoverable() => e.to_string(),
+            Ok(status) if status.is_recoverable_error() => format!("{:?}", status),
+            _ => return result,
+        };
+        if attempt >= attempts {
+            return result;
+        }
+        warn!(
+            "{} Exchange call failed (attempt {}): {}; retrying in {:?}",
+            context, attempt, error, backoff
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
     }
-    Ok(())
 }
 
+/// Place the order in `req`, returning what the exchange did with it, or
+/// `None` in dry-run mode
 async fn handle_order(
-    req: OrderRequest,
+    mut req: OrderRequest,
+    context: &MessageContext,
     client: &ExchangeClient,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let sz = req.size.parse::<f64>()?;
-    let cloid = match &req.cloid {
-        Some(c) => Some(Uuid::parse_str(c)?),
-        None => None,
+) -> Result<Option<OrderResponse>, HandlerError> {
+    req.validate_with(context.clock())?;
+    apply_precision(&mut req)?;
+    let vault = vault_client(client, req.vault_address.as_deref())?;
+    let client = vault.as_ref().unwrap_or(client);
+    let builder = req.builder.as_ref().map(|builder| BuilderInfo {
+        builder: builder.to_lowercase(),
+        fee: req.builder_fee_tenths_bps.unwrap_or_default().into(),
+    });
+    if !req.is_market() {
+        let order = ClientOrderRequest::try_from(&req)?;
+        check_limits(&order.asset, order.sz, Some(order.limit_px), client).await?;
+        let response = place_order(order, builder, &req.size, context, client).await?;
+        if let (Some(expires_at_ms), Some(response)) = (req.expires_at_ms, &response) {
+            if let (OrderStatus::Resting, Some(oid)) = (response.status, response.oid) {
+                expire_order(expires_at_ms, &req.asset, oid, context, client);
+            }
+        }
+        return Ok(response);
+    }
+
+    let params = MarketOrderParams::try_from(&req)?;
+    if req.market == MarketKind::Spot && builder.is_some() {
+        return Err(HandlerError::Validation(
+            "Builder fees are not supported for spot market orders".to_string(),
+        ));
+    }
+    check_limits(&req.asset, params.sz, None, client).await?;
+    if skip_in_dry_run(context, || {
+        format!("market_open({:?}, builder: {:?})", params, builder)
+    }) {
+        return Ok(None);
+    }
+    let status = match (req.market, builder) {
+        (MarketKind::Perp, None) => {
+            with_retry(context, || client.market_open(params.clone())).await?
+        }
+        (MarketKind::Perp, Some(builder)) => {
+            with_retry(context, || {
+                client.market_open_with_builder(params.clone(), builder.clone())
+            })
+            .await?
+        }
+        (MarketKind::Spot, _) => {
+            with_retry(context, || client.spot_market_open(params.clone())).await?
+        }
     };
+    order_outcome(status, &req.size, params.cloid).map(Some)
+}
 
-    if let Some(px) = req.limit_price {
-        let px = px.parse::<f64>()?;
-        let order = ClientOrderRequest {
-            asset: req.asset,
-            is_buy: req.is_buy,
-            reduce_only: req.reduce_only,
-            limit_px: px,
-            sz,
-            cloid,
-            order_type: ClientOrder::Limit(ClientLimit {
-                tif: req.time_in_force,
-            }),
-        };
-        client.order(order, None).await?;
-    } else {
-        let params = MarketOrderParams {
-            asset: &req.asset,
-            is_buy: req.is_buy,
-            sz,
-            px: None,
-            slippage: None,
-            cloid,
-            wallet: None,
-        };
-        client.market_open(params).await?;
+/// Place a single order of `size`, through `builder` if one is given
+async fn place_order(
+    order: ClientOrderRequest,
+    builder: Option<BuilderInfo>,
+    size: &str,
+    context: &MessageContext,
+    client: &ExchangeClient,
+) -> Result<Option<OrderResponse>, HandlerError> {
+    if skip_in_dry_run(context, || format!("order({:?}, builder: {:?})", order, builder)) {
+        return Ok(None);
+    }
+    let status = match builder {
+        Some(builder) => {
+            with_retry(context, || {
+                client.order_with_builder(order.clone(), None, builder.clone())
+            })
+            .await?
+        }
+        None => with_retry(context, || client.order(order.clone(), None)).await?,
+    };
+    order_outcome(status, size, order.cloid).map(Some)
+}
+
+/// Cancel the resting order `oid` on `asset` in the background once
+/// `expires_at_ms` passes. The timer lives in this process, so a restart
+/// forgets it.
+fn expire_order(
+    expires_at_ms: u64,
+    asset: &str,
+    oid: u64,
+    context: &MessageContext,
+    client: &ExchangeClient,
+) {
+    let now = context.clock().now_millis();
+    let delay = Duration::from_millis(expires_at_ms.saturating_sub(now));
+    info!("{} Order {} expires in {:?}", context, oid, delay);
+    let cancel = ClientCancelRequest {
+        asset: asset.to_string(),
+        oid,
+    };
+    let (context, client) = (context.to_string(), client.clone());
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let result = client.cancel(cancel, None).await.map_err(HandlerError::from);
+        let result = result.and_then(order_statuses);
+        if result.is_ok() {
+            forget_orders(&[oid], &[]);
+        }
+        match result {
+            Ok(statuses) if matches!(statuses.first(), Some(ExchangeDataStatus::Success)) => {
+                info!("{} Cancelled order {} at its expiry", context, oid)
+            }
+            // Most often the order filled or was cancelled before it expired
+            Ok(statuses) => {
+                info!("{} Order {} not cancelled at expiry: {:?}", context, oid, statuses)
+            }
+            Err(e) => error!("{} Error cancelling order {} at its expiry: {}", context, oid, e),
+        }
+    });
+}
+
+/// Describe the exchange's reply to a single order of `size` placed with
+/// `cloid`, failing if the order was rejected
+fn order_outcome(
+    status: ExchangeResponseStatus,
+    size: &str,
+    cloid: Option<Uuid>,
+) -> Result<OrderResponse, HandlerError> {
+    status_outcome(order_statuses(status)?.into_iter().next(), size, cloid)
+}
+
+/// The per-order statuses in the exchange's reply to an order action
+fn order_statuses(status: ExchangeResponseStatus) -> Result<Vec<ExchangeDataStatus>, HandlerError> {
+    match status {
+        ExchangeResponseStatus::Ok(response) => {
+            Ok(response.data.map(|data| data.statuses).unwrap_or_default())
+        }
+        ExchangeResponseStatus::Err(e) => Err(HandlerError::Exchange(e)),
+    }
+}
+
+/// Describe the exchange's `status` for one order of `size` placed with
+/// `cloid`, failing if the order was rejected
+fn status_outcome(
+    status: Option<ExchangeDataStatus>,
+    size: &str,
+    cloid: Option<Uuid>,
+) -> Result<OrderResponse, HandlerError> {
+    let response: Result<_, HandlerError> = match status {
+        Some(ExchangeDataStatus::Filled(fill)) => {
+            let response = OrderResponse::filled(fill.oid, &fill.total_sz, &fill.avg_px);
+            Ok(match remaining_size(size, &fill.total_sz)? {
+                Some(remaining) => response.with_remaining_size(&remaining),
+                None => response,
+            })
+        }
+        Some(ExchangeDataStatus::Resting(order)) => Ok(OrderResponse::resting(order.oid)),
+        Some(ExchangeDataStatus::WaitingForFill) => {
+            Ok(OrderResponse::new(OrderStatus::WaitingForFill))
+        }
+        Some(ExchangeDataStatus::WaitingForTrigger) => {
+            Ok(OrderResponse::new(OrderStatus::WaitingForTrigger))
+        }
+        Some(ExchangeDataStatus::Error(e)) => Err(HandlerError::Exchange(e)),
+        Some(ExchangeDataStatus::Success) | None => Err(HandlerError::Exchange(
+            "Exchange response carried no order status".to_string(),
+        )),
+    };
+    let response = response?;
+    Ok(match cloid {
+        Some(cloid) => response.with_cloid(&cloid.to_string()),
+        None => response,
+    })
+}
+
+/// The part of `requested` left after `filled`, if any, at the precision of the inputs
+fn remaining_size(requested: &str, filled: &str) -> Result<Option<String>, HandlerError> {
+    let decimals = |size: &str| size.split_once('.').map_or(0, |(_, fraction)| fraction.len());
+    let remaining = requested.parse::<f64>()? - filled.parse::<f64>()?;
+    let remaining = format!("{:.*}", decimals(requested).max(decimals(filled)), remaining);
+    Ok((remaining.parse::<f64>()? > 0.0).then_some(remaining))
+}
+
+/// Parse a message's hex address field
+fn parse_address(address: &str) -> Result<H160, HandlerError> {
+    address
+        .parse()
+        .map_err(|e| HandlerError::Validation(format!("Invalid address {}: {}", address, e)))
+}
+
+/// A client acting for the message's `vault_address`, or `None` to use the
+/// service's own client
+fn vault_client(
+    client: &ExchangeClient,
+    vault_address: Option<&str>,
+) -> Result<Option<ExchangeClient>, HandlerError> {
+    vault_address
+        .map(|address| Ok(client.for_vault(Some(parse_address(address)?))))
+        .transpose()
+}
+
+/// Place every order in `req` as one exchange action with the request's
+/// grouping, returning what the exchange did with each, or `None` in dry-run
+/// mode.
+///
+/// The whole group is checked before anything is sent, failing the message if
+/// any order is invalid. Once sent, each order's outcome is reported on its own.
+async fn handle_batch_order(
+    mut req: BatchOrderRequest,
+    context: &MessageContext,
+    client: &ExchangeClient,
+) -> Result<Option<Vec<Result<OrderResponse, HandlerError>>>, HandlerError> {
+    req.validate()?;
+    for order in &mut req.orders {
+        apply_precision(order)?;
+    }
+    let vault_address = req.orders.first().and_then(|order| order.vault_address.as_deref());
+    let vault = vault_client(client, vault_address)?;
+    let client = vault.as_ref().unwrap_or(client);
+    let mut orders = Vec::with_capacity(req.orders.len());
+    for order in &req.orders {
+        let order = ClientOrderRequest::try_from(order)?;
+        check_limits(&order.asset, order.sz, Some(order.limit_px), client).await?;
+        orders.push(order);
+    }
+    let grouping = req.grouping.as_str();
+    if skip_in_dry_run(context, || format!("bulk_order({:?}, grouping: {})", orders, grouping)) {
+        return Ok(None);
+    }
+    let status = with_retry(context, || {
+        client.bulk_order_with_grouping(orders.clone(), None, grouping)
+    })
+    .await?;
+
+    let mut statuses = order_statuses(status)?.into_iter();
+    let outcomes = req
+        .orders
+        .iter()
+        .zip(&orders)
+        .map(|(req, order)| status_outcome(statuses.next(), &req.size, order.cloid))
+        .collect();
+    Ok(Some(outcomes))
+}
+
+async fn check_limits(
+    asset: &str,
+    sz: f64,
+    px: Option<f64>,
+    client: &ExchangeClient,
+) -> Result<(), HandlerError> {
+    match ORDER_LIMITS.get() {
+        Some(limits) => limits.check(asset, sz, px, client).await,
+        None => Ok(()),
     }
-    Ok(())
 }
 
 async fn handle_cancel(
     req: CancelOrderRequest,
+    context: &MessageContext,
+    client: &ExchangeClient,
+) -> Result<(), HandlerError> {
+    req.validate()?;
+    let vault = vault_client(client, req.vault_address.as_deref())?;
+    let client = vault.as_ref().unwrap_or(client);
+    if req.order_id.is_some() {
+        let cancel = ClientCancelRequest::try_from(&req)?;
+        if !skip_in_dry_run(context, || format!("cancel({:?})", cancel)) {
+            with_retry(context, || client.cancel(cancel.clone(), None)).await?;
+            forget_orders(&[cancel.oid], &[]);
+        }
+    } else if req.cloid.is_some() {
+        let cancel = ClientCancelRequestCloid::try_from(&req)?;
+        if !skip_in_dry_run(context, || format!("cancel_by_cloid({:?})", cancel)) {
+            with_retry(context, || client.cancel_by_cloid(cancel.clone(), None)).await?;
+            forget_orders(&[], &[cancel.cloid]);
+        }
+    }
+    Ok(())
+}
+
+/// Cancel every order in `req`, by order ID then by cloid.
+///
+/// In dry-run mode every id is reported as cancelled.
+async fn handle_bulk_cancel(
+    req: BulkCancelRequest,
+    context: &MessageContext,
+    client: &ExchangeClient,
+) -> Result<BulkCancelResponse, HandlerError> {
+    // Parse every cloid before cancelling anything
+    req.validate()?;
+    let cloids = req
+        .cloids
+        .iter()
+        .map(|cloid| Uuid::parse_str(cloid))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if skip_in_dry_run(context, || {
+        format!(
+            "bulk_cancel({} order ids) and bulk_cancel_by_cloid({} cloids) on {}",
+            req.order_ids.len(),
+            cloids.len(),
+            req.asset
+        )
+    }) {
+        let mut cancelled: Vec<String> = req.order_ids.iter().map(u64::to_string).collect();
+        cancelled.extend(req.cloids);
+        return Ok(BulkCancelResponse {
+            cancelled,
+            not_found: Vec::new(),
+        });
+    }
+
+    let mut response = BulkCancelResponse::default();
+    if !req.order_ids.is_empty() {
+        let cancels: Vec<_> = req
+            .order_ids
+            .iter()
+            .map(|&oid| ClientCancelRequest {
+                asset: req.asset.clone(),
+                oid,
+            })
+            .collect();
+        let status = with_retry(context, || client.bulk_cancel(cancels.clone(), None)).await?;
+        let ids = req.order_ids.iter().map(u64::to_string).collect();
+        sort_cancel_statuses(&mut response, ids, status)?;
+    }
+    if !cloids.is_empty() {
+        let cancels: Vec<_> = cloids
+            .iter()
+            .map(|&cloid| ClientCancelRequestCloid {
+                asset: req.asset.clone(),
+                cloid,
+            })
+            .collect();
+        let status =
+            with_retry(context, || client.bulk_cancel_by_cloid(cancels.clone(), None)).await?;
+        sort_cancel_statuses(&mut response, req.cloids, status)?;
+    }
+    forget_orders(&req.order_ids, &cloids);
+    Ok(response)
+}
+
+/// Sort `ids` into cancelled and not found using the exchange's per-cancel statuses
+fn sort_cancel_statuses(
+    response: &mut BulkCancelResponse,
+    ids: Vec<String>,
+    status: ExchangeResponseStatus,
+) -> Result<(), HandlerError> {
+    let statuses = match status {
+        ExchangeResponseStatus::Ok(response) => {
+            response.data.map(|data| data.statuses).unwrap_or_default()
+        }
+        ExchangeResponseStatus::Err(e) => return Err(HandlerError::Exchange(e)),
+    };
+    for (i, id) in ids.into_iter().enumerate() {
+        match statuses.get(i) {
+            Some(ExchangeDataStatus::Success) => response.cancelled.push(id),
+            _ => response.not_found.push(id),
+        }
+    }
+    Ok(())
+}
+
+async fn handle_cancel_all(
+    req: CancelAllRequest,
+    context: &MessageContext,
     client: &ExchangeClient,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(oid) = req.order_id {
-        let cancel = ClientCancelRequest {
-            asset: req.asset,
-            oid,
+) -> Result<usize, HandlerError> {
+    let info = InfoClient::new(None, Some(info_base_url(client))).await?;
+    let user = client.vault_address.unwrap_or_else(|| client.wallet.address());
+    let cancels: Vec<ClientCancelRequest> = info
+        .open_orders(user)
+        .await?
+        .into_iter()
+        .filter(|o| req.asset.as_ref().is_none_or(|asset| &o.coin == asset))
+        .map(|o| ClientCancelRequest {
+            asset: o.coin,
+            oid: o.oid,
+        })
+        .collect();
+
+    let count = cancels.len();
+    if count > 0 && !skip_in_dry_run(context, || format!("bulk_cancel({:?})", cancels)) {
+        with_retry(context, || client.bulk_cancel(cancels.clone(), None)).await?;
+        let oids: Vec<_> = cancels.iter().map(|cancel| cancel.oid).collect();
+        forget_orders(&oids, &[]);
+    }
+    Ok(count)
+}
+
+/// Map the client's API URL back to the `BaseUrl` used for info requests
+fn info_base_url(client: &ExchangeClient) -> BaseUrl {
+    BaseUrl::from_url(&client.http_client.base_url)
+}
+
+fn order_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
+    Box::pin(async move {
+        let mut req = context.decode::<OrderRequest>(&data)?;
+        if req.cloid.is_none() && AUTO_CLOID.load(Ordering::Relaxed) {
+            let cloid = Uuid::from_bytes(context.header.msg_id);
+            info!("{} Assigned cloid {} to order", context, cloid);
+            req.cloid = Some(cloid.to_string());
+        }
+        let (asset, cloid) = (req.asset.clone(), req.cloid.clone());
+        let msg_id = context.header.msg_id;
+        let submitted = StatusEvent::new(msg_id, &asset, cloid.clone(), OrderStage::Submitted);
+        publish_status(&context, submitted).await;
+
+        let response = match handle_order(req, &context, client).await {
+            Ok(Some(response)) => response,
+            Ok(None) => return Ok(AckResponse::success().to_reply(&context.header)?),
+            Err(e) => {
+                let rejected = StatusEvent::rejected(msg_id, &asset, cloid, &e.to_string());
+                publish_status(&context, rejected).await;
+                return Err(e);
+            }
         };
-        client.cancel(cancel, None).await?;
-    } else if let Some(cloid) = req.cloid {
-        let cancel = ClientCancelRequestCloid {
-            asset: req.asset,
-            cloid: Uuid::parse_str(&cloid)?,
+        log_order_response(&context, &asset, &response);
+        track_cloid(&response);
+        publish_status(&context, StatusEvent::from_response(msg_id, &asset, &response)).await;
+        Ok(response.to_reply(&context.header)?)
+    })
+}
+
+fn batch_order_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<BatchOrderRequest>(&data)?;
+        let assets: Vec<_> = req.orders.iter().map(|order| order.asset.clone()).collect();
+        let Some(outcomes) = handle_batch_order(req, &context, client).await? else {
+            return Ok(AckResponse::success().to_reply(&context.header)?);
         };
-        client.cancel_by_cloid(cancel, None).await?;
+        for (i, (asset, outcome)) in assets.iter().zip(&outcomes).enumerate() {
+            match outcome {
+                Ok(order) => {
+                    log_order_response(&context, asset, order);
+                    track_cloid(order);
+                }
+                Err(e) => warn!("{} {} order {} of the batch rejected: {}", context, asset, i, e),
+            }
+        }
+        // With any order rejected, report which ones succeeded
+        if outcomes.iter().any(Result::is_err) {
+            let results = outcomes
+                .iter()
+                .enumerate()
+                .map(|(i, outcome)| match outcome {
+                    Ok(order) => ItemResult::success(i, order.oid),
+                    Err(e) => ItemResult::failure(i, &e.to_string()),
+                })
+                .collect();
+            return Ok(BatchResponse::new(results).to_reply(&context.header)?);
+        }
+        let orders = outcomes.into_iter().flatten().collect();
+        Ok(BatchOrderResponse::new(orders).to_reply(&context.header)?)
+    })
+}
+
+fn log_order_response(context: &MessageContext, asset: &str, response: &OrderResponse) {
+    let oid = response.oid.map_or_else(|| "-".to_string(), |oid| oid.to_string());
+    let filled = response.filled_size.as_deref().unwrap_or("0");
+    let avg_price = response.avg_price.as_deref().unwrap_or("-");
+    match (response.status, &response.remaining_size) {
+        (OrderStatus::Filled, Some(remaining)) => warn!(
+            "{} {} order {} partially filled: {} at avg price {}, {} unfilled",
+            context, asset, oid, filled, avg_price, remaining
+        ),
+        (OrderStatus::Filled, None) => info!(
+            "{} {} order {} filled: {} at avg price {}",
+            context, asset, oid, filled, avg_price
+        ),
+        (status, _) => info!("{} {} order {} accepted: {:?}", context, asset, oid, status),
     }
-    Ok(())
 }
 
-fn order_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn cancel_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<CancelOrderRequest>(&data)?;
+        handle_cancel(req, &context, client).await
+    })
+}
+
+fn bulk_cancel_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
     Box::pin(async move {
-        let req = <OrderRequest as ExchangeMessage>::from_msgpack(&data)?;
-        handle_order(req, client).await
+        let req = context.decode::<BulkCancelRequest>(&data)?;
+        let response = handle_bulk_cancel(req, &context, client).await?;
+        info!(
+            "{} Cancelled {} orders, {} not found",
+            context,
+            response.cancelled.len(),
+            response.not_found.len()
+        );
+        Ok(response.to_reply(&context.header)?)
+    })
+}
+
+/// Close the position in the requested asset, replying with the closing order's
+/// `OrderResponse`, or with a successful `AckResponse` if there was nothing to close
+fn close_position_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<ClosePositionRequest>(&data)?;
+        req.validate()?;
+        let Some(szi) = open_position_size(&req.asset, client).await? else {
+            let note = format!("No open {} position, nothing to close", req.asset);
+            info!("{} {}", context, note);
+            return Ok(AckResponse::success_with_message(note).to_reply(&context.header)?);
+        };
+        match close_position(&req.asset, &szi, req.slippage, &context, client).await? {
+            Some(response) => Ok(response.to_reply(&context.header)?),
+            None => Ok(AckResponse::success().to_reply(&context.header)?),
+        }
     })
 }
 
-fn cancel_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+/// Cancel the resting orders in the requested asset if asked to, then close its
+/// position, replying with a `FlattenResponse` reporting both
+fn flatten_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<FlattenRequest>(&data)?;
+        req.validate()?;
+        let mut cancelled = 0;
+        if req.cancel_resting {
+            let cancel = CancelAllRequest::for_asset(&req.asset);
+            cancelled = handle_cancel_all(cancel, &context, client).await?;
+            info!("{} Cancelled {} resting {} orders", context, cancelled, req.asset);
+        }
+        let close = match open_position_size(&req.asset, client).await? {
+            Some(szi) => close_position(&req.asset, &szi, req.slippage, &context, client).await?,
+            None => {
+                info!("{} No open {} position, nothing to close", context, req.asset);
+                None
+            }
+        };
+        Ok(FlattenResponse::new(cancelled, close).to_reply(&context.header)?)
+    })
+}
+
+/// The signed size of the open position in `asset`, if there is one
+async fn open_position_size(
+    asset: &str,
+    client: &ExchangeClient,
+) -> Result<Option<String>, HandlerError> {
+    let user = client.vault_address.unwrap_or_else(|| client.wallet.address());
+    let info = InfoClient::new(None, Some(info_base_url(client))).await?;
+    Ok(info
+        .user_state(user)
+        .await?
+        .asset_positions
+        .into_iter()
+        .find(|p| p.position.coin == asset)
+        .map(|p| p.position.szi)
+        .filter(|szi| szi.parse::<f64>().is_ok_and(|szi| szi != 0.0)))
+}
+
+/// Close the position of signed size `szi` in `asset` with a reduce-only
+/// market order, returning what the exchange did with it, or `None` in dry-run
+/// mode
+async fn close_position(
+    asset: &str,
+    szi: &str,
+    slippage: Option<f64>,
+    context: &MessageContext,
+    client: &ExchangeClient,
+) -> Result<Option<OrderResponse>, HandlerError> {
+    let params = MarketCloseParams {
+        asset,
+        sz: None,
+        px: None,
+        slippage,
+        cloid: None,
+        wallet: None,
+    };
+    if skip_in_dry_run(context, || format!("market_close({:?}) of {}", params, szi)) {
+        return Ok(None);
+    }
+    let status = with_retry(context, || client.market_close(params.clone())).await?;
+    let response = order_outcome(status, szi.trim_start_matches('-'), None)?;
+    log_order_response(context, asset, &response);
+    Ok(Some(response))
+}
+
+fn cancel_all_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        let req = <CancelOrderRequest as ExchangeMessage>::from_msgpack(&data)?;
-        handle_cancel(req, client).await
+        let req = context.decode::<CancelAllRequest>(&data)?;
+        let count = handle_cancel_all(req, &context, client).await?;
+        info!("{} Cancelled {} open orders", context, count);
+        Ok(())
     })
 }
 
-fn modify_order_handler<'a>(_data: Bytes, _client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn schedule_cancel_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        log::warn!("modify order message handling not implemented");
+        let req = context.decode::<ScheduleCancelRequest>(&data)?;
+        if skip_in_dry_run(&context, || format!("schedule_cancel({:?})", req.time_ms)) {
+            return Ok(());
+        }
+        with_retry(&context, || client.schedule_cancel(req.time_ms, None)).await?;
+        match req.time_ms {
+            Some(time_ms) => info!("{} Scheduled cancel of all orders at {}", context, time_ms),
+            None => info!("{} Cleared scheduled cancel", context),
+        }
         Ok(())
     })
 }
 
-fn update_leverage_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn modify_order_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    _client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        let req = <UpdateLeverageRequest as ExchangeMessage>::from_msgpack(&data)?;
-        client
-            .update_leverage(req.leverage, &req.asset, req.is_cross, None)
-            .await?;
+        let req = context.decode::<ModifyOrderRequest>(&data)?;
+        req.validate()?;
+        log::warn!("{} modify order message handling not implemented", context);
+        Ok(())
+    })
+}
+
+fn update_leverage_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<UpdateLeverageRequest>(&data)?;
+        if skip_in_dry_run(&context, || format!("update_leverage({:?})", req)) {
+            return Ok(());
+        }
+        with_retry(&context, || {
+            client.update_leverage(req.leverage, &req.asset, req.is_cross, None)
+        })
+        .await?;
         Ok(())
     })
 }
 
-fn transfer_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn batch_update_leverage_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<BatchUpdateLeverageRequest>(&data)?;
+        let total = req.updates.len();
+
+        // Apply every update even if earlier ones fail, collecting the failures
+        let mut failures = Vec::new();
+        for update in req.updates {
+            if skip_in_dry_run(&context, || format!("update_leverage({:?})", update)) {
+                continue;
+            }
+            let result = with_retry(&context, || {
+                client.update_leverage(update.leverage, &update.asset, update.is_cross, None)
+            })
+            .await;
+            match result {
+                Ok(ExchangeResponseStatus::Ok(_)) => {}
+                Ok(ExchangeResponseStatus::Err(e)) => {
+                    failures.push(format!("{}: {}", update.asset, e))
+                }
+                Err(e) => failures.push(format!("{}: {}", update.asset, e)),
+            }
+        }
+
+        if failures.is_empty() {
+            info!("{} Updated leverage for {} assets", context, total);
+            Ok(())
+        } else {
+            Err(HandlerError::Exchange(format!(
+                "{} of {} leverage updates failed: {}",
+                failures.len(),
+                total,
+                failures.join("; ")
+            )))
+        }
+    })
+}
+
+fn transfer_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        let req = <TransferRequest as ExchangeMessage>::from_msgpack(&data)?;
+        let req = context.decode::<TransferRequest>(&data)?;
+        req.validate()?;
+        if skip_in_dry_run(&context, || format!("transfer({:?})", req)) {
+            return Ok(());
+        }
         if req.asset.to_uppercase() == "USDC" {
-            client
-                .usdc_transfer(&req.amount, &req.destination, None)
-                .await?;
+            with_retry(&context, || {
+                client.usdc_transfer(&req.amount, &req.destination, None)
+            })
+            .await?;
         } else {
-            client
-                .spot_transfer(&req.amount, &req.destination, &req.asset, None)
-                .await?;
+            with_retry(&context, || {
+                client.spot_transfer(&req.amount, &req.destination, &req.asset, None)
+            })
+            .await?;
         }
         Ok(())
     })
 }
 
-fn withdraw_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn withdraw_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        let req = <WithdrawRequest as ExchangeMessage>::from_msgpack(&data)?;
-        client
-            .withdraw_from_bridge(&req.amount, &req.destination, None)
-            .await?;
+        let req = context.decode::<WithdrawRequest>(&data)?;
+        if skip_in_dry_run(&context, || format!("withdraw_from_bridge({:?})", req)) {
+            return Ok(());
+        }
+        with_retry(&context, || {
+            client.withdraw_from_bridge(&req.amount, &req.destination, None)
+        })
+        .await?;
         Ok(())
     })
 }
 
-fn class_transfer_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn class_transfer_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        if let Ok(req) = <ClassTransferRequest as ExchangeMessage>::from_msgpack(&data) {
-            client.class_transfer(req.amount, req.to_perp, None).await?;
-        } else if let Ok(req) = <VaultTransferRequest as ExchangeMessage>::from_msgpack(&data) {
-            let addr = req.vault_address.as_deref().and_then(|a| a.parse().ok());
-            client
-                .vault_transfer(req.is_deposit, req.usd, addr, None)
-                .await?;
-        } else {
-            log::warn!("Unknown class transfer message");
+        let req = context.decode::<ClassTransferRequest>(&data)?;
+        if skip_in_dry_run(&context, || format!("class_transfer({:?})", req)) {
+            return Ok(());
         }
+        with_retry(&context, || client.class_transfer(req.amount, req.to_perp, None)).await?;
         Ok(())
     })
 }
 
-fn update_isolated_margin_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn vault_transfer_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        let req = <UpdateIsolatedMarginRequest as ExchangeMessage>::from_msgpack(&data)?;
-        client
-            .update_isolated_margin(req.amount, &req.asset, None)
-            .await?;
+        let req = context.decode::<VaultTransferRequest>(&data)?;
+        req.validate()?;
+        let addr = req.vault_address.as_deref().map(parse_address).transpose()?;
+        if skip_in_dry_run(&context, || format!("vault_transfer({:?})", req)) {
+            return Ok(());
+        }
+        with_retry(&context, || {
+            client.vault_transfer(req.is_deposit, req.usd, addr, None)
+        })
+        .await?;
         Ok(())
     })
 }
 
-fn approve_agent_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn spot_transfer_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        let _req = <ApproveAgentRequest as ExchangeMessage>::from_msgpack(&data)?;
-        let (_key, _res) = client.approve_agent(None).await?;
-        info!("Approved agent: {}", _key);
+        let req = context.decode::<SpotTransferRequest>(&data)?;
+        req.validate()?;
+        if skip_in_dry_run(&context, || format!("spot_transfer({:?})", req)) {
+            return Ok(());
+        }
+        with_retry(&context, || {
+            client.spot_transfer(&req.amount, &req.destination, &req.token, None)
+        })
+        .await?;
         Ok(())
     })
 }
 
-fn set_referrer_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+fn update_isolated_margin_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
     Box::pin(async move {
-        let req = <SetReferrerRequest as ExchangeMessage>::from_msgpack(&data)?;
-        client.set_referrer(req.code, None).await?;
+        let req = context.decode::<UpdateIsolatedMarginRequest>(&data)?;
+        if skip_in_dry_run(&context, || format!("update_isolated_margin({:?})", req)) {
+            return Ok(());
+        }
+        with_retry(&context, || {
+            client.update_isolated_margin(req.amount, &req.asset, None)
+        })
+        .await?;
         Ok(())
     })
 }
 
-fn approve_builder_fee_handler<'a>(data: Bytes, client: &'a ExchangeClient) -> HandlerFuture<'a> {
+/// Approve the agent address named in the request, replying with an
+/// `ApproveAgentResponse`
+fn approve_agent_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
     Box::pin(async move {
-        let req = <ApproveBuilderFeeRequest as ExchangeMessage>::from_msgpack(&data)?;
-        client
-            .approve_builder_fee(req.builder, req.max_fee_rate, None)
-            .await?;
+        let req = context.decode::<ApproveAgentRequest>(&data)?;
+        req.validate()?;
+        let agent_address = parse_address(&req.agent_address)?;
+        if skip_in_dry_run(&context, || format!("approve_agent_address({:?})", req)) {
+            return Ok(AckResponse::success().to_reply(&context.header)?);
+        }
+        let status = with_retry(&context, || {
+            client.approve_agent_address(agent_address, req.agent_name.clone(), None)
+        })
+        .await?;
+        if let ExchangeResponseStatus::Err(e) = status {
+            return Err(HandlerError::Exchange(e));
+        }
+        info!("{} Approved agent {:?}", context, agent_address);
+        let response = ApproveAgentResponse {
+            agent_address: format!("{:?}", agent_address),
+            agent_name: req.agent_name,
+        };
+        Ok(response.to_reply(&context.header)?)
+    })
+}
+
+fn set_referrer_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<SetReferrerRequest>(&data)?;
+        if skip_in_dry_run(&context, || format!("set_referrer({:?})", req)) {
+            return Ok(());
+        }
+        with_retry(&context, || client.set_referrer(req.code.clone(), None)).await?;
+        Ok(())
+    })
+}
+
+fn position_query_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<PositionQueryRequest>(&data)?;
+        let user = match req.address {
+            Some(address) => parse_address(&address)?,
+            None => client.vault_address.unwrap_or_else(|| client.wallet.address()),
+        };
+        let info = InfoClient::new(None, Some(info_base_url(client))).await?;
+        let positions = info
+            .user_state(user)
+            .await?
+            .asset_positions
+            .into_iter()
+            .map(|p| PositionSummary {
+                asset: p.position.coin,
+                szi: p.position.szi,
+                entry_px: p.position.entry_px,
+                unrealized_pnl: p.position.unrealized_pnl,
+            })
+            .collect();
+        Ok(PositionQueryResponse::new(positions).to_reply(&context.header)?)
+    })
+}
+
+/// Look up the order ID of a resting order placed through this service by its
+/// cloid, replying with `None` if it is not known
+fn resolve_cloid_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    _client: &'a ExchangeClient,
+) -> QueryFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<ResolveCloidRequest>(&data)?;
+        req.validate()?;
+        let cloid = Uuid::parse_str(&req.cloid)?;
+        let order_id = CLOIDS.get().and_then(|cache| cache.resolve(&cloid));
+        Ok(ResolveCloidResponse::new(order_id).to_reply(&context.header)?)
+    })
+}
+
+fn approve_builder_fee_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<ApproveBuilderFeeRequest>(&data)?;
+        if skip_in_dry_run(&context, || format!("approve_builder_fee({:?})", req)) {
+            return Ok(());
+        }
+        with_retry(&context, || {
+            client.approve_builder_fee(req.builder.clone(), req.max_fee_rate.clone(), None)
+        })
+        .await?;
+        Ok(())
+    })
+}
+
+/// Record that the strategy sending the heartbeat is alive
+fn heartbeat_handler<'a>(
+    data: Bytes,
+    context: MessageContext,
+    _client: &'a ExchangeClient,
+) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let req = context.decode::<HeartbeatRequest>(&data)?;
+        req.validate()?;
+        let Some(heartbeats) = HEARTBEATS.get() else {
+            return Ok(());
+        };
+        if heartbeats.beat(&req.strategy_id, context.header.account.clone()) {
+            info!("{} Watching heartbeats from strategy {}", context, req.strategy_id);
+        }
         Ok(())
     })
 }
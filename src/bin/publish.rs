@@ -0,0 +1,191 @@
+//! Publish one message to the NATS service from the command line, so operators
+//! can send a `CancelAll` or a manual order without writing Rust.
+//!
+//! ```text
+//! publish --type <MessageType> [--subject <subject> | --default-subject]
+//!         [--file <body.json>] [--account <name>] [--request] [field=value ...]
+//! ```
+//!
+//! `--type` is a message type name such as `CancelAll` or `Order`. The body is
+//! read from `--file` as a JSON object, then each `field=value` argument sets a
+//! field; values are parsed as JSON where possible (numbers, booleans, arrays)
+//! and taken as strings otherwise. The message is validated before it is sent.
+//! With `--request` the service's reply is decoded and printed. A request that
+//! nothing is subscribed to fails at once with "no service is listening",
+//! while one the service is too slow to answer fails after the client's
+//! request timeout, so a stopped service can be told from a busy one.
+//!
+//! With `--default-subject` each message goes to the subject it suggests for
+//! itself, such as `hyperliquid.orders.btc` (see `ExchangeMessage::default_subject`).
+//!
+//! If `--file` holds a JSON array of objects, one message is sent per object,
+//! with the field arguments applied to each. Every message is validated first,
+//! then all are published back to back and flushed once, so fanning out many
+//! small messages costs one round trip rather than one each.
+//!
+//! `NATS_URL`, `NATS_SUBJECT`, `SUBJECT_PREFIX`, `NATS_HMAC_SECRET`,
+//! `NATS_CREDS` and `NATS_TOKEN` are read as by `nats_service`; the prefix
+//! also applies to `--subject` and `--default-subject`.
+
+use async_nats::{ConnectOptions, RequestErrorKind};
+use hyperliquid_rust_sdk::messages::{
+    prefixed_subject, AnyMessage, MessageHeader, MessageType, DEFAULT_SUBJECT_ROOT,
+};
+use serde_json::{Map, Value};
+use std::env;
+
+struct Args {
+    msg_type: MessageType,
+    subject: Option<String>,
+    default_subject: bool,
+    file: Option<String>,
+    account: Option<String>,
+    request: bool,
+    fields: Vec<(String, Value)>,
+}
+
+const USAGE: &str = "usage: publish --type <MessageType> \
+                     [--subject <subject> | --default-subject] [--file <body.json>] \
+                     [--account <name>] [--request] [field=value ...]";
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let (mut msg_type, mut subject, mut file, mut account) = (None, None, None, None);
+    let (mut default_subject, mut request) = (false, false);
+    let mut fields = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or(format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--type" => {
+                let name = value("--type")?;
+                let parsed = MessageType::from_name(&name);
+                msg_type = Some(parsed.ok_or(format!("Unknown message type: {}", name))?);
+            }
+            "--subject" => subject = Some(value("--subject")?),
+            "--file" => file = Some(value("--file")?),
+            "--account" => account = Some(value("--account")?),
+            "--default-subject" => default_subject = true,
+            "--request" => request = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            field => {
+                let (name, value) = field
+                    .split_once('=')
+                    .ok_or(format!("Unexpected argument {:?}\n{}", field, USAGE))?;
+                let value = serde_json::from_str(value)
+                    .unwrap_or_else(|_| Value::String(value.to_string()));
+                fields.push((name.to_string(), value));
+            }
+        }
+    }
+    if subject.is_some() && default_subject {
+        return Err(format!("--subject and --default-subject conflict\n{}", USAGE));
+    }
+    Ok(Args {
+        msg_type: msg_type.ok_or(format!("--type is required\n{}", USAGE))?,
+        subject,
+        default_subject,
+        file,
+        account,
+        request,
+        fields,
+    })
+}
+
+/// The message bodies: the `--file` object or each object of the `--file`
+/// array, or one empty object, with the field arguments applied
+fn build_bodies(args: &Args) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let bodies = match &args.file {
+        Some(path) => match serde_json::from_str(&std::fs::read_to_string(path)?)? {
+            Value::Array(bodies) => bodies,
+            body => vec![body],
+        },
+        None => vec![Value::Object(Map::new())],
+    };
+    bodies
+        .into_iter()
+        .map(|body| {
+            let Value::Object(mut body) = body else {
+                return Err("Message bodies must be JSON objects".into());
+            };
+            for (name, value) in &args.fields {
+                body.insert(name.clone(), value.clone());
+            }
+            Ok(Value::Object(body))
+        })
+        .collect()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let secret = env::var("NATS_HMAC_SECRET").ok().map(String::into_bytes);
+    let prefix = env::var("SUBJECT_PREFIX").unwrap_or_default();
+    let subject = args.subject.clone().unwrap_or_else(|| {
+        env::var("NATS_SUBJECT").unwrap_or_else(|_| "hyperliquid.orders".to_string())
+    });
+    let mut payloads = Vec::new();
+    for (i, body) in build_bodies(&args)?.into_iter().enumerate() {
+        let msg = AnyMessage::from_json_body(args.msg_type, body)
+            .and_then(|msg| msg.validate().map(|()| msg))
+            .map_err(|e| format!("Message {}: {}", i, e))?;
+        let mut header = MessageHeader::new(args.msg_type);
+        if let Some(account) = &args.account {
+            header = header.with_account(account);
+        }
+        let subject = if args.default_subject {
+            msg.subject_under(DEFAULT_SUBJECT_ROOT)
+        } else {
+            subject.clone()
+        };
+        let payload = msg.to_msgpack_with_header(header, secret.as_deref())?;
+        payloads.push((prefixed_subject(&prefix, &subject), payload));
+    }
+    if args.request && payloads.len() != 1 {
+        return Err("--request sends a single message".into());
+    }
+
+    let nats_url = env::var("NATS_URL").unwrap_or_else(|_| "nats://localhost:4222".to_string());
+    let mut opts = ConnectOptions::new();
+    if let Ok(creds) = env::var("NATS_CREDS") {
+        opts = opts.credentials_file(creds).await?;
+    }
+    if let Ok(token) = env::var("NATS_TOKEN") {
+        opts = opts.token(token);
+    }
+    let nc = opts.connect(nats_url).await?;
+
+    if args.request {
+        let (subject, payload) = payloads.remove(0);
+        let reply = nc
+            .request(subject.clone(), payload.into())
+            .await
+            .map_err(|e| match e.kind() {
+                RequestErrorKind::NoResponders => {
+                    format!("No service is listening on {}", subject)
+                }
+                RequestErrorKind::TimedOut => format!("No reply on {} before the timeout", subject),
+                RequestErrorKind::Other => format!("Request on {} failed: {}", subject, e),
+            })?;
+        println!("{:#?}", AnyMessage::from_msgpack(&reply.payload)?);
+    } else {
+        // Publishing only queues each message; the flush sends them all at once
+        let count = payloads.len();
+        let mut subjects: Vec<String> = Vec::new();
+        for (subject, payload) in payloads {
+            if !subjects.contains(&subject) {
+                subjects.push(subject.clone());
+            }
+            nc.publish(subject, payload.into()).await?;
+        }
+        nc.flush().await?;
+        let subjects = subjects.join(", ");
+        println!("Published {} {} message(s) to {}", count, args.msg_type, subjects);
+    }
+    Ok(())
+}
@@ -0,0 +1,1237 @@
+//! Routing of framed messages to handlers by `MessageType`
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use log::{debug, warn};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+use crate::{
+    messages::{
+        read_header, reframe, split_frame, AckResponse, AnyMessage, Clock, Codec, Message,
+        MessageError, MessageHeader, MessageType, MsgpackCodec, SystemClock,
+        DEFAULT_MAX_PAYLOAD_SIZE, PRIORITY_NORMAL,
+    },
+    metrics::{record_handler_latency, record_outcome, InFlight},
+    spans::{in_handler_span, in_message_span},
+    ExchangeClient,
+};
+
+/// How long a handler may run before the dispatcher gives up on it
+pub const DEFAULT_HANDLER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many leading bytes of an unreadable payload `UndecodableMessage` keeps
+pub const PAYLOAD_PREVIEW_BYTES: usize = 32;
+
+/// Why a message handler failed.
+///
+/// The variants separate failures worth retrying from those that are not:
+/// a `Validation` or `Decode` failure will fail again, an `Exchange` rejection
+/// usually will, and a `Transport` failure or `Timeout` may have executed the
+/// request anyway.
+#[derive(Debug, Error)]
+pub enum HandlerError {
+    /// The message's contents were rejected before anything was sent to the exchange
+    #[error("Invalid message: {0}")]
+    Validation(String),
+
+    /// The message could not be decoded, or failed its signature or expiry check
+    #[error(transparent)]
+    Decode(MessageError),
+
+    /// The exchange answered and rejected the request
+    #[error("{0}")]
+    Exchange(String),
+
+    /// The exchange could not be reached or failed to answer
+    #[error(transparent)]
+    Transport(crate::Error),
+
+    /// The handler did not finish within its timeout
+    #[error("Handler timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// The account is over its rate limit
+    #[error("Rate limited; retry after {0:?}")]
+    RateLimited(Duration),
+}
+
+impl From<MessageError> for HandlerError {
+    fn from(error: MessageError) -> Self {
+        match error {
+            MessageError::Validation(problem) => HandlerError::Validation(problem),
+            MessageError::ValidationMany(problems) => HandlerError::Validation(problems.join("; ")),
+            MessageError::HandlerTimeout(timeout) => HandlerError::Timeout(timeout),
+            MessageError::RateLimited(wait) => HandlerError::RateLimited(wait),
+            error => HandlerError::Decode(error),
+        }
+    }
+}
+
+impl From<crate::Error> for HandlerError {
+    fn from(error: crate::Error) -> Self {
+        if error.is_transport() {
+            HandlerError::Transport(error)
+        } else {
+            HandlerError::Exchange(error.to_string())
+        }
+    }
+}
+
+impl From<std::num::ParseFloatError> for HandlerError {
+    fn from(error: std::num::ParseFloatError) -> Self {
+        HandlerError::Validation(error.to_string())
+    }
+}
+
+impl From<uuid::Error> for HandlerError {
+    fn from(error: uuid::Error) -> Self {
+        HandlerError::Validation(error.to_string())
+    }
+}
+
+/// Error returned by the `dispatch` methods: a `DispatchError` once the
+/// message's header could be read, an `UndecodableMessage` before that
+pub type DispatchFailure = Box<dyn std::error::Error + Send + Sync>;
+
+/// Future returned by message handlers
+pub type HandlerFuture<'a> = BoxFuture<'a, Result<(), HandlerError>>;
+
+/// Handler invoked with the full framed message, its parsed header and the exchange client
+pub type HandlerFn = for<'a> fn(Bytes, MessageContext, &'a ExchangeClient) -> HandlerFuture<'a>;
+
+/// Future returned by query handlers, resolving to the framed reply message
+pub type QueryFuture<'a> = BoxFuture<'a, Result<Vec<u8>, HandlerError>>;
+
+/// Handler for a query, answered with a reply message instead of an `AckResponse`
+pub type QueryFn = for<'a> fn(Bytes, MessageContext, &'a ExchangeClient) -> QueryFuture<'a>;
+
+/// A registered handler
+#[derive(Debug, Clone, Copy)]
+enum Handler {
+    Command(HandlerFn),
+    Query(QueryFn),
+}
+
+/// The parsed header of a dispatched message, passed to its handler.
+///
+/// Displays as `[msg_id=<hex> correlation_id=<hex>]`, with `-` for a missing
+/// correlation id, for tying log lines and errors back to the publisher's request.
+/// Replies should be built with `header.reply` so they echo the correlation id.
+#[derive(Debug, Clone)]
+pub struct MessageContext {
+    pub header: MessageHeader,
+    /// Whether `decode` rejects expired messages
+    reject_expired: bool,
+    /// The dispatcher's clock; `None` uses `SystemClock`
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl PartialEq for MessageContext {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.reject_expired == other.reject_expired
+    }
+}
+
+impl From<&MessageHeader> for MessageContext {
+    fn from(header: &MessageHeader) -> Self {
+        Self {
+            header: header.clone(),
+            reject_expired: true,
+            clock: None,
+        }
+    }
+}
+
+impl MessageContext {
+    /// Decode the dispatched message `data` as `M`, accepting it even if expired
+    /// when the dispatcher was built with `with_reject_expired(false)`.
+    ///
+    /// Expiry is judged by the dispatcher's clock.
+    pub fn decode<M: Message>(&self, data: &[u8]) -> Result<M, MessageError> {
+        if self.reject_expired && self.header.is_expired_with(self.clock()) {
+            return Err(MessageError::Expired);
+        }
+        M::from_msgpack_ignoring_expiry(data)
+    }
+
+    /// The dispatcher's clock, for handlers that judge time-based fields
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_deref().unwrap_or(&SystemClock)
+    }
+}
+
+impl fmt::Display for MessageContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let correlation_id = self
+            .header
+            .correlation_id
+            .map_or_else(|| "-".to_string(), hex::encode);
+        write!(
+            f,
+            "[msg_id={} correlation_id={}]",
+            hex::encode(self.header.msg_id),
+            correlation_id
+        )
+    }
+}
+
+/// A dispatch failure for a message whose header could be read
+#[derive(Debug, Error)]
+#[error("{context} {error}")]
+pub struct DispatchError {
+    pub context: MessageContext,
+    pub error: HandlerError,
+}
+
+/// A dispatch failure for a message whose header could not be read.
+///
+/// Keeps the start of the payload so a misconfigured publisher (say, one sending
+/// JSON or protobuf to a msgpack subject) can be recognised from the logs.
+#[derive(Debug, Error)]
+#[error("{error} ({len} byte payload starting {preview})")]
+pub struct UndecodableMessage {
+    pub error: MessageError,
+    /// Length of the whole payload
+    pub len: usize,
+    /// The first `PAYLOAD_PREVIEW_BYTES` bytes of the payload as hex
+    pub preview: String,
+}
+
+impl UndecodableMessage {
+    fn new(error: MessageError, data: &[u8]) -> Self {
+        Self {
+            error,
+            len: data.len(),
+            preview: hex::encode(&data[..data.len().min(PAYLOAD_PREVIEW_BYTES)]),
+        }
+    }
+}
+
+/// What `MessageDispatcher::with_ordering` groups messages by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKey {
+    /// The asset the message trades or cancels, from `AnyMessage::asset`
+    Asset,
+    /// The header's `account`
+    Account,
+}
+
+/// A message's place in line within its partition, from `MessageDispatcher::claim_turn`.
+///
+/// Keep the turn until the message has been handled; dropping it lets the
+/// next message in the partition run.
+#[derive(Debug)]
+pub struct Turn {
+    previous: Option<oneshot::Receiver<()>>,
+    _done: oneshot::Sender<()>,
+}
+
+impl Turn {
+    /// Wait until every message claimed earlier in the partition has been handled.
+    ///
+    /// Cancel-safe: if the wait is abandoned, calling `ready` again resumes it.
+    pub async fn ready(&mut self) {
+        if let Some(previous) = &mut self.previous {
+            // The sender is only ever dropped, so an error means the earlier turn ended
+            let _ = previous.await;
+            self.previous = None;
+        }
+    }
+}
+
+/// Messages waiting for a free handler slot, handed out highest priority
+/// first and in arrival order within a priority.
+///
+/// Push messages with the priority from `MessageDispatcher::priority_of`.
+#[derive(Debug)]
+pub struct PriorityQueue<T> {
+    heap: BinaryHeap<Queued<T>>,
+    pushed: u64,
+}
+
+#[derive(Debug)]
+struct Queued<T> {
+    /// Ordered by priority, then earliest pushed
+    rank: (u8, Reverse<u64>),
+    item: T,
+}
+
+impl<T> PartialEq for Queued<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.rank == other.rank
+    }
+}
+
+impl<T> Eq for Queued<T> {}
+
+impl<T> PartialOrd for Queued<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Queued<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank.cmp(&other.rank)
+    }
+}
+
+impl<T> PriorityQueue<T> {
+    /// Create an empty queue
+    pub fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            pushed: 0,
+        }
+    }
+
+    /// Queue `item` with `priority`
+    pub fn push(&mut self, priority: u8, item: T) {
+        self.heap.push(Queued {
+            rank: (priority, Reverse(self.pushed)),
+            item,
+        });
+        self.pushed += 1;
+    }
+
+    /// Take the highest-priority item, the earliest pushed among equals
+    pub fn pop(&mut self) -> Option<T> {
+        self.heap.pop().map(|queued| queued.item)
+    }
+
+    /// Number of items waiting
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Whether nothing is waiting
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The last turn claimed in each partition
+#[derive(Debug)]
+struct Partitions {
+    key: PartitionKey,
+    /// `None` is the default partition, for messages without a key
+    last: Mutex<HashMap<Option<String>, oneshot::Receiver<()>>>,
+}
+
+/// Dispatches framed messages to the handler registered for their header's `msg_type`
+#[derive(Debug, Default)]
+pub struct MessageDispatcher {
+    handlers: HashMap<MessageType, Handler>,
+    secret: Option<Vec<u8>>,
+    /// `None` uses `MsgpackCodec`
+    codec: Option<&'static dyn Codec>,
+    /// `None` uses `DEFAULT_MAX_PAYLOAD_SIZE`
+    max_payload_size: Option<usize>,
+    /// `None` uses `DEFAULT_HANDLER_TIMEOUT`
+    handler_timeout: Option<Duration>,
+    handler_timeouts: HashMap<MessageType, Duration>,
+    dedup: Option<Mutex<SeenIds>>,
+    duplicates: AtomicU64,
+    rate_limit: Option<RateLimiter>,
+    partitions: Option<Partitions>,
+    accept_expired: bool,
+    /// Lifetime of the replies built by `dispatch_with_ack`; `None` keeps the
+    /// one they were built with
+    reply_expiration: Option<Duration>,
+    /// `None` uses `SystemClock`
+    clock: Option<Arc<dyn Clock>>,
+}
+
+/// Token buckets limiting the message rate of each account
+#[derive(Debug)]
+struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    max_wait: Duration,
+    /// Keyed by the header's `account`; `None` is the default account
+    buckets: Mutex<HashMap<Option<String>, TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// Negative while callers are waiting for tokens already handed out
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Take a token for `account`, returning how long to wait before using it,
+    /// or `RateLimited` if that would exceed `max_wait`
+    fn acquire(&self, account: Option<&str>) -> Result<Duration, MessageError> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry(account.map(str::to_string))
+            .or_insert(TokenBucket {
+                tokens: self.burst,
+                updated: now,
+            });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+
+        let wait = Duration::from_secs_f64((1.0 - bucket.tokens).max(0.0) / self.per_second);
+        if wait > self.max_wait {
+            return Err(MessageError::RateLimited(wait));
+        }
+        bucket.tokens -= 1.0;
+        Ok(wait)
+    }
+}
+
+/// Bounded record of recently seen `msg_id`s, oldest first
+#[derive(Debug)]
+struct SeenIds {
+    window: Duration,
+    capacity: usize,
+    order: VecDeque<([u8; 16], Instant)>,
+    ids: HashSet<[u8; 16]>,
+}
+
+impl SeenIds {
+    /// Record `msg_id`, returning `false` if it was already seen within the window
+    fn insert(&mut self, msg_id: [u8; 16]) -> bool {
+        let now = Instant::now();
+        while let Some(&(oldest, seen_at)) = self.order.front() {
+            if now.duration_since(seen_at) < self.window && self.order.len() < self.capacity {
+                break;
+            }
+            self.order.pop_front();
+            self.ids.remove(&oldest);
+        }
+
+        if !self.ids.insert(msg_id) {
+            return false;
+        }
+        self.order.push_back((msg_id, now));
+        true
+    }
+
+    /// Remove `msg_id` so that a later delivery is not treated as a duplicate
+    fn forget(&mut self, msg_id: &[u8; 16]) {
+        if self.ids.remove(msg_id) {
+            self.order.retain(|(id, _)| id != msg_id);
+        }
+    }
+}
+
+impl MessageDispatcher {
+    /// Create a dispatcher with no handlers registered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require messages to carry a valid HMAC signature for `secret`
+    pub fn with_secret(mut self, secret: &[u8]) -> Self {
+        self.secret = Some(secret.to_vec());
+        self
+    }
+
+    /// Read message headers with `codec` instead of `MsgpackCodec`.
+    ///
+    /// Handlers decode bodies with `Message::from_msgpack`, so the message
+    /// types they handle must use the same codec.
+    pub fn with_codec(mut self, codec: &'static dyn Codec) -> Self {
+        self.codec = Some(codec);
+        self
+    }
+
+    /// Reject messages larger than `bytes` before their header is parsed
+    /// (default: `DEFAULT_MAX_PAYLOAD_SIZE`).
+    ///
+    /// Handlers decode bodies with `Message::from_msgpack`, which applies each
+    /// type's own `Message::max_payload_size`.
+    pub fn with_max_payload_size(mut self, bytes: usize) -> Self {
+        self.max_payload_size = Some(bytes);
+        self
+    }
+
+    /// Whether to reject messages whose `expires_at` has passed (default: `true`).
+    ///
+    /// Turn this off to run expired messages anyway, e.g. when replaying
+    /// recorded traffic. Handlers must then decode with `MessageContext::decode`.
+    pub fn with_reject_expired(mut self, reject: bool) -> Self {
+        self.accept_expired = !reject;
+        self
+    }
+
+    /// Give the replies built by `dispatch_with_ack`, including query handlers'
+    /// responses, a lifetime of `lifetime` instead of `DEFAULT_EXPIRATION_SECS`;
+    /// `Duration::ZERO` never expires
+    pub fn with_reply_expiration(mut self, lifetime: Duration) -> Self {
+        self.reply_expiration = Some(lifetime);
+        self
+    }
+
+    /// Judge expiry by `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Give up on handlers that run longer than `timeout`
+    /// (default: `DEFAULT_HANDLER_TIMEOUT`).
+    ///
+    /// A timed-out handler is dropped and the message fails with
+    /// `MessageError::HandlerTimeout`. The exchange may still have executed the
+    /// request, so with deduplication enabled the message stays marked as seen.
+    pub fn with_handler_timeout(mut self, timeout: Duration) -> Self {
+        self.handler_timeout = Some(timeout);
+        self
+    }
+
+    /// Override the handler timeout for one message type
+    pub fn with_handler_timeout_for(mut self, msg_type: MessageType, timeout: Duration) -> Self {
+        self.handler_timeouts.insert(msg_type, timeout);
+        self
+    }
+
+    /// Drop messages whose `msg_id` was already seen within `window`.
+    ///
+    /// At most `capacity` ids are remembered; the oldest are forgotten first.
+    pub fn with_dedup(mut self, window: Duration, capacity: usize) -> Self {
+        self.dedup = Some(Mutex::new(SeenIds {
+            window,
+            capacity: capacity.max(1),
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+        }));
+        self
+    }
+
+    /// Limit each account to `per_second` messages, allowing bursts of `burst`.
+    ///
+    /// Accounts are named by the header's `account`, so a single-account service
+    /// shares one limit. Messages over the limit are held until a token is free,
+    /// for at most `max_wait`; beyond that they fail with `MessageError::RateLimited`.
+    ///
+    /// # Panics
+    ///
+    /// If `per_second` is not a positive, finite number.
+    pub fn with_rate_limit(mut self, per_second: f64, burst: u32, max_wait: Duration) -> Self {
+        assert!(
+            per_second > 0.0 && per_second.is_finite(),
+            "rate limit must be a positive number of messages per second, got {}",
+            per_second
+        );
+        self.rate_limit = Some(RateLimiter {
+            per_second,
+            burst: f64::from(burst.max(1)),
+            max_wait,
+            buckets: Mutex::new(HashMap::new()),
+        });
+        self
+    }
+
+    /// Handle messages with the same `key` one at a time, in the order their
+    /// turns are claimed with `claim_turn`.
+    ///
+    /// Messages in different partitions still run concurrently. Messages
+    /// without a key, or whose key cannot be read, share a default partition.
+    pub fn with_ordering(mut self, key: PartitionKey) -> Self {
+        self.partitions = Some(Partitions {
+            key,
+            last: Mutex::new(HashMap::new()),
+        });
+        self
+    }
+
+    /// Claim `data`'s turn in its partition, or `None` if ordering is not enabled.
+    ///
+    /// Claim turns in the order messages arrive, before handing them to
+    /// concurrent tasks, and call `Turn::ready` before dispatching.
+    pub fn claim_turn(&self, data: &[u8]) -> Option<Turn> {
+        let partitions = self.partitions.as_ref()?;
+        let key = match partitions.key {
+            PartitionKey::Asset => AnyMessage::from_msgpack(data)
+                .ok()
+                .and_then(|msg| msg.asset().map(str::to_string)),
+            PartitionKey::Account => read_header(self.codec(), data, self.max_payload_size())
+                .ok()
+                .and_then(|(header, _)| header.account),
+        };
+        let (done, next) = oneshot::channel();
+        let previous = partitions.last.lock().unwrap().insert(key, next);
+        Some(Turn {
+            previous,
+            _done: done,
+        })
+    }
+
+    /// The header priority of `data`, or `PRIORITY_NORMAL` if its header cannot be read
+    pub fn priority_of(&self, data: &[u8]) -> u8 {
+        read_header(self.codec(), data, self.max_payload_size())
+            .map_or(PRIORITY_NORMAL, |(header, _)| header.priority)
+    }
+
+    /// Number of duplicate messages dropped so far
+    pub fn duplicates_dropped(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// Register the handler for a message type, replacing any previous one
+    pub fn register(&mut self, msg_type: MessageType, handler: HandlerFn) {
+        self.handlers.insert(msg_type, Handler::Command(handler));
+    }
+
+    /// Register a query handler for a message type, replacing any previous handler.
+    ///
+    /// `dispatch_with_ack` replies with the message the handler returns; other
+    /// dispatch methods discard it.
+    pub fn register_query(&mut self, msg_type: MessageType, handler: QueryFn) {
+        self.handlers.insert(msg_type, Handler::Query(handler));
+    }
+
+    /// Parse the header of `data`, check its signature and expiry, and run the matching handler.
+    ///
+    /// Messages with no registered handler are logged and ignored. Once the header
+    /// has been read, failures are returned as a `DispatchError` carrying its ids;
+    /// before that, as an `UndecodableMessage` carrying the start of the payload.
+    pub async fn dispatch(
+        &self,
+        data: Bytes,
+        client: &ExchangeClient,
+    ) -> Result<(), DispatchFailure> {
+        self.dispatch_inner(data, client, None).await.map(drop)
+    }
+
+    /// Like `dispatch`, but reject messages whose type is not in `allowed`
+    pub async fn dispatch_allowed(
+        &self,
+        data: Bytes,
+        client: &ExchangeClient,
+        allowed: &[MessageType],
+    ) -> Result<(), DispatchFailure> {
+        self.dispatch_inner(data, client, Some(allowed)).await.map(drop)
+    }
+
+    /// Dispatch `data`, restricted to `allowed` types if given, and build the
+    /// reply: the query handler's response, or an `AckResponse` reporting the outcome.
+    ///
+    /// The reply is correlated with the request as described in
+    /// `AckResponse::to_reply`. No reply is built if the header cannot be read.
+    pub async fn dispatch_with_ack(
+        &self,
+        data: Bytes,
+        client: &ExchangeClient,
+        allowed: Option<&[MessageType]>,
+    ) -> (Result<(), DispatchFailure>, Option<Vec<u8>>) {
+        let header = match read_header(self.codec(), &data, self.max_payload_size()) {
+            Ok((header, _)) => header,
+            Err(e) => return (Err(UndecodableMessage::new(e, &data).into()), None),
+        };
+        let result = match self.dispatch_inner(data, client, allowed).await {
+            Ok(Some(reply)) => return (Ok(()), Some(self.set_reply_expiration(reply))),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        let ack = match &result {
+            Ok(()) => AckResponse::success(),
+            Err(e) => AckResponse::failure(e),
+        };
+        match ack.to_reply(&header) {
+            Ok(reply) => (result, Some(self.set_reply_expiration(reply))),
+            Err(e) => {
+                warn!("{} Failed to encode reply: {}", MessageContext::from(&header), e);
+                (result, None)
+            }
+        }
+    }
+
+    async fn dispatch_inner(
+        &self,
+        data: Bytes,
+        client: &ExchangeClient,
+        allowed: Option<&[MessageType]>,
+    ) -> Result<Option<Vec<u8>>, DispatchFailure> {
+        let _in_flight = InFlight::start();
+        let (header, body) = read_header(self.codec(), &data, self.max_payload_size())
+            .map_err(|e| {
+                record_outcome(None, "invalid");
+                UndecodableMessage::new(e, &data)
+            })?;
+        let context = MessageContext {
+            header: header.clone(),
+            reject_expired: !self.accept_expired,
+            clock: self.clock.clone(),
+        };
+        if let Err(e) = self.check(&header, body, allowed) {
+            record_outcome(Some(header.msg_type), "rejected");
+            return Err(DispatchError {
+                context,
+                error: e.into(),
+            }
+            .into());
+        }
+        in_message_span(&context, self.run(&header, &data, context.clone(), client))
+            .await
+            .map_err(|error| DispatchError { context, error }.into())
+    }
+
+    fn handler_timeout(&self, msg_type: MessageType) -> Duration {
+        self.handler_timeouts
+            .get(&msg_type)
+            .copied()
+            .or(self.handler_timeout)
+            .unwrap_or(DEFAULT_HANDLER_TIMEOUT)
+    }
+
+    fn codec(&self) -> &'static dyn Codec {
+        self.codec.unwrap_or(&MsgpackCodec)
+    }
+
+    fn max_payload_size(&self) -> usize {
+        self.max_payload_size.unwrap_or(DEFAULT_MAX_PAYLOAD_SIZE)
+    }
+
+    /// Reframe `reply` with the configured reply lifetime, if any. Replies are
+    /// not signed, so changing the header leaves them valid.
+    fn set_reply_expiration(&self, reply: Vec<u8>) -> Vec<u8> {
+        let Some(lifetime) = self.reply_expiration else {
+            return reply;
+        };
+        let Ok((header, body)) = split_frame(&reply) else {
+            return reply;
+        };
+        let header = match lifetime.as_millis() {
+            0 => header.with_deadline(0),
+            ms => header.with_expiration_ms(ms as u64),
+        };
+        reframe(&header, body)
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_deref().unwrap_or(&SystemClock)
+    }
+
+    /// Check the message is allowed here, correctly signed and not expired
+    fn check(
+        &self,
+        header: &MessageHeader,
+        body: &[u8],
+        allowed: Option<&[MessageType]>,
+    ) -> Result<(), MessageError> {
+        if header.msg_type == MessageType::Unknown {
+            return Err(MessageError::UnknownMessageType(header.type_name()));
+        }
+        if allowed.is_some_and(|allowed| !allowed.contains(&header.msg_type)) {
+            return Err(MessageError::Validation(format!(
+                "{} is not permitted here",
+                header.msg_type
+            )));
+        }
+        if let Some(secret) = &self.secret {
+            header.verify(secret, body)?;
+        }
+        if !self.accept_expired && header.is_expired_with(self.clock()) {
+            return Err(MessageError::Expired);
+        }
+        Ok(())
+    }
+
+    /// Run the handler for a checked message unless it is a duplicate or over
+    /// the rate limit, returning a query handler's reply
+    async fn run(
+        &self,
+        header: &MessageHeader,
+        data: &Bytes,
+        context: MessageContext,
+        client: &ExchangeClient,
+    ) -> Result<Option<Vec<u8>>, HandlerError> {
+        if let Some(dedup) = &self.dedup {
+            let first_seen = dedup.lock().unwrap().insert(header.msg_id);
+            if !first_seen {
+                self.duplicates.fetch_add(1, Ordering::Relaxed);
+                record_outcome(Some(header.msg_type), "duplicate");
+                debug!("{} Dropping duplicate {} message", context, header.msg_type);
+                return Ok(None);
+            }
+        }
+
+        if let Some(limiter) = &self.rate_limit {
+            match limiter.acquire(header.account.as_deref()) {
+                Ok(wait) if !wait.is_zero() => {
+                    debug!("{} Rate limited, waiting {:?}", context, wait);
+                    tokio::time::sleep(wait).await;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    record_outcome(Some(header.msg_type), "rate_limited");
+                    warn!("{} Dropping {} message: {}", context, header.msg_type, e);
+                    // The message was not executed, so a retry must not count as a duplicate
+                    if let Some(dedup) = &self.dedup {
+                        dedup.lock().unwrap().forget(&header.msg_id);
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+
+        match self.handlers.get(&header.msg_type) {
+            Some(handler) => {
+                debug!("{} Handling {} message", context, header.msg_type);
+                let started = Instant::now();
+                let handled = in_handler_span(header.msg_type, async {
+                    match handler {
+                        Handler::Command(handler) => {
+                            handler(data.clone(), context.clone(), client).await.map(|()| None)
+                        }
+                        Handler::Query(handler) => {
+                            handler(data.clone(), context.clone(), client).await.map(Some)
+                        }
+                    }
+                });
+                let timeout = self.handler_timeout(header.msg_type);
+                let Ok(result) = tokio::time::timeout(timeout, handled).await else {
+                    record_handler_latency(header.msg_type, started.elapsed());
+                    record_outcome(Some(header.msg_type), "timeout");
+                    warn!("{} {} handler timed out after {:?}", context, header.msg_type, timeout);
+                    return Err(HandlerError::Timeout(timeout));
+                };
+                record_handler_latency(header.msg_type, started.elapsed());
+                record_outcome(
+                    Some(header.msg_type),
+                    if result.is_ok() { "ok" } else { "error" },
+                );
+                // A failed message may be retried, so it must not count as seen
+                if let (Err(_), Some(dedup)) = (&result, &self.dedup) {
+                    dedup.lock().unwrap().forget(&header.msg_id);
+                }
+                result
+            }
+            None => {
+                record_outcome(Some(header.msg_type), "unhandled");
+                warn!("{} No handler registered for {:?}", context, header.msg_type);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        messages::{
+            peek_header, CancelAllRequest, CancelOrderRequest, ManualClock, Message, OrderRequest,
+            PositionQueryRequest, PositionQueryResponse, DEFAULT_EXPIRATION_SECS, PRIORITY_HIGH,
+        },
+        req::HttpClient,
+        Meta, SpotMeta, MAINNET_API_URL,
+    };
+
+    static ORDERS_HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_handler<'a>(
+        data: Bytes,
+        _context: MessageContext,
+        _client: &'a ExchangeClient,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            OrderRequest::from_msgpack(&data)?;
+            ORDERS_HANDLED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+
+    static CANCELS_HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+    fn hanging_handler<'a>(
+        _data: Bytes,
+        _context: MessageContext,
+        _client: &'a ExchangeClient,
+    ) -> HandlerFuture<'a> {
+        Box::pin(std::future::pending())
+    }
+
+    fn counting_cancel_handler<'a>(
+        _data: Bytes,
+        _context: MessageContext,
+        _client: &'a ExchangeClient,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            CANCELS_HANDLED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+
+    fn test_client() -> ExchangeClient {
+        ExchangeClient {
+            http_client: HttpClient {
+                client: Default::default(),
+                base_url: MAINNET_API_URL.to_string(),
+            },
+            wallet: "e908f86dbb4d55ac876378565aafeabc187f6690f046459397b17d9b9a19688e"
+                .parse()
+                .unwrap(),
+            meta: Meta { universe: vec![] },
+            spot_meta: SpotMeta {
+                universe: vec![],
+                tokens: vec![],
+            },
+            vault_address: None,
+            coin_to_asset: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let client = test_client();
+        let mut dispatcher = MessageDispatcher::new().with_secret(b"secret");
+        dispatcher.register(MessageType::Order, counting_handler);
+
+        let order = OrderRequest::market("BTC", true, "1.0");
+        let signed = Bytes::from(order.to_msgpack_signed(b"secret").unwrap());
+        dispatcher.dispatch(signed, &client).await.unwrap();
+        assert_eq!(ORDERS_HANDLED.load(Ordering::SeqCst), 1);
+
+        // Unsigned and truncated messages never reach the handler
+        let unsigned = Bytes::from(order.to_msgpack().unwrap());
+        assert!(dispatcher.dispatch(unsigned, &client).await.is_err());
+        assert!(dispatcher.dispatch(Bytes::from_static(&[0, 0]), &client).await.is_err());
+        assert_eq!(ORDERS_HANDLED.load(Ordering::SeqCst), 1);
+
+        // Types without a handler are ignored
+        let cancel = CancelOrderRequest::by_order_id("BTC", 1);
+        let cancel = Bytes::from(cancel.to_msgpack_signed(b"secret").unwrap());
+        assert!(dispatcher.dispatch(cancel.clone(), &client).await.is_ok());
+
+        // Types outside the allowed set are rejected before reaching a handler
+        let allowed = [MessageType::Order];
+        assert!(dispatcher.dispatch_allowed(cancel, &client, &allowed).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let client = test_client();
+        let mut dispatcher = MessageDispatcher::new().with_dedup(Duration::from_secs(60), 2);
+        dispatcher.register(MessageType::CancelOrder, counting_cancel_handler);
+
+        let first = Bytes::from(CancelOrderRequest::by_order_id("BTC", 1).to_msgpack().unwrap());
+        let second = Bytes::from(CancelOrderRequest::by_order_id("BTC", 2).to_msgpack().unwrap());
+        let third = Bytes::from(CancelOrderRequest::by_order_id("BTC", 3).to_msgpack().unwrap());
+
+        dispatcher.dispatch(first.clone(), &client).await.unwrap();
+        dispatcher.dispatch(first.clone(), &client).await.unwrap();
+        dispatcher.dispatch(second, &client).await.unwrap();
+        assert_eq!(CANCELS_HANDLED.load(Ordering::SeqCst), 2);
+        assert_eq!(dispatcher.duplicates_dropped(), 1);
+
+        // Once capacity is exceeded the oldest id is forgotten
+        dispatcher.dispatch(third, &client).await.unwrap();
+        dispatcher.dispatch(first, &client).await.unwrap();
+        assert_eq!(CANCELS_HANDLED.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_error_context() {
+        let client = test_client();
+        let dispatcher = MessageDispatcher::new().with_secret(b"secret");
+
+        let header = MessageHeader::new(MessageType::Order).with_correlation_id([7; 16]);
+        let context = MessageContext::from(&header);
+        assert_eq!(
+            context.to_string(),
+            format!("[msg_id={} correlation_id={}]", hex::encode(header.msg_id), "07".repeat(16))
+        );
+
+        // Failures after the header is read carry the message's ids
+        let unsigned = Bytes::from(OrderRequest::market("BTC", true, "1.0").to_msgpack().unwrap());
+        let err = dispatcher.dispatch(unsigned, &client).await.unwrap_err();
+        let err = err.downcast::<DispatchError>().unwrap();
+        assert_eq!(err.context.header.msg_type, MessageType::Order);
+        assert!(err.to_string().starts_with("[msg_id="));
+
+        // A type from a newer producer is rejected by id, naming the type
+        let order = OrderRequest::market("BTC", true, "1.0").to_msgpack_signed(b"secret").unwrap();
+        let at = order.windows(6).position(|w| w == b"\xa5Order").unwrap();
+        let mut unknown = order.clone();
+        unknown[at + 1..at + 6].copy_from_slice(b"Quote");
+        let err = dispatcher.dispatch(Bytes::from(unknown), &client).await.unwrap_err();
+        let err = err.downcast::<DispatchError>().unwrap();
+        assert_eq!(err.context.header.msg_id, peek_header(&order).unwrap().msg_id);
+        assert!(err.to_string().ends_with("Unknown message type: Quote"));
+
+        // Unreadable payloads carry their first bytes instead
+        let garbage = Bytes::from(vec![0xab; 100]);
+        let err = dispatcher.dispatch(garbage, &client).await.unwrap_err();
+        let err = err.downcast::<UndecodableMessage>().unwrap();
+        assert_eq!(err.len, 100);
+        assert_eq!(err.preview, "ab".repeat(PAYLOAD_PREVIEW_BYTES));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_with_ack() {
+        let client = test_client();
+        let dispatcher = MessageDispatcher::new().with_secret(b"secret");
+
+        let request = MessageHeader::new(MessageType::CancelOrder).with_correlation_id([5; 16]);
+        let cancel = CancelOrderRequest::by_order_id("BTC", 1);
+        let unsigned = Bytes::from(cancel.to_msgpack_with_header(request).unwrap());
+        let (result, reply) = dispatcher.dispatch_with_ack(unsigned, &client, None).await;
+        assert!(result.is_err());
+        let reply = reply.unwrap();
+        let ack = AckResponse::from_msgpack(&reply).unwrap();
+        assert!(!ack.ok);
+        assert!(ack.message.unwrap().contains("signature"));
+        let header = peek_header(&reply).unwrap();
+        assert_eq!(header.correlation_id, Some([5; 16]));
+        assert_eq!(header.expires_at - header.timestamp, DEFAULT_EXPIRATION_SECS * 1000);
+
+        // Reply lifetimes are set on the dispatcher
+        let dispatcher = dispatcher.with_reply_expiration(Duration::from_secs(5));
+        let unsigned = Bytes::from(cancel.to_msgpack().unwrap());
+        let (_, reply) = dispatcher.dispatch_with_ack(unsigned, &client, None).await;
+        let reply = reply.unwrap();
+        assert!(!AckResponse::from_msgpack(&reply).unwrap().ok);
+        let header = peek_header(&reply).unwrap();
+        assert_eq!(header.expires_at - header.timestamp, 5000);
+
+        // Unreadable messages get no reply
+        let (result, reply) = dispatcher
+            .dispatch_with_ack(Bytes::from_static(&[0, 0]), &client, None)
+            .await;
+        assert!(result.is_err());
+        assert!(reply.is_none());
+    }
+
+    fn empty_positions_handler<'a>(
+        _data: Bytes,
+        context: MessageContext,
+        _client: &'a ExchangeClient,
+    ) -> QueryFuture<'a> {
+        Box::pin(async move {
+            let reply = PositionQueryResponse::new(Vec::new()).to_reply(&context.header)?;
+            Ok(reply)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_query() {
+        let client = test_client();
+        let mut dispatcher = MessageDispatcher::new();
+        dispatcher.register_query(MessageType::PositionQuery, empty_positions_handler);
+
+        let request = MessageHeader::new(MessageType::PositionQuery).with_correlation_id([9; 16]);
+        let query = PositionQueryRequest::own().to_msgpack_with_header(request).unwrap();
+        let (result, reply) = dispatcher
+            .dispatch_with_ack(Bytes::from(query.clone()), &client, None)
+            .await;
+        assert!(result.is_ok());
+        let reply = reply.unwrap();
+        let response = PositionQueryResponse::from_msgpack(&reply).unwrap();
+        assert!(response.positions.is_empty());
+        let header = peek_header(&reply).unwrap();
+        assert_eq!(header.correlation_id, Some([9; 16]));
+
+        // Without a reply subject the response is discarded
+        assert!(dispatcher.dispatch(Bytes::from(query), &client).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handler_timeout() {
+        let client = test_client();
+        let mut dispatcher = MessageDispatcher::new()
+            .with_dedup(Duration::from_secs(60), 16)
+            .with_handler_timeout(Duration::from_secs(60))
+            .with_handler_timeout_for(MessageType::CancelOrder, Duration::from_millis(10));
+        dispatcher.register(MessageType::CancelOrder, hanging_handler);
+
+        let cancel = Bytes::from(CancelOrderRequest::by_order_id("BTC", 1).to_msgpack().unwrap());
+        let (result, reply) = dispatcher.dispatch_with_ack(cancel.clone(), &client, None).await;
+        let error = result.unwrap_err();
+        let error = &error.downcast_ref::<DispatchError>().unwrap().error;
+        assert!(matches!(error, HandlerError::Timeout(_)));
+        let ack = AckResponse::from_msgpack(&reply.unwrap()).unwrap();
+        assert!(ack.message.unwrap().contains("timed out"));
+
+        // The request may have reached the exchange, so a redelivery is not retried
+        assert!(dispatcher.dispatch(cancel, &client).await.is_ok());
+        assert_eq!(dispatcher.duplicates_dropped(), 1);
+    }
+
+    fn noop_handler<'a>(
+        _data: Bytes,
+        _context: MessageContext,
+        _client: &'a ExchangeClient,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let client = test_client();
+        let mut dispatcher = MessageDispatcher::new()
+            .with_dedup(Duration::from_secs(60), 16)
+            .with_rate_limit(1.0, 2, Duration::ZERO);
+        dispatcher.register(MessageType::CancelOrder, noop_handler);
+
+        let cancel = |account: Option<&str>| {
+            let mut header = MessageHeader::new(MessageType::CancelOrder);
+            if let Some(account) = account {
+                header = header.with_account(account);
+            }
+            let cancel = CancelOrderRequest::by_order_id("BTC", 1);
+            Bytes::from(cancel.to_msgpack_with_header(header).unwrap())
+        };
+
+        // The burst is allowed, then messages are rejected rather than delayed
+        assert!(dispatcher.dispatch(cancel(None), &client).await.is_ok());
+        assert!(dispatcher.dispatch(cancel(None), &client).await.is_ok());
+        let limited = cancel(None);
+        let error = dispatcher.dispatch(limited.clone(), &client).await.unwrap_err();
+        let error = &error.downcast_ref::<DispatchError>().unwrap().error;
+        assert!(matches!(error, HandlerError::RateLimited(_)));
+
+        // Each account has its own bucket
+        assert!(dispatcher.dispatch(cancel(Some("sub1")), &client).await.is_ok());
+
+        // Within `max_wait` the message is delayed until a token is free
+        let dispatcher = MessageDispatcher::new()
+            .with_dedup(Duration::from_secs(60), 16)
+            .with_rate_limit(100.0, 1, Duration::from_secs(1));
+        assert!(dispatcher.dispatch(cancel(None), &client).await.is_ok());
+        let started = Instant::now();
+        assert!(dispatcher.dispatch(limited, &client).await.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(5));
+        assert_eq!(dispatcher.duplicates_dropped(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "rate limit must be a positive number")]
+    fn test_zero_rate_limit() {
+        let _ = MessageDispatcher::new().with_rate_limit(0.0, 1, Duration::ZERO);
+    }
+
+    fn decoding_cancel_handler<'a>(
+        data: Bytes,
+        context: MessageContext,
+        _client: &'a ExchangeClient,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            context.decode::<CancelOrderRequest>(&data)?;
+            Ok(())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_reject_expired() {
+        let client = test_client();
+        let header = MessageHeader::new(MessageType::CancelOrder).with_deadline(1);
+        let cancel = CancelOrderRequest::by_order_id("BTC", 1);
+        let expired = Bytes::from(cancel.to_msgpack_with_header(header).unwrap());
+
+        let mut dispatcher = MessageDispatcher::new();
+        dispatcher.register(MessageType::CancelOrder, decoding_cancel_handler);
+        let error = dispatcher.dispatch(expired.clone(), &client).await.unwrap_err();
+        assert!(error.to_string().contains("expired"));
+
+        // Replays run expired messages, and handlers can still decode them
+        let mut dispatcher = MessageDispatcher::new().with_reject_expired(false);
+        dispatcher.register(MessageType::CancelOrder, decoding_cancel_handler);
+        assert!(dispatcher.dispatch(expired, &client).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_clock() {
+        let client = test_client();
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let header = MessageHeader::new_with_clock(MessageType::CancelOrder, clock.as_ref())
+            .with_expiration_secs(60);
+        let cancel = CancelOrderRequest::by_order_id("BTC", 1);
+        let data = Bytes::from(cancel.to_msgpack_with_header(header).unwrap());
+
+        // Long expired by the system clock, but not by the dispatcher's
+        let mut dispatcher = MessageDispatcher::new().with_clock(clock.clone());
+        dispatcher.register(MessageType::CancelOrder, decoding_cancel_handler);
+        assert!(dispatcher.dispatch(data.clone(), &client).await.is_ok());
+
+        clock.advance(Duration::from_secs(61));
+        let error = dispatcher.dispatch(data, &client).await.unwrap_err();
+        assert!(error.to_string().contains("expired"));
+    }
+
+    #[tokio::test]
+    async fn test_ordering() {
+        let dispatcher = MessageDispatcher::new().with_ordering(PartitionKey::Asset);
+        let btc = CancelOrderRequest::by_order_id("BTC", 1).to_msgpack().unwrap();
+        let eth = CancelOrderRequest::by_order_id("ETH", 1).to_msgpack().unwrap();
+
+        let mut first = dispatcher.claim_turn(&btc).unwrap();
+        let mut second = dispatcher.claim_turn(&btc).unwrap();
+        let mut other = dispatcher.claim_turn(&eth).unwrap();
+        first.ready().await;
+        other.ready().await;
+
+        // The second BTC message waits until the first one's turn ends
+        let wait = tokio::time::timeout(Duration::from_millis(10), second.ready()).await;
+        assert!(wait.is_err());
+        drop(first);
+        second.ready().await;
+
+        assert!(MessageDispatcher::new().claim_turn(&btc).is_none());
+    }
+
+    #[test]
+    fn test_priority_queue() {
+        let dispatcher = MessageDispatcher::new();
+        let order = OrderRequest::market("BTC", true, "1.0").to_msgpack().unwrap();
+        let cancel_all = CancelAllRequest::all().to_msgpack().unwrap();
+        assert_eq!(dispatcher.priority_of(&order), PRIORITY_NORMAL);
+        assert_eq!(dispatcher.priority_of(&cancel_all), PRIORITY_HIGH);
+        assert_eq!(dispatcher.priority_of(b"junk"), PRIORITY_NORMAL);
+
+        // The cancel overtakes the orders queued ahead of it, which keep their order
+        let mut queue = PriorityQueue::new();
+        for (name, data) in [("first", &order), ("second", &order), ("cancel", &cancel_all)] {
+            queue.push(dispatcher.priority_of(data), name);
+        }
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.pop(), Some("cancel"));
+        assert_eq!(queue.pop(), Some("first"));
+        assert_eq!(queue.pop(), Some("second"));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_handler_error_kinds() {
+        let problems = vec!["size is empty".to_string(), "asset is empty".to_string()];
+        let error = HandlerError::from(MessageError::ValidationMany(problems));
+        assert_eq!(error.to_string(), "Invalid message: size is empty; asset is empty");
+        assert!(matches!(
+            HandlerError::from(MessageError::Expired),
+            HandlerError::Decode(MessageError::Expired)
+        ));
+        assert!(matches!(
+            HandlerError::from(crate::Error::GenericRequest("connection reset".to_string())),
+            HandlerError::Transport(_)
+        ));
+        assert!(matches!(
+            HandlerError::from(crate::Error::AssetNotFound),
+            HandlerError::Exchange(_)
+        ));
+        let error = HandlerError::from("x".parse::<f64>().unwrap_err());
+        assert!(matches!(error, HandlerError::Validation(_)));
+    }
+}
@@ -62,3 +62,17 @@ pub enum Error {
     #[error("Vault address not found")]
     VaultAddressNotFound,
 }
+
+impl Error {
+    /// Whether the request failed to reach the exchange or the exchange failed
+    /// to answer it, as opposed to the exchange rejecting the request
+    pub fn is_transport(&self) -> bool {
+        matches!(self, Error::GenericRequest(_) | Error::ServerRequest { .. })
+    }
+
+    /// Whether the exchange turned the request away without executing it for a
+    /// reason that may pass, so the same request can safely be sent again
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Error::ClientRequest { status_code: 429, .. })
+    }
+}
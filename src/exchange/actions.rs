@@ -305,3 +305,10 @@ pub struct ApproveBuilderFee {
     pub signature_chain_id: U256,
     pub hyperliquid_chain: String,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleCancel {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+}
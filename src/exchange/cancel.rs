@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientCancelRequest {
     pub asset: String,
     pub oid: u64,
@@ -15,7 +15,7 @@ pub struct CancelRequest {
     pub oid: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientCancelRequestCloid {
     pub asset: String,
     pub cloid: Uuid,
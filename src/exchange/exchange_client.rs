@@ -2,16 +2,19 @@ use crate::signature::sign_typed_data;
 use crate::{
     exchange::{
         actions::{
-            ApproveAgent, ApproveBuilderFee, BulkCancel, BulkModify, BulkOrder, SetReferrer,
-            UpdateIsolatedMargin, UpdateLeverage, UsdSend,
+            ApproveAgent, ApproveBuilderFee, BulkCancel, BulkModify, BulkOrder, ScheduleCancel,
+            SetReferrer, UpdateIsolatedMargin, UpdateLeverage, UsdSend,
         },
         cancel::{CancelRequest, CancelRequestCloid},
         modify::{ClientModifyRequest, ModifyRequest},
         ClientCancelRequest, ClientOrderRequest,
     },
-    helpers::{generate_random_key, next_nonce, uuid_to_hex_string},
+    helpers::{
+        generate_random_key, next_nonce, round_to_decimals, round_to_significant_and_decimal,
+        uuid_to_hex_string,
+    },
     info::info_client::InfoClient,
-    meta::Meta,
+    meta::{Meta, SpotMeta},
     prelude::*,
     req::HttpClient,
     signature::sign_l1_action,
@@ -32,11 +35,12 @@ use super::cancel::ClientCancelRequestCloid;
 use super::order::{MarketCloseParams, MarketOrderParams};
 use super::{BuilderInfo, ClientLimit, ClientOrder};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ExchangeClient {
     pub http_client: HttpClient,
     pub wallet: LocalWallet,
     pub meta: Meta,
+    pub spot_meta: SpotMeta,
     pub vault_address: Option<H160>,
     pub coin_to_asset: HashMap<String, u32>,
 }
@@ -68,6 +72,7 @@ pub enum Actions {
     SpotSend(SpotSend),
     SetReferrer(SetReferrer),
     ApproveBuilderFee(ApproveBuilderFee),
+    ScheduleCancel(ScheduleCancel),
 }
 
 impl Actions {
@@ -96,7 +101,7 @@ impl ExchangeClient {
         let client = client.unwrap_or_default();
         let base_url = base_url.unwrap_or(BaseUrl::Mainnet);
 
-        let info = InfoClient::new(None, Some(base_url)).await?;
+        let info = InfoClient::new(Some(client.clone()), Some(base_url.clone())).await?;
         let meta = if let Some(meta) = meta {
             meta
         } else {
@@ -108,14 +113,13 @@ impl ExchangeClient {
             coin_to_asset.insert(asset.name.clone(), asset_ind as u32);
         }
 
-        coin_to_asset = info
-            .spot_meta()
-            .await?
-            .add_pair_and_name_to_index_map(coin_to_asset);
+        let spot_meta = info.spot_meta().await?;
+        coin_to_asset = spot_meta.add_pair_and_name_to_index_map(coin_to_asset);
 
         Ok(ExchangeClient {
             wallet,
             meta,
+            spot_meta,
             vault_address,
             http_client: HttpClient {
                 client,
@@ -125,6 +129,19 @@ impl ExchangeClient {
         })
     }
 
+    /// A client trading on behalf of `vault_address` (or the wallet itself if
+    /// `None`), sharing this client's wallet, metadata and HTTP connection pool
+    pub fn for_vault(&self, vault_address: Option<H160>) -> ExchangeClient {
+        ExchangeClient {
+            http_client: self.http_client.clone(),
+            wallet: self.wallet.clone(),
+            meta: self.meta.clone(),
+            spot_meta: self.spot_meta.clone(),
+            vault_address,
+            coin_to_asset: self.coin_to_asset.clone(),
+        }
+    }
+
     async fn post(
         &self,
         action: serde_json::Value,
@@ -252,6 +269,31 @@ impl ExchangeClient {
         self.order(order, params.wallet).await
     }
 
+    /// Place a market order on a spot pair such as `"PURR/USDC"` or `"@107"`
+    pub async fn spot_market_open(
+        &self,
+        params: MarketOrderParams<'_>,
+    ) -> Result<ExchangeResponseStatus> {
+        let slippage = params.slippage.unwrap_or(0.05); // Default 5% slippage
+        let (px, sz_decimals) = self
+            .calculate_spot_slippage_price(params.asset, params.is_buy, slippage, params.px)
+            .await?;
+
+        let order = ClientOrderRequest {
+            asset: params.asset.to_string(),
+            is_buy: params.is_buy,
+            reduce_only: false,
+            limit_px: px,
+            sz: round_to_decimals(params.sz, sz_decimals),
+            cloid: params.cloid,
+            order_type: ClientOrder::Limit(ClientLimit {
+                tif: "Ioc".to_string(),
+            }),
+        };
+
+        self.order(order, params.wallet).await
+    }
+
     pub async fn market_open_with_builder(
         &self,
         params: MarketOrderParams<'_>,
@@ -277,6 +319,13 @@ impl ExchangeClient {
         self.order_with_builder(order, params.wallet, builder).await
     }
 
+    /// An `InfoClient` for the node this client sends actions to, sharing its
+    /// HTTP connection pool
+    async fn info_client(&self) -> Result<InfoClient> {
+        let base_url = BaseUrl::from_url(&self.http_client.base_url);
+        InfoClient::new(Some(self.http_client.client.clone()), Some(base_url)).await
+    }
+
     pub async fn market_close(
         &self,
         params: MarketCloseParams<'_>,
@@ -284,12 +333,7 @@ impl ExchangeClient {
         let slippage = params.slippage.unwrap_or(0.05); // Default 5% slippage
         let wallet = params.wallet.unwrap_or(&self.wallet);
 
-        let base_url = match self.http_client.base_url.as_str() {
-            "https://api.hyperliquid.xyz" => BaseUrl::Mainnet,
-            "https://api.hyperliquid-testnet.xyz" => BaseUrl::Testnet,
-            _ => return Err(Error::GenericRequest("Invalid base URL".to_string())),
-        };
-        let info_client = InfoClient::new(None, Some(base_url)).await?;
+        let info_client = self.info_client().await?;
         let user_state = info_client.user_state(wallet.address()).await?;
 
         let position = user_state
@@ -332,12 +376,7 @@ impl ExchangeClient {
         slippage: f64,
         px: Option<f64>,
     ) -> Result<(f64, u32)> {
-        let base_url = match self.http_client.base_url.as_str() {
-            "https://api.hyperliquid.xyz" => BaseUrl::Mainnet,
-            "https://api.hyperliquid-testnet.xyz" => BaseUrl::Testnet,
-            _ => return Err(Error::GenericRequest("Invalid base URL".to_string())),
-        };
-        let info_client = InfoClient::new(None, Some(base_url)).await?;
+        let info_client = self.info_client().await?;
         let meta = info_client.meta().await?;
 
         let asset_meta = meta
@@ -365,19 +404,47 @@ impl ExchangeClient {
                 .map_err(|_| Error::FloatStringParse)?
         };
 
-        debug!("px before slippage: {px:?}");
-        let slippage_factor = if is_buy {
-            1.0 + slippage
-        } else {
-            1.0 - slippage
+        Ok((apply_slippage(px, is_buy, slippage, price_decimals), sz_decimals))
+    }
+
+    async fn calculate_spot_slippage_price(
+        &self,
+        asset: &str,
+        is_buy: bool,
+        slippage: f64,
+        px: Option<f64>,
+    ) -> Result<(f64, u32)> {
+        let token = |index: Option<&usize>| {
+            index.and_then(|&index| self.spot_meta.tokens.iter().find(|t| t.index == index))
         };
-        let px = px * slippage_factor;
+        // Spot assets are named either by pair ("PURR/USDC") or by universe name ("@107")
+        let (asset_meta, base_token) = self
+            .spot_meta
+            .universe
+            .iter()
+            .find_map(|a| {
+                let base = token(a.tokens.first())?;
+                let quote = token(a.tokens.get(1))?;
+                (a.name == asset || format!("{}/{}", base.name, quote.name) == asset)
+                    .then_some((a, base))
+            })
+            .ok_or(Error::AssetNotFound)?;
 
-        // Round to the correct number of decimal places and significant figures
-        let px = round_to_significant_and_decimal(px, 5, price_decimals);
+        let sz_decimals = base_token.sz_decimals as u32;
+        let price_decimals = 8u32.saturating_sub(sz_decimals);
 
-        debug!("px after slippage: {px:?}");
-        Ok((px, sz_decimals))
+        let px = if let Some(px) = px {
+            px
+        } else {
+            let all_mids = self.info_client().await?.all_mids().await?;
+            all_mids
+                .get(&asset_meta.name)
+                .ok_or(Error::AssetNotFound)?
+                .parse::<f64>()
+                .map_err(|_| Error::FloatStringParse)?
+        };
+
+        Ok((apply_slippage(px, is_buy, slippage, price_decimals), sz_decimals))
     }
 
     pub async fn order(
@@ -402,6 +469,18 @@ impl ExchangeClient {
         &self,
         orders: Vec<ClientOrderRequest>,
         wallet: Option<&LocalWallet>,
+    ) -> Result<ExchangeResponseStatus> {
+        self.bulk_order_with_grouping(orders, wallet, "na").await
+    }
+
+    /// Place `orders` as one action with the exchange's `grouping`: "na" for
+    /// independent orders, "normalTpsl" for an entry followed by its take-profit
+    /// and stop-loss, or "positionTpsl" for take-profit and stop-loss on a position
+    pub async fn bulk_order_with_grouping(
+        &self,
+        orders: Vec<ClientOrderRequest>,
+        wallet: Option<&LocalWallet>,
+        grouping: &str,
     ) -> Result<ExchangeResponseStatus> {
         let wallet = wallet.unwrap_or(&self.wallet);
         let timestamp = next_nonce();
@@ -414,7 +493,7 @@ impl ExchangeClient {
 
         let action = Actions::Order(BulkOrder {
             orders: transformed_orders,
-            grouping: "na".to_string(),
+            grouping: grouping.to_string(),
             builder: None,
         });
         let connection_id = action.hash(timestamp, self.vault_address)?;
@@ -625,7 +704,6 @@ impl ExchangeClient {
         &self,
         wallet: Option<&LocalWallet>,
     ) -> Result<(String, ExchangeResponseStatus)> {
-        let wallet = wallet.unwrap_or(&self.wallet);
         let key = H256::from(generate_random_key()?).encode_hex()[2..].to_string();
 
         let address = key
@@ -633,6 +711,17 @@ impl ExchangeClient {
             .map_err(|e| Error::PrivateKeyParse(e.to_string()))?
             .address();
 
+        Ok((key, self.approve_agent_address(address, None, wallet).await?))
+    }
+
+    /// Approve an agent whose key the caller already holds, optionally under a name
+    pub async fn approve_agent_address(
+        &self,
+        agent_address: H160,
+        agent_name: Option<String>,
+        wallet: Option<&LocalWallet>,
+    ) -> Result<ExchangeResponseStatus> {
+        let wallet = wallet.unwrap_or(&self.wallet);
         let hyperliquid_chain = if self.http_client.is_mainnet() {
             "Mainnet".to_string()
         } else {
@@ -643,14 +732,14 @@ impl ExchangeClient {
         let approve_agent = ApproveAgent {
             signature_chain_id: 421614.into(),
             hyperliquid_chain,
-            agent_address: address,
-            agent_name: None,
+            agent_address,
+            agent_name,
             nonce,
         };
         let signature = sign_typed_data(&approve_agent, wallet)?;
         let action = serde_json::to_value(Actions::ApproveAgent(approve_agent))
             .map_err(|e| Error::JsonParse(e.to_string()))?;
-        Ok((key, self.post(action, signature, nonce).await?))
+        self.post(action, signature, nonce).await
     }
 
     pub async fn withdraw_from_bridge(
@@ -759,19 +848,44 @@ impl ExchangeClient {
         let signature = sign_l1_action(wallet, connection_id, is_mainnet)?;
         self.post(action, signature, timestamp).await
     }
-}
 
-fn round_to_decimals(value: f64, decimals: u32) -> f64 {
-    let factor = 10f64.powi(decimals as i32);
-    (value * factor).round() / factor
+    /// Cancel all open orders at `time` (ms since epoch) unless rescheduled first.
+    ///
+    /// Passing `None` clears a previously scheduled cancel.
+    pub async fn schedule_cancel(
+        &self,
+        time: Option<u64>,
+        wallet: Option<&LocalWallet>,
+    ) -> Result<ExchangeResponseStatus> {
+        let wallet = wallet.unwrap_or(&self.wallet);
+        let timestamp = next_nonce();
+
+        let action = Actions::ScheduleCancel(ScheduleCancel { time });
+
+        let connection_id = action.hash(timestamp, self.vault_address)?;
+        let action = serde_json::to_value(&action).map_err(|e| Error::JsonParse(e.to_string()))?;
+
+        let is_mainnet = self.http_client.is_mainnet();
+        let signature = sign_l1_action(wallet, connection_id, is_mainnet)?;
+        self.post(action, signature, timestamp).await
+    }
 }
 
-fn round_to_significant_and_decimal(value: f64, sig_figs: u32, max_decimals: u32) -> f64 {
-    let abs_value = value.abs();
-    let magnitude = abs_value.log10().floor() as i32;
-    let scale = 10f64.powi(sig_figs as i32 - magnitude - 1);
-    let rounded = (abs_value * scale).round() / scale;
-    round_to_decimals(rounded.copysign(value), max_decimals)
+/// Move `px` against the trader by `slippage` and round it to a valid price
+fn apply_slippage(px: f64, is_buy: bool, slippage: f64, price_decimals: u32) -> f64 {
+    debug!("px before slippage: {px:?}");
+    let slippage_factor = if is_buy {
+        1.0 + slippage
+    } else {
+        1.0 - slippage
+    };
+    let px = px * slippage_factor;
+
+    // Round to the correct number of decimal places and significant figures
+    let px = round_to_significant_and_decimal(px, 5, price_decimals);
+
+    debug!("px after slippage: {px:?}");
+    px
 }
 
 #[cfg(test)]
@@ -43,3 +43,19 @@ pub enum ExchangeResponseStatus {
     Ok(ExchangeResponse),
     Err(String),
 }
+
+impl ExchangeResponseStatus {
+    /// Whether the exchange rejected the request for a reason that may pass,
+    /// such as a stale nonce or rate limiting, so it can safely be sent again
+    pub fn is_recoverable_error(&self) -> bool {
+        match self {
+            ExchangeResponseStatus::Ok(_) => false,
+            ExchangeResponseStatus::Err(message) => {
+                let message = message.to_lowercase();
+                message.contains("nonce")
+                    || message.contains("rate limit")
+                    || message.contains("too many requests")
+            }
+        }
+    }
+}
@@ -47,19 +47,19 @@ pub struct OrderRequest {
     pub cloid: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientLimit {
     pub tif: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientTrigger {
     pub is_market: bool,
     pub trigger_px: f64,
     pub tpsl: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MarketOrderParams<'a> {
     pub asset: &'a str,
     pub is_buy: bool,
@@ -70,7 +70,7 @@ pub struct MarketOrderParams<'a> {
     pub wallet: Option<&'a LocalWallet>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MarketCloseParams<'a> {
     pub asset: &'a str,
     pub sz: Option<f64>,
@@ -80,13 +80,13 @@ pub struct MarketCloseParams<'a> {
     pub wallet: Option<&'a LocalWallet>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ClientOrder {
     Limit(ClientLimit),
     Trigger(ClientTrigger),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientOrderRequest {
     pub asset: String,
     pub is_buy: bool,
@@ -11,6 +11,12 @@ fn now_timestamp_ms() -> u64 {
     now.timestamp_millis() as u64
 }
 
+/// The nonce for the next signed action.
+///
+/// Every `ExchangeClient` in the process draws from this one counter, so
+/// concurrent callers always sign with distinct, strictly increasing nonces.
+/// It starts at the current time and is pulled forward if it falls more than
+/// five minutes behind.
 pub(crate) fn next_nonce() -> u64 {
     let nonce = CUR_NONCE.fetch_add(1, Ordering::Relaxed);
     let now_ms = now_timestamp_ms();
@@ -59,6 +65,23 @@ pub(crate) fn generate_random_key() -> Result<[u8; 32]> {
     Ok(arr)
 }
 
+pub(crate) fn round_to_decimals(value: f64, decimals: u32) -> f64 {
+    let factor = 10f64.powi(decimals as i32);
+    (value * factor).round() / factor
+}
+
+pub(crate) fn round_to_significant_and_decimal(
+    value: f64,
+    sig_figs: u32,
+    max_decimals: u32,
+) -> f64 {
+    let abs_value = value.abs();
+    let magnitude = abs_value.log10().floor() as i32;
+    let scale = 10f64.powi(sig_figs as i32 - magnitude - 1);
+    let rounded = (abs_value * scale).round() / scale;
+    round_to_decimals(rounded.copysign(value), max_decimals)
+}
+
 pub fn truncate_float(float: f64, decimals: u32, round_up: bool) -> f64 {
     let pow10 = 10i64.pow(decimals) as f64;
     let mut float = (float * pow10) as u64;
@@ -76,9 +99,11 @@ pub fn bps_diff(x: f64, y: f64) -> u16 {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum BaseUrl {
     Localhost,
+    /// A local node on another host or port than `LOCAL_API_URL`
+    LocalhostAt(String),
     Testnet,
     Mainnet,
 }
@@ -87,10 +112,25 @@ impl BaseUrl {
     pub(crate) fn get_url(&self) -> String {
         match self {
             BaseUrl::Localhost => LOCAL_API_URL.to_string(),
+            BaseUrl::LocalhostAt(url) => url.trim_end_matches('/').to_string(),
             BaseUrl::Mainnet => MAINNET_API_URL.to_string(),
             BaseUrl::Testnet => TESTNET_API_URL.to_string(),
         }
     }
+
+    /// Map a client's API URL back to the `BaseUrl` it was created with
+    pub fn from_url(url: &str) -> BaseUrl {
+        let url = url.trim_end_matches('/');
+        if url == MAINNET_API_URL {
+            BaseUrl::Mainnet
+        } else if url == TESTNET_API_URL {
+            BaseUrl::Testnet
+        } else if url == LOCAL_API_URL {
+            BaseUrl::Localhost
+        } else {
+            BaseUrl::LocalhostAt(url.to_string())
+        }
+    }
 }
 
 lazy_static! {
@@ -141,4 +181,31 @@ mod tests {
             "987654321".to_string()
         );
     }
+
+    #[test]
+    fn next_nonce_concurrent_test() {
+        let threads: Vec<_> = (0..8)
+            .map(|_| std::thread::spawn(|| (0..1000).map(|_| next_nonce()).collect::<Vec<_>>()))
+            .collect();
+        let mut all = Vec::new();
+        for thread in threads {
+            let nonces = thread.join().unwrap();
+            assert!(nonces.windows(2).all(|pair| pair[0] < pair[1]));
+            all.extend(nonces);
+        }
+        let count = all.len();
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), count);
+    }
+
+    #[test]
+    fn base_url_round_trip_test() {
+        let local = BaseUrl::LocalhostAt("http://127.0.0.1:3999/".to_string());
+        assert_eq!(local.get_url(), "http://127.0.0.1:3999");
+        for base in [BaseUrl::Localhost, local, BaseUrl::Testnet, BaseUrl::Mainnet] {
+            assert_eq!(BaseUrl::from_url(&base.get_url()).get_url(), base.get_url());
+        }
+        assert_eq!(BaseUrl::from_url(LOCAL_API_URL), BaseUrl::Localhost);
+    }
 }
@@ -1,5 +1,6 @@
 #![deny(unreachable_pub)]
 mod consts;
+mod dispatch;
 mod errors;
 pub mod exchange;
 mod helpers;
@@ -7,16 +8,23 @@ mod info;
 mod market_maker;
 mod meta;
 pub mod messages;
+pub mod metrics;
 mod prelude;
 mod proxy_digest;
 mod req;
 mod signature;
+mod spans;
 mod ws;
 pub use consts::{EPSILON, LOCAL_API_URL, MAINNET_API_URL, TESTNET_API_URL};
+pub use dispatch::{
+    DispatchError, DispatchFailure, HandlerError, HandlerFn, HandlerFuture, MessageContext,
+    MessageDispatcher, PartitionKey, PriorityQueue, QueryFn, QueryFuture, Turn,
+    UndecodableMessage, DEFAULT_HANDLER_TIMEOUT, PAYLOAD_PREVIEW_BYTES,
+};
 pub use errors::Error;
 pub use exchange::*;
 pub use helpers::{bps_diff, truncate_float, BaseUrl};
 pub use info::{info_client::*, *};
 pub use market_maker::{MarketMaker, MarketMakerInput, MarketMakerRestingOrder};
-pub use meta::{AssetMeta, Meta};
+pub use meta::{AssetMeta, Meta, SpotAssetMeta, SpotMeta, TokenInfo};
 pub use ws::*;
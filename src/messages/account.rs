@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::messages::ExchangeMessage;
 
-use super::MessageType;
+use super::MessageError;
 
 /// Request to update isolated margin
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,8 +31,8 @@ impl ExchangeMessage for UpdateIsolatedMarginRequest {
         "update_isolated_margin"
     }
 
-    fn message_type() -> MessageType {
-        MessageType::UpdateIsolatedMargin
+    fn asset(&self) -> Option<&str> {
+        Some(&self.asset)
     }
 }
 
@@ -43,6 +43,7 @@ pub struct ApproveAgentRequest {
     pub agent_address: String,
 
     /// Optional agent name
+    #[serde(default)]
     pub agent_name: Option<String>,
 }
 
@@ -60,16 +61,24 @@ impl ApproveAgentRequest {
         self.agent_name = Some(name.to_string());
         self
     }
+
+    /// Check that `agent_address` is a 20-byte hex address
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let digits = self.agent_address.strip_prefix("0x").unwrap_or(&self.agent_address);
+        match hex::decode(digits) {
+            Ok(bytes) if bytes.len() == 20 => Ok(()),
+            _ => Err(MessageError::Validation(format!(
+                "Invalid agent address: {}",
+                self.agent_address
+            ))),
+        }
+    }
 }
 
 impl ExchangeMessage for ApproveAgentRequest {
     fn message_type_str(&self) -> &'static str {
         "approve_agent"
     }
-
-    fn message_type() -> MessageType {
-        MessageType::ApproveAgent
-    }
 }
 
 /// Request to set a referrer
@@ -92,10 +101,6 @@ impl ExchangeMessage for SetReferrerRequest {
     fn message_type_str(&self) -> &'static str {
         "set_referrer"
     }
-
-    fn message_type() -> MessageType {
-        MessageType::SetReferrer
-    }
 }
 
 /// Request to approve builder fee
@@ -122,8 +127,38 @@ impl ExchangeMessage for ApproveBuilderFeeRequest {
     fn message_type_str(&self) -> &'static str {
         "approve_builder_fee"
     }
+}
+
+/// Liveness signal from a strategy.
+///
+/// The service remembers when each strategy last sent one. If a strategy goes
+/// quiet for longer than the service's heartbeat window, the service schedules
+/// a cancel of every open order on the account the heartbeats named.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatRequest {
+    /// Identifies the strategy sending the heartbeat
+    pub strategy_id: String,
+}
+
+impl HeartbeatRequest {
+    /// Create a heartbeat for the strategy `strategy_id`
+    pub fn new(strategy_id: &str) -> Self {
+        Self {
+            strategy_id: strategy_id.to_string(),
+        }
+    }
 
-    fn message_type() -> MessageType {
-        MessageType::ApproveBuilderFee
+    /// Check that `strategy_id` is set
+    pub fn validate(&self) -> Result<(), MessageError> {
+        if self.strategy_id.is_empty() {
+            return Err(MessageError::Validation("strategy_id must be set".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl ExchangeMessage for HeartbeatRequest {
+    fn message_type_str(&self) -> &'static str {
+        "heartbeat"
     }
 }
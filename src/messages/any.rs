@@ -0,0 +1,175 @@
+//! A single type covering every message, for decoding payloads of unknown type
+
+use super::types::encode_message;
+use super::*;
+
+macro_rules! any_message {
+    ($($variant:ident($t:ty)),* $(,)?) => {
+        /// Any message, with one variant per `MessageType`.
+        ///
+        /// Decode a payload of unknown type with `AnyMessage::from_msgpack` and
+        /// `match` on the result; adding a `MessageType` without a variant here
+        /// fails to compile.
+        #[derive(Debug, Clone)]
+        pub enum AnyMessage {
+            $($variant($t),)*
+        }
+
+        impl AnyMessage {
+            /// The message type of the wrapped message
+            pub fn message_type(&self) -> MessageType {
+                match self {
+                    $(AnyMessage::$variant(_) => MessageType::$variant,)*
+                }
+            }
+
+            /// Decode a framed message into the variant named by its header's `msg_type`
+            pub fn from_msgpack(data: &[u8]) -> Result<Self, MessageError> {
+                Self::from_msgpack_verified(data, None)
+            }
+
+            /// Like `from_msgpack`, verifying the signature when a secret is given
+            pub fn from_msgpack_verified(
+                data: &[u8],
+                secret: Option<&[u8]>,
+            ) -> Result<Self, MessageError> {
+                let header = peek_header(data)?;
+                match header.msg_type {
+                    $(MessageType::$variant => Ok(AnyMessage::$variant(
+                        <$t as Message>::from_msgpack_verified(data, secret)?,
+                    )),)*
+                    MessageType::Unknown => {
+                        Err(MessageError::UnknownMessageType(header.type_name()))
+                    }
+                }
+            }
+
+            /// The asset the wrapped message trades or cancels, if it names a single one
+            pub fn asset(&self) -> Option<&str> {
+                match self {
+                    $(AnyMessage::$variant(msg) => msg.asset(),)*
+                }
+            }
+
+            /// The subject the wrapped message belongs on under `root`
+            pub fn subject_under(&self, root: &str) -> String {
+                match self {
+                    $(AnyMessage::$variant(msg) => msg.subject_under(root),)*
+                }
+            }
+
+            /// Serialize the wrapped message with a fresh header of its type
+            pub fn to_msgpack(&self) -> Result<Vec<u8>, MessageError> {
+                match self {
+                    $(AnyMessage::$variant(msg) => msg.to_msgpack(),)*
+                }
+            }
+
+            /// Serialize the wrapped message behind a caller-built header,
+            /// signing it when a secret is given.
+            ///
+            /// The header's `msg_type` must match the wrapped message.
+            pub fn to_msgpack_with_header(
+                &self,
+                header: MessageHeader,
+                secret: Option<&[u8]>,
+            ) -> Result<Vec<u8>, MessageError> {
+                if header.msg_type != self.message_type() {
+                    return Err(MessageError::MismatchedType {
+                        expected: self.message_type(),
+                        actual: header.msg_type,
+                    });
+                }
+                match self {
+                    $(AnyMessage::$variant(msg) => encode_message(msg, header, secret),)*
+                }
+            }
+
+            /// Build a message of `msg_type` from its fields as a JSON object,
+            /// e.g. `{"asset": "BTC"}` for a `CancelAll`
+            pub fn from_json_body(
+                msg_type: MessageType,
+                body: serde_json::Value,
+            ) -> Result<Self, MessageError> {
+                let invalid = |e: serde_json::Error| {
+                    MessageError::InvalidFormat(format!("Invalid {} body: {}", msg_type, e))
+                };
+                match msg_type {
+                    $(MessageType::$variant => Ok(AnyMessage::$variant(
+                        serde_json::from_value::<$t>(body).map_err(invalid)?,
+                    )),)*
+                    MessageType::Unknown => {
+                        Err(MessageError::UnknownMessageType(msg_type.to_string()))
+                    }
+                }
+            }
+        }
+
+        $(
+            impl From<$t> for AnyMessage {
+                fn from(msg: $t) -> Self {
+                    AnyMessage::$variant(msg)
+                }
+            }
+        )*
+    };
+}
+
+any_message! {
+    Order(OrderRequest),
+    CancelOrder(CancelOrderRequest),
+    ModifyOrder(ModifyOrderRequest),
+    UpdateLeverage(UpdateLeverageRequest),
+    BatchUpdateLeverage(BatchUpdateLeverageRequest),
+    BatchOrder(BatchOrderRequest),
+    CancelAll(CancelAllRequest),
+    ScheduleCancel(ScheduleCancelRequest),
+    BulkCancel(BulkCancelRequest),
+    ClosePosition(ClosePositionRequest),
+    Flatten(FlattenRequest),
+    Transfer(TransferRequest),
+    Withdraw(WithdrawRequest),
+    ClassTransfer(ClassTransferRequest),
+    VaultTransfer(VaultTransferRequest),
+    SpotTransfer(SpotTransferRequest),
+    UpdateIsolatedMargin(UpdateIsolatedMarginRequest),
+    ApproveAgent(ApproveAgentRequest),
+    SetReferrer(SetReferrerRequest),
+    ApproveBuilderFee(ApproveBuilderFeeRequest),
+    Heartbeat(HeartbeatRequest),
+    Ack(AckResponse),
+    PositionQueryResponse(PositionQueryResponse),
+    BulkCancelResponse(BulkCancelResponse),
+    OrderResponse(OrderResponse),
+    ApproveAgentResponse(ApproveAgentResponse),
+    BatchOrderResponse(BatchOrderResponse),
+    FlattenResponse(FlattenResponse),
+    BatchResponse(BatchResponse),
+    ResolveCloidResponse(ResolveCloidResponse),
+    PositionQuery(PositionQueryRequest),
+    ResolveCloid(ResolveCloidRequest),
+    StatusEvent(StatusEvent),
+}
+
+impl AnyMessage {
+    /// Check the wrapped message's fields, for message types that have a
+    /// `validate` method; other messages are always valid
+    pub fn validate(&self) -> Result<(), MessageError> {
+        match self {
+            AnyMessage::Order(msg) => msg.validate(),
+            AnyMessage::BatchOrder(msg) => msg.validate(),
+            AnyMessage::CancelOrder(msg) => msg.validate(),
+            AnyMessage::ModifyOrder(msg) => msg.validate(),
+            AnyMessage::BulkCancel(msg) => msg.validate(),
+            AnyMessage::ClosePosition(msg) => msg.validate(),
+            AnyMessage::Flatten(msg) => msg.validate(),
+            AnyMessage::Transfer(msg) => msg.validate(),
+            AnyMessage::VaultTransfer(msg) => msg.validate(),
+            AnyMessage::SpotTransfer(msg) => msg.validate(),
+            AnyMessage::ApproveAgent(msg) => msg.validate(),
+            AnyMessage::Heartbeat(msg) => msg.validate(),
+            AnyMessage::ResolveCloid(msg) => msg.validate(),
+            _ => Ok(()),
+        }
+    }
+}
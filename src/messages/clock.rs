@@ -0,0 +1,60 @@
+//! Sources of the current time for message headers and expiry checks
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tells the time to `MessageHeader::new_with_clock`,
+/// `MessageHeader::is_expired_with` and `MessageDispatcher::with_clock`.
+///
+/// The default is `SystemClock`. Tests can step a `ManualClock` through
+/// expiry deterministically, and deployments whose system clock drifts can
+/// supply an NTP-corrected source.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current time in milliseconds since the Unix epoch
+    fn now_millis(&self) -> u64;
+}
+
+/// The system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+/// A clock that only moves when it is set or advanced
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    /// Create a clock reading `now_millis`
+    pub fn new(now_millis: u64) -> Self {
+        Self {
+            now: AtomicU64::new(now_millis),
+        }
+    }
+
+    /// Set the time to `now_millis`
+    pub fn set(&self, now_millis: u64) {
+        self.now.store(now_millis, Ordering::Relaxed);
+    }
+
+    /// Move the time forward by `by`
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,108 @@
+//! Wire framing of a message header and its serialized body
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::header::MessageHeader;
+use super::types::{MessageError, MAX_HEADER_SIZE};
+
+/// Frames a header and an already-serialized body into a single payload.
+///
+/// Body serialization, compression and signing happen before `encode` and
+/// after `decode`, so a codec only decides how the header and body share
+/// the wire. Message types pick theirs with `Message::codec`.
+pub trait Codec: fmt::Debug + Send + Sync {
+    /// Combine `header` and the raw `body` bytes into one payload
+    fn encode(&self, header: &MessageHeader, body: &[u8]) -> Result<Vec<u8>, MessageError>;
+
+    /// Split a payload into its header and raw body bytes
+    fn decode<'a>(&self, data: &'a [u8]) -> Result<(MessageHeader, &'a [u8]), MessageError>;
+
+    /// How bodies framed by this codec are serialized (default: `BodyEncoding::Named`)
+    fn body_encoding(&self) -> BodyEncoding {
+        BodyEncoding::Named
+    }
+}
+
+/// How a message body is serialized to msgpack, as declared by the header's
+/// `encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyEncoding {
+    /// Structs as maps keyed by field name: self-describing and readable by
+    /// any msgpack library
+    #[default]
+    Named,
+    /// Structs as arrays of field values in declaration order: smaller, but
+    /// readers must know the field order
+    Compact,
+}
+
+impl BodyEncoding {
+    /// Whether this is the default `Named` encoding
+    pub fn is_named(&self) -> bool {
+        *self == BodyEncoding::Named
+    }
+}
+
+/// The default codec: header length (4 bytes, big endian), msgpack header, body
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MsgpackCodec;
+
+impl Codec for MsgpackCodec {
+    fn encode(&self, header: &MessageHeader, body: &[u8]) -> Result<Vec<u8>, MessageError> {
+        let header_bytes = rmp_serde::to_vec_named(header)?;
+
+        let mut result = Vec::with_capacity(4 + header_bytes.len() + body.len());
+        result.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+        result.extend(header_bytes);
+        result.extend_from_slice(body);
+
+        Ok(result)
+    }
+
+    fn decode<'a>(&self, data: &'a [u8]) -> Result<(MessageHeader, &'a [u8]), MessageError> {
+        // First 4 bytes are the header length. Lengths come from untrusted
+        // input, so every split below is checked rather than indexed.
+        let Some((len_bytes, rest)) = data.split_first_chunk::<4>() else {
+            return Err(MessageError::InvalidFormat("Message too short".to_string()));
+        };
+        let header_len = usize::try_from(u32::from_be_bytes(*len_bytes))
+            .map_err(|_| MessageError::InvalidFormat("Invalid header length".to_string()))?;
+
+        if header_len > MAX_HEADER_SIZE {
+            return Err(MessageError::InvalidFormat(format!(
+                "Header of {} bytes exceeds the {} byte limit",
+                header_len, MAX_HEADER_SIZE
+            )));
+        }
+        let Some((header_bytes, body)) = rest.split_at_checked(header_len) else {
+            return Err(MessageError::InvalidFormat("Invalid header length".to_string()));
+        };
+
+        let header = MessageHeader::from_msgpack_slice(header_bytes)?;
+        Ok((header, body))
+    }
+}
+
+/// Frames like `MsgpackCodec`, but serializes bodies with
+/// `BodyEncoding::Compact` to save bytes on links where both ends share the
+/// message definitions. Headers stay named, and declare the encoding, so
+/// compact and named messages can share a subject.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompactMsgpackCodec;
+
+impl Codec for CompactMsgpackCodec {
+    fn encode(&self, header: &MessageHeader, body: &[u8]) -> Result<Vec<u8>, MessageError> {
+        MsgpackCodec.encode(header, body)
+    }
+
+    fn decode<'a>(&self, data: &'a [u8]) -> Result<(MessageHeader, &'a [u8]), MessageError> {
+        MsgpackCodec.decode(data)
+    }
+
+    fn body_encoding(&self) -> BodyEncoding {
+        BodyEncoding::Compact
+    }
+}
@@ -0,0 +1,124 @@
+//! Conversions from messages to the `ExchangeClient` request types they
+//! describe, so handlers and tests share one mapping
+
+use uuid::Uuid;
+
+use crate::messages::{CancelOrderRequest, MessageError, OrderRequest};
+use crate::{
+    ClientCancelRequest, ClientCancelRequestCloid, ClientLimit, ClientOrder, ClientOrderRequest,
+    ClientTrigger, MarketOrderParams,
+};
+
+/// Parse the decimal string `value` of the field `name`
+fn parse_number(name: &str, value: &str) -> Result<f64, MessageError> {
+    value
+        .parse()
+        .map_err(|_| MessageError::Validation(format!("Invalid {}: {}", name, value)))
+}
+
+/// Parse an optional cloid
+fn parse_cloid(cloid: Option<&str>) -> Result<Option<Uuid>, MessageError> {
+    cloid
+        .map(|cloid| {
+            Uuid::parse_str(cloid)
+                .map_err(|_| MessageError::Validation(format!("Invalid cloid: {}", cloid)))
+        })
+        .transpose()
+}
+
+/// A limit or trigger order. Market orders have no price to send and fail;
+/// convert them to `MarketOrderParams` instead.
+impl TryFrom<&OrderRequest> for ClientOrderRequest {
+    type Error = MessageError;
+
+    fn try_from(req: &OrderRequest) -> Result<Self, Self::Error> {
+        let (limit_px, order_type) = match (&req.trigger, &req.limit_price) {
+            (Some(trigger), limit_price) => {
+                let trigger_px = parse_number("trigger price", &trigger.trigger_px)?;
+                let limit_px = match limit_price {
+                    Some(px) => parse_number("limit price", px)?,
+                    None => trigger_px,
+                };
+                let trigger = ClientTrigger {
+                    is_market: trigger.is_market,
+                    trigger_px,
+                    tpsl: trigger.tpsl.clone(),
+                };
+                (limit_px, ClientOrder::Trigger(trigger))
+            }
+            (None, Some(px)) => {
+                let limit = ClientLimit {
+                    tif: req.time_in_force.to_string(),
+                };
+                (parse_number("limit price", px)?, ClientOrder::Limit(limit))
+            }
+            (None, None) => {
+                return Err(MessageError::Validation(
+                    "Market orders have no limit price or trigger".to_string(),
+                ))
+            }
+        };
+        Ok(ClientOrderRequest {
+            asset: req.asset.clone(),
+            is_buy: req.is_buy,
+            reduce_only: req.reduce_only,
+            limit_px,
+            sz: parse_number("size", &req.size)?,
+            cloid: parse_cloid(req.cloid.as_deref())?,
+            order_type,
+        })
+    }
+}
+
+/// A market order, priced from the mid when it is placed. Limit and trigger
+/// orders fail; convert them to `ClientOrderRequest` instead.
+impl<'a> TryFrom<&'a OrderRequest> for MarketOrderParams<'a> {
+    type Error = MessageError;
+
+    fn try_from(req: &'a OrderRequest) -> Result<Self, Self::Error> {
+        if !req.is_market() {
+            return Err(MessageError::Validation(
+                "Only orders without a limit price or trigger are market orders".to_string(),
+            ));
+        }
+        Ok(MarketOrderParams {
+            asset: &req.asset,
+            is_buy: req.is_buy,
+            sz: parse_number("size", &req.size)?,
+            px: None,
+            slippage: req.slippage,
+            cloid: parse_cloid(req.cloid.as_deref())?,
+            wallet: None,
+        })
+    }
+}
+
+/// A cancel by order ID; fails if the request has none
+impl TryFrom<&CancelOrderRequest> for ClientCancelRequest {
+    type Error = MessageError;
+
+    fn try_from(req: &CancelOrderRequest) -> Result<Self, Self::Error> {
+        let oid = req.order_id.ok_or_else(|| {
+            MessageError::Validation("Cancel request has no order_id".to_string())
+        })?;
+        Ok(ClientCancelRequest {
+            asset: req.asset.clone(),
+            oid,
+        })
+    }
+}
+
+/// A cancel by client order ID; fails if the request has none
+impl TryFrom<&CancelOrderRequest> for ClientCancelRequestCloid {
+    type Error = MessageError;
+
+    fn try_from(req: &CancelOrderRequest) -> Result<Self, Self::Error> {
+        let cloid = parse_cloid(req.cloid.as_deref())?.ok_or_else(|| {
+            MessageError::Validation("Cancel request has no cloid".to_string())
+        })?;
+        Ok(ClientCancelRequestCloid {
+            asset: req.asset.clone(),
+            cloid,
+        })
+    }
+}
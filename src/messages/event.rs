@@ -0,0 +1,123 @@
+//! Event messages published by the service as things happen, rather than in
+//! reply to a request
+
+use serde::{Deserialize, Serialize};
+
+use crate::messages::{
+    ExchangeMessage, Message, MessageError, MessageHeader, MessageType, OrderResponse, OrderStatus,
+};
+
+/// Stage an order has reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStage {
+    /// About to be sent to the exchange
+    Submitted,
+    /// Resting on the book
+    Resting,
+    /// Filled immediately, fully or in part
+    Filled,
+    /// Accepted and waiting to fill
+    WaitingForFill,
+    /// A trigger order waiting for its trigger price
+    WaitingForTrigger,
+    /// Rejected by the exchange or the service
+    Rejected,
+}
+
+impl From<OrderStatus> for OrderStage {
+    fn from(status: OrderStatus) -> Self {
+        match status {
+            OrderStatus::Filled => OrderStage::Filled,
+            OrderStatus::Resting => OrderStage::Resting,
+            OrderStatus::WaitingForFill => OrderStage::WaitingForFill,
+            OrderStatus::WaitingForTrigger => OrderStage::WaitingForTrigger,
+        }
+    }
+}
+
+/// A step in the lifecycle of an order placed from an `OrderRequest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusEvent {
+    /// `msg_id` of the `OrderRequest` that placed the order
+    pub msg_id: [u8; 16],
+
+    /// Client order ID of the order (optional)
+    #[serde(default)]
+    pub cloid: Option<String>,
+
+    /// The asset traded
+    pub asset: String,
+
+    /// Stage the order has reached
+    pub stage: OrderStage,
+
+    /// Exchange order ID, once known (optional)
+    #[serde(default)]
+    pub oid: Option<u64>,
+
+    /// Size filled immediately (optional)
+    #[serde(default)]
+    pub filled_size: Option<String>,
+
+    /// Average price of the immediate fill (optional)
+    #[serde(default)]
+    pub avg_price: Option<String>,
+
+    /// Size left unfilled by a partial fill (optional)
+    #[serde(default)]
+    pub remaining_size: Option<String>,
+
+    /// Why the order was rejected (optional)
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl StatusEvent {
+    /// Create an event for the order from the request with `msg_id` reaching `stage`
+    pub fn new(msg_id: [u8; 16], asset: &str, cloid: Option<String>, stage: OrderStage) -> Self {
+        Self {
+            msg_id,
+            cloid,
+            asset: asset.to_string(),
+            stage,
+            oid: None,
+            filled_size: None,
+            avg_price: None,
+            remaining_size: None,
+            error: None,
+        }
+    }
+
+    /// Create an event reporting what the exchange did with the order
+    pub fn from_response(msg_id: [u8; 16], asset: &str, response: &OrderResponse) -> Self {
+        Self {
+            oid: response.oid,
+            filled_size: response.filled_size.clone(),
+            avg_price: response.avg_price.clone(),
+            remaining_size: response.remaining_size.clone(),
+            ..Self::new(msg_id, asset, response.cloid.clone(), response.status.into())
+        }
+    }
+
+    /// Create an event for an order that was rejected with `error`
+    pub fn rejected(msg_id: [u8; 16], asset: &str, cloid: Option<String>, error: &str) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(msg_id, asset, cloid, OrderStage::Rejected)
+        }
+    }
+
+    /// Serialize this event about the request with header `request`.
+    ///
+    /// Like a reply, the event carries the request's correlation id, or its
+    /// `msg_id` if it had none.
+    pub fn to_event(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::StatusEvent))
+    }
+}
+
+impl ExchangeMessage for StatusEvent {
+    fn message_type_str(&self) -> &'static str {
+        "status_event"
+    }
+}
@@ -1,9 +1,25 @@
-use serde::{Deserialize, Serialize};
+use hmac::{Hmac, Mac};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::Sha256;
 use uuid::Uuid;
-use crate::messages::types::MessageType;
+use crate::messages::clock::{Clock, SystemClock};
+use crate::messages::codec::BodyEncoding;
+use crate::messages::types::{
+    initial_schema_version, normal_priority, MessageError, MessageType,
+    DEFAULT_EXPIRATION_SECS, PRIORITY_NORMAL, SCHEMA_VERSION,
+};
 
-/// Standard message header for all exchange messages
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+type HmacSha256 = Hmac<Sha256>;
+
+/// Standard message header for all exchange messages.
+///
+/// This is the only header type on the wire; both `Message` and
+/// `ExchangeMessage` serialization build it with `MessageHeader::new`, which
+/// assigns a random `msg_id`, the current time, and an expiry of
+/// `DEFAULT_EXPIRATION_SECS`.
+/// `MessageHeader::default()` is an all-zero header that never expires.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct MessageHeader {
     /// Message type identifier
     pub msg_type: MessageType,
@@ -19,20 +35,86 @@ pub struct MessageHeader {
     
     /// Expiration timestamp in milliseconds since epoch (0 for no expiration)
     pub expires_at: u64,
+
+    /// Whether the body is zstd-compressed
+    #[serde(default)]
+    pub compressed: bool,
+
+    /// Schema version of the message body
+    #[serde(default = "initial_schema_version")]
+    pub version: u16,
+
+    /// Optional HMAC-SHA256 over the msg_id, timestamp, msg_type, account, priority,
+    /// expires_at, correlation_id and body bytes
+    #[serde(default)]
+    pub signature: Option<[u8; 32]>,
+
+    /// Name of the configured account to act as (optional; the service's default if unset)
+    #[serde(default)]
+    pub account: Option<String>,
+
+    /// How urgently to handle the message; higher priorities are handled first
+    /// when messages queue up (`PRIORITY_NORMAL` if unset)
+    #[serde(default = "normal_priority")]
+    pub priority: u8,
+
+    /// How the body is serialized. Only written when `Compact`, so named
+    /// messages keep their original framing.
+    #[serde(default, skip_serializing_if = "BodyEncoding::is_named")]
+    pub encoding: BodyEncoding,
+
+    /// The type name on the wire when `msg_type` is `MessageType::Unknown`.
+    /// Filled in when a header is decoded, and written back in place of
+    /// `msg_type` when the header is serialized again.
+    #[serde(skip)]
+    pub unknown_type: Option<String>,
 }
 
 impl MessageHeader {
-    /// Create a new message header with the given message type
+    /// Create a new message header with the given message type.
+    ///
+    /// The header expires `DEFAULT_EXPIRATION_SECS` after its timestamp; use
+    /// `with_expiration_secs` or `with_deadline` to override it.
     pub fn new(msg_type: MessageType) -> Self {
+        Self::new_with_clock(msg_type, &SystemClock)
+    }
+
+    /// Create a new message header timestamped by `clock` instead of the
+    /// system clock
+    pub fn new_with_clock(msg_type: MessageType, clock: &dyn Clock) -> Self {
+        let timestamp = clock.now_millis();
         Self {
             msg_type,
             msg_id: Uuid::new_v4().into_bytes(),
             correlation_id: None,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
-            expires_at: 0, // No expiration by default
+            timestamp,
+            expires_at: timestamp + DEFAULT_EXPIRATION_SECS * 1000,
+            compressed: false,
+            version: SCHEMA_VERSION,
+            signature: None,
+            account: None,
+            priority: msg_type.default_priority(),
+            encoding: BodyEncoding::Named,
+            unknown_type: None,
+        }
+    }
+    
+    /// Create a header with a fixed `msg_id`, `timestamp` and `expires_at`
+    /// instead of a random id and the current time.
+    ///
+    /// Messages framed with it are byte-for-byte reproducible, for tests that
+    /// compare against a known encoding.
+    pub fn with_fixed(
+        msg_type: MessageType,
+        msg_id: [u8; 16],
+        timestamp: u64,
+        expires_at: u64,
+    ) -> Self {
+        Self {
+            msg_id,
+            timestamp,
+            expires_at,
+            ..Self::new(msg_type)
         }
     }
     
@@ -42,22 +124,149 @@ impl MessageHeader {
         self
     }
     
+    /// Act as the configured account named `account`
+    pub fn with_account(mut self, account: &str) -> Self {
+        self.account = Some(account.to_string());
+        self
+    }
+    
+    /// Set the priority, overriding the message type's default
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+    
     /// Set an expiration time in seconds from now
-    pub fn with_expiration_secs(mut self, seconds: u64) -> Self {
-        self.expires_at = self.timestamp + (seconds * 1000);
+    pub fn with_expiration_secs(self, seconds: u64) -> Self {
+        self.with_expiration_ms(seconds * 1000)
+    }
+    
+    /// Set an expiration time in milliseconds from now, for sub-second expiry
+    pub fn with_expiration_ms(mut self, ms: u64) -> Self {
+        self.expires_at = self.timestamp + ms;
+        self
+    }
+    
+    /// Expire at an absolute time in milliseconds since epoch; `0` never expires
+    pub fn with_deadline(mut self, epoch_ms: u64) -> Self {
+        self.expires_at = epoch_ms;
         self
     }
     
+    /// Create the header for a reply to this message.
+    ///
+    /// The reply carries this message's correlation id, or its `msg_id` if it has none.
+    pub fn reply(&self, msg_type: MessageType) -> MessageHeader {
+        MessageHeader::new(msg_type).with_correlation_id(self.correlation_id.unwrap_or(self.msg_id))
+    }
+    
     /// Check if the message has expired
     pub fn is_expired(&self) -> bool {
+        self.is_expired_with(&SystemClock)
+    }
+
+    /// Check if the message has expired by the time `clock` tells
+    pub fn is_expired_with(&self, clock: &dyn Clock) -> bool {
         if self.expires_at == 0 {
             return false; // No expiration
         }
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-        now > self.expires_at
+        clock.now_millis() > self.expires_at
+    }
+
+    /// Sign the header and the given body bytes with a shared secret
+    pub fn sign(&mut self, secret: &[u8], body: &[u8]) {
+        let mac = self.mac(secret, body);
+        self.signature = Some(mac.finalize().into_bytes().into());
+    }
+
+    /// Verify the signature against the given body bytes in constant time
+    pub fn verify(&self, secret: &[u8], body: &[u8]) -> Result<(), MessageError> {
+        let signature = self.signature.ok_or(MessageError::InvalidSignature)?;
+        self.mac(secret, body)
+            .verify_slice(&signature)
+            .map_err(|_| MessageError::InvalidSignature)
+    }
+
+    fn mac(&self, secret: &[u8], body: &[u8]) -> HmacSha256 {
+        // HMAC accepts keys of any length, so this cannot fail
+        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+        mac.update(&self.msg_id);
+        mac.update(&self.timestamp.to_be_bytes());
+        mac.update(&[self.msg_type as u8]);
+        // Only covered when set, so signatures from before accounts existed still verify
+        if let Some(account) = &self.account {
+            mac.update(&(account.len() as u32).to_be_bytes());
+            mac.update(account.as_bytes());
+        }
+        // Likewise only covered when it differs from the pre-priority default
+        if self.priority != PRIORITY_NORMAL {
+            mac.update(&[self.priority]);
+        }
+        // And the expiry and correlation id, so a message cannot be kept alive
+        // or passed off as the reply to another request
+        if self.expires_at != 0 {
+            mac.update(&self.expires_at.to_be_bytes());
+        }
+        if let Some(correlation_id) = &self.correlation_id {
+            mac.update(correlation_id);
+        }
+        mac.update(body);
+        mac
+    }
+
+    /// The name of the message type: `msg_type`'s, or for an `Unknown` type
+    /// the name it was sent with
+    pub fn type_name(&self) -> String {
+        match (self.msg_type, &self.unknown_type) {
+            (MessageType::Unknown, Some(name)) => name.clone(),
+            (msg_type, _) => msg_type.to_string(),
+        }
+    }
+
+    /// Deserialize a msgpack header, keeping the wire name of a type this
+    /// build does not know in `unknown_type`
+    pub(crate) fn from_msgpack_slice(bytes: &[u8]) -> Result<Self, MessageError> {
+        /// Just the type of a header, as written
+        #[derive(Deserialize)]
+        struct WireType {
+            msg_type: String,
+        }
+
+        let mut header: MessageHeader = rmp_serde::from_slice(bytes)?;
+        if header.msg_type == MessageType::Unknown {
+            let wire: WireType = rmp_serde::from_slice(bytes)?;
+            header.unknown_type = Some(wire.msg_type);
+        }
+        Ok(header)
+    }
+}
+
+/// Field for field as `derive(Serialize)` would write it, except that a header
+/// read with an unknown type writes the name it arrived with, so relabelled or
+/// forwarded messages keep their type
+impl Serialize for MessageHeader {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let named = self.encoding.is_named();
+        let mut header = serializer.serialize_struct("MessageHeader", 11 - usize::from(named))?;
+        match (self.msg_type, &self.unknown_type) {
+            (MessageType::Unknown, Some(name)) => header.serialize_field("msg_type", name)?,
+            (msg_type, _) => header.serialize_field("msg_type", &msg_type)?,
+        }
+        header.serialize_field("msg_id", &self.msg_id)?;
+        header.serialize_field("correlation_id", &self.correlation_id)?;
+        header.serialize_field("timestamp", &self.timestamp)?;
+        header.serialize_field("expires_at", &self.expires_at)?;
+        header.serialize_field("compressed", &self.compressed)?;
+        header.serialize_field("version", &self.version)?;
+        header.serialize_field("signature", &self.signature)?;
+        header.serialize_field("account", &self.account)?;
+        header.serialize_field("priority", &self.priority)?;
+        if named {
+            header.skip_field("encoding")?;
+        } else {
+            header.serialize_field("encoding", &self.encoding)?;
+        }
+        header.end()
     }
 }
 
@@ -69,6 +278,13 @@ impl Default for MessageHeader {
             correlation_id: None,
             timestamp: 0,
             expires_at: 0,
+            compressed: false,
+            version: SCHEMA_VERSION,
+            signature: None,
+            account: None,
+            priority: PRIORITY_NORMAL,
+            encoding: BodyEncoding::Named,
+            unknown_type: None,
         }
     }
 }
@@ -0,0 +1,141 @@
+//! JSON encoding of messages, for publishers that cannot easily write msgpack
+
+use serde::{Deserialize, Serialize};
+
+use super::codec::BodyEncoding;
+use super::header::MessageHeader;
+use super::types::{
+    initial_schema_version, normal_priority, Message, MessageError, MessageType, SCHEMA_VERSION,
+};
+
+/// Text form of `MessageHeader` used by JSON messages.
+///
+/// `msg_type` is the `Display` name of the type (e.g. `"Order"`) and ids are
+/// 32-character hex strings. JSON bodies are never compressed or signed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonHeader {
+    /// Message type name
+    pub msg_type: String,
+
+    /// Unique message ID as hex
+    pub msg_id: String,
+
+    /// Optional correlation ID as hex
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+
+    /// Timestamp in milliseconds since epoch
+    pub timestamp: u64,
+
+    /// Expiration timestamp in milliseconds since epoch (0 for no expiration)
+    #[serde(default)]
+    pub expires_at: u64,
+
+    /// Schema version of the message body
+    #[serde(default = "initial_schema_version")]
+    pub version: u16,
+
+    /// Name of the configured account to act as (optional)
+    #[serde(default)]
+    pub account: Option<String>,
+
+    /// How urgently to handle the message
+    #[serde(default = "normal_priority")]
+    pub priority: u8,
+}
+
+impl From<&MessageHeader> for JsonHeader {
+    fn from(header: &MessageHeader) -> Self {
+        Self {
+            msg_type: header.type_name(),
+            msg_id: hex::encode(header.msg_id),
+            correlation_id: header.correlation_id.map(hex::encode),
+            timestamp: header.timestamp,
+            expires_at: header.expires_at,
+            version: header.version,
+            account: header.account.clone(),
+            priority: header.priority,
+        }
+    }
+}
+
+impl TryFrom<JsonHeader> for MessageHeader {
+    type Error = MessageError;
+
+    fn try_from(header: JsonHeader) -> Result<Self, Self::Error> {
+        Ok(Self {
+            msg_type: header.msg_type.parse::<MessageType>()?,
+            msg_id: parse_id(&header.msg_id)?,
+            correlation_id: header.correlation_id.as_deref().map(parse_id).transpose()?,
+            timestamp: header.timestamp,
+            expires_at: header.expires_at,
+            compressed: false,
+            version: header.version,
+            signature: None,
+            account: header.account,
+            priority: header.priority,
+            encoding: BodyEncoding::Named,
+            unknown_type: None,
+        })
+    }
+}
+
+fn parse_id(id: &str) -> Result<[u8; 16], MessageError> {
+    hex::decode(id)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| MessageError::InvalidFormat(format!("Invalid message id: {}", id)))
+}
+
+#[derive(Serialize)]
+struct JsonFrame<'a, M> {
+    header: JsonHeader,
+    body: &'a M,
+}
+
+#[derive(Deserialize)]
+struct RawJsonFrame {
+    header: JsonHeader,
+    body: serde_json::Value,
+}
+
+/// Serialize `msg` as a `{"header": .., "body": ..}` JSON object
+pub(crate) fn encode_json<M: Message>(
+    msg: &M,
+    header: &MessageHeader,
+) -> Result<String, MessageError> {
+    let frame = JsonFrame {
+        header: JsonHeader::from(header),
+        body: msg,
+    };
+    Ok(serde_json::to_string(&frame)?)
+}
+
+/// Validate the header of a JSON message and deserialize its body
+pub(crate) fn decode_json<M: Message>(data: &[u8]) -> Result<M, MessageError> {
+    let max_size = M::max_payload_size();
+    if data.len() > max_size {
+        return Err(MessageError::InvalidFormat(format!(
+            "Message of {} bytes exceeds the {} byte limit",
+            data.len(),
+            max_size
+        )));
+    }
+
+    let frame: RawJsonFrame = serde_json::from_slice(data)?;
+    let header = MessageHeader::try_from(frame.header)?;
+    M::validate(&header)?;
+
+    if header.version > SCHEMA_VERSION {
+        return Err(MessageError::UnsupportedVersion {
+            got: header.version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    // Older bodies go through the same msgpack migration as framed messages
+    if header.version < SCHEMA_VERSION {
+        return M::migrate(&header, &rmp_serde::to_vec_named(&frame.body)?);
+    }
+    Ok(serde_json::from_value(frame.body)?)
+}
@@ -3,70 +3,66 @@
 //! This module contains all message types that map to the public API of `ExchangeClient`.
 
 mod types;
+mod clock;
+mod codec;
+mod convert;
 mod header;
 mod order;
 mod transfer;
 mod account;
+mod response;
+mod query;
+mod event;
+mod any;
+#[cfg(feature = "json")]
+mod json;
 
 pub use types::*;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use codec::{BodyEncoding, Codec, CompactMsgpackCodec, MsgpackCodec};
 pub use header::MessageHeader;
 pub use order::*;
 pub use transfer::*;
 pub use account::*;
+pub use response::*;
+pub use query::*;
+pub use event::*;
+pub use any::AnyMessage;
+#[cfg(feature = "json")]
+pub use json::JsonHeader;
 
-use serde::{Deserialize, Serialize};
 
-
-/// Trait for all exchange messages that can be serialized/deserialized
-pub trait ExchangeMessage: Serialize + for<'de> Deserialize<'de> + std::fmt::Debug + Send + Sync + 'static {
+/// Exchange messages handled by the NATS service.
+///
+/// Serialization and header validation come from the `Message` supertrait, so
+/// every exchange message shares the same wire contract.
+pub trait ExchangeMessage: Message {
     /// Returns the message type as a string (for backward compatibility)
+    #[deprecated(note = "use `message_name`, which follows `MessageType`'s `Display`")]
     fn message_type_str(&self) -> &'static str;
-    
-    /// Get the message type
-    fn message_type() -> MessageType
-    where
-        Self: Sized;
-    
-    /// Serialize the message to a byte vector with header
-    fn to_msgpack(&self) -> Result<Vec<u8>, MessageError> {
-        // Create the message header
-        let header = MessageHeader::new(Self::message_type());
-        
-        // Serialize the header and body
-        let header_bytes = rmp_serde::to_vec_named(&header)?;
-        let body_bytes = rmp_serde::to_vec_named(self)?;
-        
-        // Combine header length (4 bytes), header, and body
-        let mut result = Vec::with_capacity(4 + header_bytes.len() + body_bytes.len());
-        result.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
-        result.extend(header_bytes);
-        result.extend(body_bytes);
-        
-        Ok(result)
+
+    /// The message's type name, as `MessageType` displays it (e.g. `"Order"`)
+    fn message_name(&self) -> String {
+        Self::message_type().to_string()
     }
-    
-    /// Deserialize a message from a byte slice
-    fn from_msgpack(data: &[u8]) -> Result<Self, MessageError>
-    where
-        Self: Sized,
-    {
-        if data.len() < 4 {
-            return Err(MessageError::InvalidFormat("Message too short".to_string()));
-        }
-        
-        // Read header length (first 4 bytes, big endian)
-        let header_len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
-        
-        if data.len() < 4 + header_len {
-            return Err(MessageError::InvalidFormat("Incomplete header".to_string()));
-        }
-        
-        // Deserialize the message body
-        rmp_serde::from_slice(&data[4 + header_len..])
-            .map_err(MessageError::Decode)
+
+    /// The asset the message trades or cancels, if it names a single one
+    fn asset(&self) -> Option<&str> {
+        None
+    }
+
+    /// The subject this message belongs on under `root`, from its category and
+    /// asset (see `message_subject`)
+    fn subject_under(&self, root: &str) -> String {
+        message_subject(root, Self::message_type(), self.asset())
     }
-}
 
+    /// The subject this message belongs on under `DEFAULT_SUBJECT_ROOT`, e.g.
+    /// `hyperliquid.orders.btc`
+    fn default_subject(&self) -> String {
+        self.subject_under(DEFAULT_SUBJECT_ROOT)
+    }
+}
 
 // Implement Message trait for all ExchangeMessage types
 macro_rules! impl_message {
@@ -75,13 +71,6 @@ macro_rules! impl_message {
             fn message_type() -> crate::messages::types::MessageType {
                 $msg_type
             }
-            
-            fn from_msgpack(data: &[u8]) -> Result<Self, crate::messages::types::MessageError>
-            where
-                Self: Sized,
-            {
-                <Self as crate::messages::types::Message>::default_from_msgpack(data)
-            }
         }
     };
 }
@@ -89,17 +78,45 @@ macro_rules! impl_message {
 // Implement Message for order messages
 impl_message!(OrderRequest, crate::messages::types::MessageType::Order);
 impl_message!(CancelOrderRequest, crate::messages::types::MessageType::CancelOrder);
+impl_message!(CancelAllRequest, crate::messages::types::MessageType::CancelAll);
+impl_message!(ScheduleCancelRequest, crate::messages::types::MessageType::ScheduleCancel);
+impl_message!(BulkCancelRequest, crate::messages::types::MessageType::BulkCancel);
+impl_message!(ClosePositionRequest, crate::messages::types::MessageType::ClosePosition);
 impl_message!(ModifyOrderRequest, crate::messages::types::MessageType::ModifyOrder);
 impl_message!(UpdateLeverageRequest, crate::messages::types::MessageType::UpdateLeverage);
+impl_message!(BatchUpdateLeverageRequest, crate::messages::types::MessageType::BatchUpdateLeverage);
+impl_message!(BatchOrderRequest, MessageType::BatchOrder);
+impl_message!(FlattenRequest, MessageType::Flatten);
 
 // Implement Message for transfer messages
 impl_message!(TransferRequest, crate::messages::types::MessageType::Transfer);
 
 impl_message!(WithdrawRequest, MessageType::Withdraw);
 impl_message!(ClassTransferRequest, MessageType::ClassTransfer);
+impl_message!(VaultTransferRequest, MessageType::VaultTransfer);
+impl_message!(SpotTransferRequest, MessageType::SpotTransfer);
 
 // Implement Message for account messages
 impl_message!(UpdateIsolatedMarginRequest, MessageType::UpdateIsolatedMargin);
 impl_message!(ApproveAgentRequest, MessageType::ApproveAgent);
 impl_message!(SetReferrerRequest, MessageType::SetReferrer);
 impl_message!(ApproveBuilderFeeRequest, MessageType::ApproveBuilderFee);
+impl_message!(HeartbeatRequest, MessageType::Heartbeat);
+
+// Implement Message for response messages
+impl_message!(AckResponse, MessageType::Ack);
+impl_message!(PositionQueryResponse, MessageType::PositionQueryResponse);
+impl_message!(BulkCancelResponse, MessageType::BulkCancelResponse);
+impl_message!(OrderResponse, MessageType::OrderResponse);
+impl_message!(ApproveAgentResponse, MessageType::ApproveAgentResponse);
+impl_message!(BatchOrderResponse, MessageType::BatchOrderResponse);
+impl_message!(FlattenResponse, MessageType::FlattenResponse);
+impl_message!(BatchResponse, MessageType::BatchResponse);
+impl_message!(ResolveCloidResponse, MessageType::ResolveCloidResponse);
+
+// Implement Message for query messages
+impl_message!(PositionQueryRequest, MessageType::PositionQuery);
+impl_message!(ResolveCloidRequest, MessageType::ResolveCloid);
+
+// Implement Message for event messages
+impl_message!(StatusEvent, MessageType::StatusEvent);
@@ -1,10 +1,16 @@
 //! Order-related message types
 
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
+use crate::helpers::{
+    float_to_string_for_hashing, round_to_decimals, round_to_significant_and_decimal,
+};
 use crate::messages::ExchangeMessage;
 
-use super::MessageType;
+use super::clock::{Clock, SystemClock};
+use super::types::asset_subject;
+use super::MessageError;
 
 /// Request to place a new order
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,16 +25,212 @@ pub struct OrderRequest {
     pub size: String,
 
     /// Limit price (required for limit orders)
+    #[serde(default)]
     pub limit_price: Option<String>,
 
     /// Client order ID (optional)
+    #[serde(default)]
     pub cloid: Option<String>,
 
     /// Whether this is a reduce-only order
+    #[serde(default)]
     pub reduce_only: bool,
 
-    /// Time in force (e.g., "Gtc", "Ioc", "Fok")
-    pub time_in_force: String,
+    /// Time in force for limit orders
+    pub time_in_force: TimeInForce,
+
+    /// Trigger parameters for take-profit/stop-loss orders (optional)
+    #[serde(default)]
+    pub trigger: Option<TriggerSpec>,
+
+    /// Whether `asset` is a perpetual or a spot market (defaults to perp)
+    #[serde(default)]
+    pub market: MarketKind,
+
+    /// Builder address to route the order through (optional)
+    #[serde(default)]
+    pub builder: Option<String>,
+
+    /// Builder fee in tenths of a basis point, required when `builder` is set
+    #[serde(default)]
+    pub builder_fee_tenths_bps: Option<u32>,
+
+    /// Maximum slippage of a market order from the mid price as a fraction,
+    /// e.g. `0.01` for 1% (optional; the exchange client's default of 5% if unset)
+    #[serde(default)]
+    pub slippage: Option<f64>,
+
+    /// Sub-account or vault to place the order for (optional; the service's
+    /// own account if unset)
+    #[serde(default)]
+    pub vault_address: Option<String>,
+
+    /// Time in ms since the Unix epoch at which a resting limit order is
+    /// cancelled if it has not filled (optional; it rests until cancelled if
+    /// unset)
+    #[serde(default)]
+    pub expires_at_ms: Option<u64>,
+}
+
+/// How long a limit order rests on the book
+///
+/// Serialized as the exchange's strings: `"Gtc"`, `"Ioc"`, `"Fok"` and `"Alo"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Good til cancelled
+    Gtc,
+    /// Immediate or cancel
+    Ioc,
+    /// Fill or kill
+    Fok,
+    /// Add liquidity only (post-only)
+    Alo,
+}
+
+impl std::fmt::Display for TimeInForce {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeInForce::Gtc => write!(f, "Gtc"),
+            TimeInForce::Ioc => write!(f, "Ioc"),
+            TimeInForce::Fok => write!(f, "Fok"),
+            TimeInForce::Alo => write!(f, "Alo"),
+        }
+    }
+}
+
+impl std::str::FromStr for TimeInForce {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Gtc" => Ok(TimeInForce::Gtc),
+            "Ioc" => Ok(TimeInForce::Ioc),
+            "Fok" => Ok(TimeInForce::Fok),
+            "Alo" => Ok(TimeInForce::Alo),
+            _ => Err(MessageError::Validation(format!("Unknown time in force: {:?}", s))),
+        }
+    }
+}
+
+/// Kind of market an order is placed on
+///
+/// Perp assets are coin names such as `"BTC"`. Spot assets are either a pair
+/// name such as `"PURR/USDC"` or an `"@<index>"` spot asset name such as `"@107"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarketKind {
+    /// Perpetual futures market
+    #[default]
+    Perp,
+    /// Spot market
+    Spot,
+}
+
+impl MarketKind {
+    /// Check that `asset` is named the way this kind of market expects
+    pub fn validate_asset(&self, asset: &str) -> Result<(), MessageError> {
+        let is_spot_name = asset.contains('/') || asset.starts_with('@');
+        match self {
+            MarketKind::Perp if is_spot_name => Err(MessageError::Validation(format!(
+                "{} is a spot asset name, but the order is for a perp market",
+                asset
+            ))),
+            MarketKind::Spot if !is_spot_name => Err(MessageError::Validation(format!(
+                "Spot asset must be a pair like \"PURR/USDC\" or \"@<index>\", got {:?}",
+                asset
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How finely an asset's orders may be sized and priced, from exchange metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssetPrecision {
+    /// Decimal places allowed in a size
+    pub sz_decimals: u32,
+
+    /// Decimal places allowed in a price. Prices other than whole numbers are
+    /// also limited to five significant figures.
+    pub price_decimals: u32,
+}
+
+impl AssetPrecision {
+    /// The precision of a perp whose sizes have `sz_decimals` decimal places
+    pub fn perp(sz_decimals: u32) -> Self {
+        Self {
+            sz_decimals,
+            price_decimals: 6u32.saturating_sub(sz_decimals),
+        }
+    }
+
+    /// The precision of a spot pair whose base token has `sz_decimals`
+    /// decimal places
+    pub fn spot(sz_decimals: u32) -> Self {
+        Self {
+            sz_decimals,
+            price_decimals: 8u32.saturating_sub(sz_decimals),
+        }
+    }
+
+    fn round_size(&self, size: f64) -> f64 {
+        round_to_decimals(size, self.sz_decimals)
+    }
+
+    fn round_price(&self, px: f64) -> f64 {
+        if px.fract() == 0.0 {
+            px
+        } else {
+            round_to_significant_and_decimal(px, 5, self.price_decimals)
+        }
+    }
+}
+
+/// Parse the decimal string `value` of the field `name` and round it with `round`
+fn round_field(
+    name: &str,
+    value: &str,
+    round: impl Fn(f64) -> f64,
+) -> Result<String, MessageError> {
+    let parsed = value
+        .parse::<f64>()
+        .map_err(|_| MessageError::Validation(format!("Invalid {}: {}", name, value)))?;
+    Ok(float_to_string_for_hashing(round(parsed)))
+}
+
+/// Trigger parameters for a take-profit or stop-loss order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerSpec {
+    /// Price at which the order triggers
+    pub trigger_px: String,
+
+    /// Whether the order executes as a market order once triggered
+    pub is_market: bool,
+
+    /// Either "tp" (take profit) or "sl" (stop loss)
+    pub tpsl: String,
+}
+
+impl TriggerSpec {
+    /// Create a new trigger specification
+    pub fn new(trigger_px: &str, is_market: bool, tpsl: &str) -> Self {
+        Self {
+            trigger_px: trigger_px.to_string(),
+            is_market,
+            tpsl: tpsl.to_string(),
+        }
+    }
+
+    /// Check that `tpsl` is "tp" or "sl" and `trigger_px` is a number
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let mut problems = Vec::new();
+        if self.tpsl != "tp" && self.tpsl != "sl" {
+            problems.push(format!("tpsl must be \"tp\" or \"sl\", got {:?}", self.tpsl));
+        }
+        if self.trigger_px.parse::<f64>().is_err() {
+            problems.push(format!("Invalid trigger price: {}", self.trigger_px));
+        }
+        MessageError::from_problems(problems)
+    }
 }
 
 impl OrderRequest {
@@ -41,10 +243,38 @@ impl OrderRequest {
             limit_price: None,
             cloid: None,
             reduce_only: false,
-            time_in_force: "Ioc".to_string(),
+            time_in_force: TimeInForce::Ioc,
+            trigger: None,
+            market: MarketKind::Perp,
+            builder: None,
+            builder_fee_tenths_bps: None,
+            slippage: None,
+            vault_address: None,
+            expires_at_ms: None,
         }
     }
 
+    /// Create a market order request filling at most `slippage` (as a fraction)
+    /// away from the mid price
+    pub fn market_with_slippage(asset: &str, is_buy: bool, size: &str, slippage: f64) -> Self {
+        Self {
+            slippage: Some(slippage),
+            ..Self::market(asset, is_buy, size)
+        }
+    }
+
+    /// Whether this is a market order, i.e. it has no limit price or trigger
+    pub fn is_market(&self) -> bool {
+        self.limit_price.is_none() && self.trigger.is_none()
+    }
+
+    /// The per-asset subject to publish this order on, `{base}.{asset}` with
+    /// the asset lowercased (e.g. `hyperliquid.orders.btc`). With the base
+    /// `hyperliquid.orders` this is the order's `default_subject`.
+    pub fn subject(&self, base: &str) -> String {
+        asset_subject(base, &self.asset)
+    }
+
     /// Create a new limit order request
     pub fn limit(asset: &str, is_buy: bool, size: &str, price: &str) -> Self {
         Self {
@@ -54,7 +284,14 @@ impl OrderRequest {
             limit_price: Some(price.to_string()),
             cloid: None,
             reduce_only: false,
-            time_in_force: "Gtc".to_string(),
+            time_in_force: TimeInForce::Gtc,
+            trigger: None,
+            market: MarketKind::Perp,
+            builder: None,
+            builder_fee_tenths_bps: None,
+            slippage: None,
+            vault_address: None,
+            expires_at_ms: None,
         }
     }
 
@@ -71,10 +308,195 @@ impl OrderRequest {
     }
 
     /// Set time in force
-    pub fn with_time_in_force(mut self, tif: &str) -> Self {
-        self.time_in_force = tif.to_string();
+    pub fn with_time_in_force(mut self, tif: TimeInForce) -> Self {
+        self.time_in_force = tif;
+        self
+    }
+
+    /// Make this a take-profit/stop-loss trigger order
+    pub fn with_trigger(mut self, trigger: TriggerSpec) -> Self {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    /// Set the kind of market the order is placed on
+    pub fn with_market(mut self, market: MarketKind) -> Self {
+        self.market = market;
+        self
+    }
+
+    /// Route the order through `builder`, paying it `fee_tenths_bps`
+    pub fn with_builder(mut self, builder: &str, fee_tenths_bps: u32) -> Self {
+        self.builder = Some(builder.to_string());
+        self.builder_fee_tenths_bps = Some(fee_tenths_bps);
+        self
+    }
+
+    /// Place the order for the sub-account or vault at `vault_address`
+    pub fn with_vault_address(mut self, vault_address: &str) -> Self {
+        self.vault_address = Some(vault_address.to_string());
+        self
+    }
+
+    /// Cancel the order at `expires_at_ms` (ms since the Unix epoch) if it is
+    /// still resting, making it good till that date
+    pub fn with_expiry(mut self, expires_at_ms: u64) -> Self {
+        self.expires_at_ms = Some(expires_at_ms);
         self
     }
+
+    /// Round the size, limit price and trigger price to `precision`, failing
+    /// if one doesn't parse or the size rounds to zero
+    pub fn normalize(&mut self, precision: AssetPrecision) -> Result<(), MessageError> {
+        let size = round_field("size", &self.size, |sz| precision.round_size(sz))?;
+        if size == "0" {
+            return Err(MessageError::Validation(format!(
+                "Size {} rounds to zero at {} decimal places",
+                self.size, precision.sz_decimals
+            )));
+        }
+        self.size = size;
+        if let Some(px) = &mut self.limit_price {
+            *px = round_field("limit price", px, |px| precision.round_price(px))?;
+        }
+        if let Some(trigger) = &mut self.trigger {
+            trigger.trigger_px =
+                round_field("trigger price", &trigger.trigger_px, |px| precision.round_price(px))?;
+        }
+        Ok(())
+    }
+
+    /// Check that the size, limit price and trigger price are already at
+    /// `precision`, reporting each one that `normalize` would change
+    pub fn check_precision(&self, precision: AssetPrecision) -> Result<(), MessageError> {
+        let mut normalized = self.clone();
+        normalized.normalize(precision)?;
+        let mut problems = Vec::new();
+        let mut compare = |name: &str, value: &str, rounded: &str| {
+            if value.parse::<f64>().ok() != rounded.parse::<f64>().ok() {
+                problems.push(format!(
+                    "{} {} is finer than {} allows, nearest is {}",
+                    name, value, self.asset, rounded
+                ));
+            }
+        };
+        compare("Size", &self.size, &normalized.size);
+        if let (Some(px), Some(rounded)) = (&self.limit_price, &normalized.limit_price) {
+            compare("Limit price", px, rounded);
+        }
+        if let (Some(trigger), Some(rounded)) = (&self.trigger, &normalized.trigger) {
+            compare("Trigger price", &trigger.trigger_px, &rounded.trigger_px);
+        }
+        MessageError::from_problems(problems)
+    }
+
+    /// Check every field of the order, reporting all problems at once.
+    ///
+    /// This covers the asset name for the market kind, reduce-only spot orders,
+    /// the size, limit price and cloid formats, the trigger, the builder, the
+    /// slippage, the vault address and the expiry.
+    pub fn validate(&self) -> Result<(), MessageError> {
+        self.validate_with(&SystemClock)
+    }
+
+    /// Like `validate`, but judge the expiry by `clock`
+    pub fn validate_with(&self, clock: &dyn Clock) -> Result<(), MessageError> {
+        let mut problems = Vec::new();
+        let mut check = |result: Result<(), MessageError>| {
+            if let Err(e) = result {
+                problems.extend(e.problems());
+            }
+        };
+        check(self.market.validate_asset(&self.asset));
+        check(self.trigger.as_ref().map_or(Ok(()), TriggerSpec::validate));
+        check(self.validate_builder());
+        check(self.validate_slippage());
+        check(validate_vault_address(self.vault_address.as_deref()));
+        check(self.validate_expiry_with(clock));
+
+        if self.market == MarketKind::Spot && self.reduce_only {
+            problems.push("Spot orders cannot be reduce-only".to_string());
+        }
+        if !matches!(self.size.parse::<f64>(), Ok(size) if size > 0.0) {
+            problems.push(format!("Size must be a positive number, got {:?}", self.size));
+        }
+        if let Some(price) = &self.limit_price {
+            if price.parse::<f64>().is_err() {
+                problems.push(format!("Invalid limit price: {}", price));
+            }
+        }
+        if let Some(cloid) = &self.cloid {
+            if Uuid::parse_str(cloid).is_err() {
+                problems.push(format!("Invalid cloid: {}", cloid));
+            }
+        }
+        MessageError::from_problems(problems)
+    }
+
+    /// Check that slippage, if set, is a fraction between 0 and 1 on a market order
+    pub fn validate_slippage(&self) -> Result<(), MessageError> {
+        if self.slippage.is_some() && !self.is_market() {
+            return Err(MessageError::Validation(
+                "slippage only applies to market orders".to_string(),
+            ));
+        }
+        check_slippage(self.slippage)
+    }
+
+    /// Check that an expiry, if set, is in the future and on a limit order
+    /// that rests on the book (`Gtc` or `Alo`)
+    pub fn validate_expiry(&self) -> Result<(), MessageError> {
+        self.validate_expiry_with(&SystemClock)
+    }
+
+    /// Like `validate_expiry`, but judge whether the expiry has passed by `clock`
+    pub fn validate_expiry_with(&self, clock: &dyn Clock) -> Result<(), MessageError> {
+        let Some(expires_at_ms) = self.expires_at_ms else {
+            return Ok(());
+        };
+        let mut problems = Vec::new();
+        let rests = matches!(self.time_in_force, TimeInForce::Gtc | TimeInForce::Alo);
+        if self.limit_price.is_none() || self.trigger.is_some() || !rests {
+            problems.push("expires_at_ms only applies to Gtc or Alo limit orders".to_string());
+        }
+        if expires_at_ms <= clock.now_millis() {
+            problems.push(format!("expires_at_ms {} is in the past", expires_at_ms));
+        }
+        MessageError::from_problems(problems)
+    }
+
+    /// Check that a builder, if set, is a valid address and has a fee
+    pub fn validate_builder(&self) -> Result<(), MessageError> {
+        let Some(builder) = &self.builder else {
+            if self.builder_fee_tenths_bps.is_some() {
+                return Err(MessageError::Validation(
+                    "builder_fee_tenths_bps is set without a builder".to_string(),
+                ));
+            }
+            return Ok(());
+        };
+        let mut problems = Vec::new();
+        let digits = builder.strip_prefix("0x").unwrap_or(builder);
+        if !matches!(hex::decode(digits), Ok(bytes) if bytes.len() == 20) {
+            problems.push(format!("Invalid builder address: {}", builder));
+        }
+        if self.builder_fee_tenths_bps.is_none() {
+            problems.push("builder_fee_tenths_bps is required with a builder".to_string());
+        }
+        MessageError::from_problems(problems)
+    }
+}
+
+/// Check that a vault address, if set, is a 20-byte hex address
+fn validate_vault_address(vault_address: Option<&str>) -> Result<(), MessageError> {
+    let Some(address) = vault_address else {
+        return Ok(());
+    };
+    let digits = address.strip_prefix("0x").unwrap_or(address);
+    if !matches!(hex::decode(digits), Ok(bytes) if bytes.len() == 20) {
+        return Err(MessageError::Validation(format!("Invalid vault address: {}", address)));
+    }
+    Ok(())
 }
 
 impl ExchangeMessage for OrderRequest {
@@ -86,8 +508,167 @@ impl ExchangeMessage for OrderRequest {
         }
     }
 
-    fn message_type() -> MessageType {
-        MessageType::Order
+    fn asset(&self) -> Option<&str> {
+        Some(&self.asset)
+    }
+}
+
+/// How the orders of a `BatchOrderRequest` relate to each other on the exchange
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderGrouping {
+    /// Independent orders
+    #[default]
+    Na,
+    /// An entry order followed by its take-profit and/or stop-loss, placed as
+    /// one group whose triggers only become active once the entry fills
+    NormalTpsl,
+    /// Take-profit and/or stop-loss orders on the existing position
+    PositionTpsl,
+}
+
+impl OrderGrouping {
+    /// The exchange's name for this grouping
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderGrouping::Na => "na",
+            OrderGrouping::NormalTpsl => "normalTpsl",
+            OrderGrouping::PositionTpsl => "positionTpsl",
+        }
+    }
+}
+
+/// Request to place several limit or trigger orders in one exchange action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchOrderRequest {
+    /// The orders to place, in order
+    pub orders: Vec<OrderRequest>,
+
+    /// How the orders are grouped (defaults to independent orders)
+    #[serde(default)]
+    pub grouping: OrderGrouping,
+}
+
+impl BatchOrderRequest {
+    /// Create a request placing independent `orders`
+    pub fn new(orders: Vec<OrderRequest>) -> Self {
+        Self {
+            orders,
+            grouping: OrderGrouping::Na,
+        }
+    }
+
+    /// Create a bracket: an `entry` order placed with its take-profit and/or
+    /// stop-loss `exits` as a `normalTpsl` group
+    pub fn bracket(entry: OrderRequest, exits: Vec<OrderRequest>) -> Self {
+        Self {
+            orders: std::iter::once(entry).chain(exits).collect(),
+            grouping: OrderGrouping::NormalTpsl,
+        }
+    }
+
+    /// Set how the orders are grouped
+    pub fn with_grouping(mut self, grouping: OrderGrouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    /// Check every order and the makeup of the group, reporting all problems at once.
+    ///
+    /// Every order needs a limit price or a trigger, as market orders and
+    /// builders and expiring orders are not supported in a batch, and every
+    /// order must be for the
+    /// same vault address, if any. A `normalTpsl` group is one entry
+    /// without a trigger followed by at most one "tp" and one "sl" trigger
+    /// order that reduce the entry's position: same asset, opposite side.
+    /// A `positionTpsl` group is only reduce-only trigger orders.
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let mut problems = Vec::new();
+        if self.orders.is_empty() {
+            problems.push("Batch order needs at least one order".to_string());
+        }
+        for (i, order) in self.orders.iter().enumerate() {
+            if let Err(e) = order.validate() {
+                problems.extend(e.problems().into_iter().map(|p| format!("order {}: {}", i, p)));
+            }
+            if order.limit_price.is_none() && order.trigger.is_none() {
+                problems.push(format!("order {}: needs a limit price or trigger", i));
+            }
+            if order.builder.is_some() {
+                problems.push(format!("order {}: builders are not supported in a batch", i));
+            }
+            if order.expires_at_ms.is_some() {
+                problems.push(format!("order {}: expiry is not supported in a batch", i));
+            }
+        }
+        if let Some((first, rest)) = self.orders.split_first() {
+            if rest.iter().any(|order| order.vault_address != first.vault_address) {
+                problems.push("Every order in a batch must be for the same vault".to_string());
+            }
+        }
+        match self.grouping {
+            OrderGrouping::Na => {}
+            OrderGrouping::NormalTpsl => self.check_normal_tpsl(&mut problems),
+            OrderGrouping::PositionTpsl => {
+                for (i, order) in self.orders.iter().enumerate() {
+                    if order.trigger.is_none() || !order.reduce_only {
+                        problems.push(format!(
+                            "order {}: positionTpsl orders must be reduce-only triggers",
+                            i
+                        ));
+                    }
+                }
+            }
+        }
+        MessageError::from_problems(problems)
+    }
+
+    fn check_normal_tpsl(&self, problems: &mut Vec<String>) {
+        let Some((entry, exits)) = self.orders.split_first() else {
+            return;
+        };
+        if entry.trigger.is_some() {
+            problems.push("normalTpsl entry (order 0) must not be a trigger order".to_string());
+        }
+        if exits.is_empty() || exits.len() > 2 {
+            problems.push(format!(
+                "normalTpsl needs one or two trigger orders after the entry, got {}",
+                exits.len()
+            ));
+        }
+        let mut seen = Vec::new();
+        for (i, exit) in exits.iter().enumerate().map(|(i, exit)| (i + 1, exit)) {
+            let Some(trigger) = &exit.trigger else {
+                problems.push(format!("order {}: normalTpsl exits must be trigger orders", i));
+                continue;
+            };
+            if seen.contains(&&trigger.tpsl) {
+                problems.push(format!("order {}: more than one {:?} exit", i, trigger.tpsl));
+            }
+            seen.push(&trigger.tpsl);
+            if exit.asset != entry.asset {
+                problems.push(format!("order {}: exit asset must match entry {}", i, entry.asset));
+            }
+            if exit.is_buy == entry.is_buy {
+                problems.push(format!("order {}: exit must be on the opposite side", i));
+            }
+            if !exit.reduce_only {
+                problems.push(format!("order {}: exit must be reduce-only", i));
+            }
+        }
+    }
+}
+
+impl ExchangeMessage for BatchOrderRequest {
+    fn message_type_str(&self) -> &'static str {
+        "batch_order"
+    }
+
+    fn asset(&self) -> Option<&str> {
+        let asset = &self.orders.first()?.asset;
+        self.orders
+            .iter()
+            .all(|order| &order.asset == asset)
+            .then_some(asset.as_str())
     }
 }
 
@@ -98,10 +679,17 @@ pub struct CancelOrderRequest {
     pub asset: String,
 
     /// The order ID to cancel (either this or cloid must be provided)
+    #[serde(default)]
     pub order_id: Option<u64>,
 
     /// The client order ID to cancel (either this or order_id must be provided)
+    #[serde(default)]
     pub cloid: Option<String>,
+
+    /// Sub-account or vault the order was placed for (optional; the service's
+    /// own account if unset)
+    #[serde(default)]
+    pub vault_address: Option<String>,
 }
 
 impl CancelOrderRequest {
@@ -111,6 +699,7 @@ impl CancelOrderRequest {
             asset: asset.to_string(),
             order_id: Some(order_id),
             cloid: None,
+            vault_address: None,
         }
     }
 
@@ -120,8 +709,20 @@ impl CancelOrderRequest {
             asset: asset.to_string(),
             order_id: None,
             cloid: Some(cloid.to_string()),
+            vault_address: None,
         }
     }
+
+    /// Cancel an order placed for the sub-account or vault at `vault_address`
+    pub fn with_vault_address(mut self, vault_address: &str) -> Self {
+        self.vault_address = Some(vault_address.to_string());
+        self
+    }
+
+    /// Check that the vault address, if set, is a valid address
+    pub fn validate(&self) -> Result<(), MessageError> {
+        validate_vault_address(self.vault_address.as_deref())
+    }
 }
 
 impl ExchangeMessage for CancelOrderRequest {
@@ -129,8 +730,240 @@ impl ExchangeMessage for CancelOrderRequest {
         "cancel_order"
     }
 
-    fn message_type() -> MessageType {
-        MessageType::CancelOrder
+    fn asset(&self) -> Option<&str> {
+        Some(&self.asset)
+    }
+}
+
+/// Request to cancel every open order, optionally restricted to one asset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelAllRequest {
+    /// The asset to cancel orders for (all assets if `None`)
+    #[serde(default)]
+    pub asset: Option<String>,
+}
+
+impl CancelAllRequest {
+    /// Create a request cancelling all open orders for one asset
+    pub fn for_asset(asset: &str) -> Self {
+        Self {
+            asset: Some(asset.to_string()),
+        }
+    }
+
+    /// Create a request cancelling all open orders across every asset
+    pub fn all() -> Self {
+        Self { asset: None }
+    }
+}
+
+impl ExchangeMessage for CancelAllRequest {
+    fn message_type_str(&self) -> &'static str {
+        "cancel_all"
+    }
+
+    fn asset(&self) -> Option<&str> {
+        self.asset.as_deref()
+    }
+}
+
+/// Check that `slippage`, if set, is a fraction between 0 and 1
+fn check_slippage(slippage: Option<f64>) -> Result<(), MessageError> {
+    match slippage {
+        Some(slippage) if !(0.0..1.0).contains(&slippage) => Err(MessageError::Validation(
+            format!("Slippage must be a fraction between 0 and 1, got {}", slippage),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Request to close the whole open position in one asset with a reduce-only
+/// market order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClosePositionRequest {
+    /// The asset whose position to close
+    pub asset: String,
+
+    /// Maximum slippage from the mid price as a fraction, e.g. `0.01` for 1%
+    /// (optional; the exchange client's default of 5% if unset)
+    #[serde(default)]
+    pub slippage: Option<f64>,
+}
+
+impl ClosePositionRequest {
+    /// Create a request closing the position in `asset` at the default slippage
+    pub fn new(asset: &str) -> Self {
+        Self {
+            asset: asset.to_string(),
+            slippage: None,
+        }
+    }
+
+    /// Set the maximum slippage as a fraction of the mid price
+    pub fn with_slippage(mut self, slippage: f64) -> Self {
+        self.slippage = Some(slippage);
+        self
+    }
+
+    /// Check the slippage, if set, is a fraction between 0 and 1
+    pub fn validate(&self) -> Result<(), MessageError> {
+        check_slippage(self.slippage)
+    }
+}
+
+impl ExchangeMessage for ClosePositionRequest {
+    fn message_type_str(&self) -> &'static str {
+        "close_position"
+    }
+
+    fn asset(&self) -> Option<&str> {
+        Some(&self.asset)
+    }
+}
+
+/// Request to flatten one asset in a single message: cancel its resting
+/// orders, then close its position with a reduce-only market order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlattenRequest {
+    /// The asset to flatten
+    pub asset: String,
+
+    /// Whether to cancel the asset's resting orders before closing
+    #[serde(default)]
+    pub cancel_resting: bool,
+
+    /// Maximum slippage of the closing order from the mid price as a fraction,
+    /// e.g. `0.01` for 1% (optional; the exchange client's default of 5% if unset)
+    #[serde(default)]
+    pub slippage: Option<f64>,
+}
+
+impl FlattenRequest {
+    /// Create a request cancelling the resting orders in `asset` and closing
+    /// its position at the default slippage
+    pub fn new(asset: &str) -> Self {
+        Self {
+            asset: asset.to_string(),
+            cancel_resting: true,
+            slippage: None,
+        }
+    }
+
+    /// Set whether resting orders are cancelled before closing
+    pub fn with_cancel_resting(mut self, cancel_resting: bool) -> Self {
+        self.cancel_resting = cancel_resting;
+        self
+    }
+
+    /// Set the maximum slippage as a fraction of the mid price
+    pub fn with_slippage(mut self, slippage: f64) -> Self {
+        self.slippage = Some(slippage);
+        self
+    }
+
+    /// Check the slippage, if set, is a fraction between 0 and 1
+    pub fn validate(&self) -> Result<(), MessageError> {
+        check_slippage(self.slippage)
+    }
+}
+
+impl ExchangeMessage for FlattenRequest {
+    fn message_type_str(&self) -> &'static str {
+        "flatten"
+    }
+
+    fn asset(&self) -> Option<&str> {
+        Some(&self.asset)
+    }
+}
+
+/// Request to cancel several orders on one asset at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkCancelRequest {
+    /// The asset of the orders to cancel
+    pub asset: String,
+
+    /// Order IDs to cancel
+    #[serde(default)]
+    pub order_ids: Vec<u64>,
+
+    /// Client order IDs to cancel
+    #[serde(default)]
+    pub cloids: Vec<String>,
+}
+
+impl BulkCancelRequest {
+    /// Create a request cancelling orders by order ID
+    pub fn by_order_ids(asset: &str, order_ids: Vec<u64>) -> Self {
+        Self {
+            asset: asset.to_string(),
+            order_ids,
+            cloids: Vec::new(),
+        }
+    }
+
+    /// Create a request cancelling orders by client order ID
+    pub fn by_cloids(asset: &str, cloids: Vec<String>) -> Self {
+        Self {
+            asset: asset.to_string(),
+            order_ids: Vec::new(),
+            cloids,
+        }
+    }
+
+    /// Check that there is something to cancel and every cloid is a UUID
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let mut problems = Vec::new();
+        if self.order_ids.is_empty() && self.cloids.is_empty() {
+            problems.push("Bulk cancel needs at least one order_id or cloid".to_string());
+        }
+        for cloid in &self.cloids {
+            if Uuid::parse_str(cloid).is_err() {
+                problems.push(format!("Invalid cloid: {}", cloid));
+            }
+        }
+        MessageError::from_problems(problems)
+    }
+}
+
+impl ExchangeMessage for BulkCancelRequest {
+    fn message_type_str(&self) -> &'static str {
+        "bulk_cancel"
+    }
+
+    fn asset(&self) -> Option<&str> {
+        Some(&self.asset)
+    }
+}
+
+/// Request to schedule (or clear) a cancel of all open orders
+///
+/// This is a dead man's switch: unless it is pushed back by another request,
+/// every open order is cancelled at `time_ms`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleCancelRequest {
+    /// Time in milliseconds since epoch to cancel at (`None` clears the schedule)
+    #[serde(default)]
+    pub time_ms: Option<u64>,
+}
+
+impl ScheduleCancelRequest {
+    /// Create a request cancelling all open orders at `time_ms`
+    pub fn at(time_ms: u64) -> Self {
+        Self {
+            time_ms: Some(time_ms),
+        }
+    }
+
+    /// Create a request clearing any scheduled cancel
+    pub fn clear() -> Self {
+        Self { time_ms: None }
+    }
+}
+
+impl ExchangeMessage for ScheduleCancelRequest {
+    fn message_type_str(&self) -> &'static str {
+        "schedule_cancel"
     }
 }
 
@@ -138,15 +971,19 @@ impl ExchangeMessage for CancelOrderRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModifyOrderRequest {
     /// The order ID to modify (either this or cloid must be provided)
+    #[serde(default)]
     pub order_id: Option<u64>,
 
     /// The client order ID to modify (either this or order_id must be provided)
+    #[serde(default)]
     pub cloid: Option<String>,
 
     /// New size for the order (optional)
+    #[serde(default)]
     pub new_size: Option<String>,
 
     /// New price for the order (optional)
+    #[serde(default)]
     pub new_price: Option<String>,
 }
 
@@ -172,26 +1009,34 @@ impl ModifyOrderRequest {
     }
 
     /// Set the new size
-    pub(crate) fn with_size(mut self, size: &str) -> Self {
+    pub fn with_size(mut self, size: &str) -> Self {
         self.new_size = Some(size.to_string());
         self
     }
 
     /// Set the new price
-    pub(crate) fn with_price(mut self, price: &str) -> Self {
+    pub fn with_price(mut self, price: &str) -> Self {
         self.new_price = Some(price.to_string());
         self
     }
+
+    /// Check that the request identifies an order and changes its size or price
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let mut problems = Vec::new();
+        if self.order_id.is_none() && self.cloid.is_none() {
+            problems.push("Modify request needs an order_id or cloid".to_string());
+        }
+        if self.new_size.is_none() && self.new_price.is_none() {
+            problems.push("Modify request must set a new size or price".to_string());
+        }
+        MessageError::from_problems(problems)
+    }
 }
 
 impl ExchangeMessage for ModifyOrderRequest {
     fn message_type_str(&self) -> &'static str {
         "modify_order"
     }
-
-    fn message_type() -> MessageType {
-        MessageType::ModifyOrder
-    }
 }
 
 /// Request to update leverage
@@ -223,7 +1068,27 @@ impl ExchangeMessage for UpdateLeverageRequest {
         "update_leverage"
     }
 
-    fn message_type() -> MessageType {
-        MessageType::UpdateLeverage
+    fn asset(&self) -> Option<&str> {
+        Some(&self.asset)
+    }
+}
+
+/// Request to update leverage for several assets at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchUpdateLeverageRequest {
+    /// The per-asset leverage updates, applied in order
+    pub updates: Vec<UpdateLeverageRequest>,
+}
+
+impl BatchUpdateLeverageRequest {
+    /// Create a new batch update leverage request
+    pub fn new(updates: Vec<UpdateLeverageRequest>) -> Self {
+        Self { updates }
+    }
+}
+
+impl ExchangeMessage for BatchUpdateLeverageRequest {
+    fn message_type_str(&self) -> &'static str {
+        "batch_update_leverage"
     }
 }
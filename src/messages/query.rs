@@ -0,0 +1,65 @@
+//! Query message types, answered with a response message instead of an ack
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::messages::{ExchangeMessage, MessageError};
+
+/// Request for the open positions of an account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionQueryRequest {
+    /// Address to query; defaults to the service's own account
+    #[serde(default)]
+    pub address: Option<String>,
+}
+
+impl PositionQueryRequest {
+    /// Query the service's own account
+    pub fn own() -> Self {
+        Self { address: None }
+    }
+
+    /// Query the account at `address`
+    pub fn for_address(address: &str) -> Self {
+        Self {
+            address: Some(address.to_string()),
+        }
+    }
+}
+
+impl ExchangeMessage for PositionQueryRequest {
+    fn message_type_str(&self) -> &'static str {
+        "position_query"
+    }
+}
+
+/// Request for the exchange order ID of an order placed through the service
+/// with a client order ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveCloidRequest {
+    /// The order's client order ID
+    pub cloid: String,
+}
+
+impl ResolveCloidRequest {
+    /// Resolve `cloid`
+    pub fn new(cloid: &str) -> Self {
+        Self {
+            cloid: cloid.to_string(),
+        }
+    }
+
+    /// Check that the cloid is a UUID
+    pub fn validate(&self) -> Result<(), MessageError> {
+        match Uuid::parse_str(&self.cloid) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(MessageError::Validation(format!("Invalid cloid: {}", self.cloid))),
+        }
+    }
+}
+
+impl ExchangeMessage for ResolveCloidRequest {
+    fn message_type_str(&self) -> &'static str {
+        "resolve_cloid"
+    }
+}
@@ -0,0 +1,408 @@
+//! Response message types sent back to publishers
+
+use serde::{Deserialize, Serialize};
+
+use crate::messages::{ExchangeMessage, Message, MessageError, MessageHeader, MessageType};
+
+/// Generic reply reporting whether a request was executed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AckResponse {
+    /// Whether the request was handled successfully
+    pub ok: bool,
+
+    /// Error description when `ok` is false, or a note on a successful request (optional)
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl AckResponse {
+    /// Create a successful acknowledgement
+    pub fn success() -> Self {
+        Self {
+            ok: true,
+            message: None,
+        }
+    }
+
+    /// Create a successful acknowledgement carrying a note, e.g. that there was nothing to do
+    pub fn success_with_message(message: impl ToString) -> Self {
+        Self {
+            ok: true,
+            message: Some(message.to_string()),
+        }
+    }
+
+    /// Create a failed acknowledgement with a description of the error
+    pub fn failure(message: impl ToString) -> Self {
+        Self {
+            ok: false,
+            message: Some(message.to_string()),
+        }
+    }
+
+    /// Serialize this response as a reply to the request with header `request`.
+    ///
+    /// The reply header is built with `MessageHeader::reply`, so it echoes the
+    /// request's correlation id.
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::Ack))
+    }
+}
+
+impl ExchangeMessage for AckResponse {
+    fn message_type_str(&self) -> &'static str {
+        "ack"
+    }
+}
+
+/// Reply to a `PositionQueryRequest` listing the account's open positions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionQueryResponse {
+    /// Open positions, one per asset
+    #[serde(default)]
+    pub positions: Vec<PositionSummary>,
+}
+
+/// An open perp position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionSummary {
+    /// The asset held (e.g., "BTC")
+    pub asset: String,
+
+    /// Signed position size; negative for shorts
+    pub szi: String,
+
+    /// Average entry price (optional)
+    #[serde(default)]
+    pub entry_px: Option<String>,
+
+    /// Unrealized profit and loss in USD
+    pub unrealized_pnl: String,
+}
+
+impl PositionQueryResponse {
+    /// Create a response listing `positions`
+    pub fn new(positions: Vec<PositionSummary>) -> Self {
+        Self { positions }
+    }
+
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::PositionQueryResponse))
+    }
+}
+
+impl ExchangeMessage for PositionQueryResponse {
+    fn message_type_str(&self) -> &'static str {
+        "position_query_response"
+    }
+}
+
+/// Reply to a `BulkCancelRequest` reporting the outcome for each id
+///
+/// Ids are order IDs in decimal or client order IDs, as given in the request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BulkCancelResponse {
+    /// Ids whose orders were cancelled
+    #[serde(default)]
+    pub cancelled: Vec<String>,
+
+    /// Ids with no open order to cancel (never placed, already cancelled or filled)
+    #[serde(default)]
+    pub not_found: Vec<String>,
+}
+
+impl BulkCancelResponse {
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::BulkCancelResponse))
+    }
+}
+
+impl ExchangeMessage for BulkCancelResponse {
+    fn message_type_str(&self) -> &'static str {
+        "bulk_cancel_response"
+    }
+}
+
+/// Reply to an `ApproveAgentRequest` naming the agent that was approved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApproveAgentResponse {
+    /// Address of the approved agent
+    pub agent_address: String,
+
+    /// Name the agent was approved under (optional)
+    #[serde(default)]
+    pub agent_name: Option<String>,
+}
+
+impl ApproveAgentResponse {
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::ApproveAgentResponse))
+    }
+}
+
+impl ExchangeMessage for ApproveAgentResponse {
+    fn message_type_str(&self) -> &'static str {
+        "approve_agent_response"
+    }
+}
+
+/// What the exchange did with an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    /// Filled immediately, fully or in part
+    Filled,
+    /// Resting on the book
+    Resting,
+    /// Accepted and waiting to fill
+    WaitingForFill,
+    /// A trigger order waiting for its trigger price
+    WaitingForTrigger,
+}
+
+/// Reply to an `OrderRequest` describing how the order was executed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderResponse {
+    /// What the exchange did with the order
+    pub status: OrderStatus,
+
+    /// Exchange order ID (optional)
+    #[serde(default)]
+    pub oid: Option<u64>,
+
+    /// Size filled immediately (optional)
+    #[serde(default)]
+    pub filled_size: Option<String>,
+
+    /// Average price of the immediate fill (optional)
+    #[serde(default)]
+    pub avg_price: Option<String>,
+
+    /// Size left unfilled and cancelled when an immediate-or-cancel or market
+    /// order only partially filled (optional)
+    #[serde(default)]
+    pub remaining_size: Option<String>,
+
+    /// Client order ID the order was placed with, including one assigned by the
+    /// service (optional)
+    #[serde(default)]
+    pub cloid: Option<String>,
+}
+
+impl OrderResponse {
+    /// Create a response with `status` and no fill details
+    pub fn new(status: OrderStatus) -> Self {
+        Self {
+            status,
+            oid: None,
+            filled_size: None,
+            avg_price: None,
+            remaining_size: None,
+            cloid: None,
+        }
+    }
+
+    /// Create a response for an order that filled `size` at an average of `avg_price`
+    pub fn filled(oid: u64, size: &str, avg_price: &str) -> Self {
+        Self {
+            oid: Some(oid),
+            filled_size: Some(size.to_string()),
+            avg_price: Some(avg_price.to_string()),
+            ..Self::new(OrderStatus::Filled)
+        }
+    }
+
+    /// Create a response for an order resting on the book
+    pub fn resting(oid: u64) -> Self {
+        Self {
+            oid: Some(oid),
+            ..Self::new(OrderStatus::Resting)
+        }
+    }
+
+    /// Record the size left unfilled by a partial fill
+    pub fn with_remaining_size(mut self, size: &str) -> Self {
+        self.remaining_size = Some(size.to_string());
+        self
+    }
+
+    /// Record the client order ID the order was placed with
+    pub fn with_cloid(mut self, cloid: &str) -> Self {
+        self.cloid = Some(cloid.to_string());
+        self
+    }
+
+    /// Whether the order filled only in part
+    pub fn is_partial_fill(&self) -> bool {
+        self.remaining_size.is_some()
+    }
+
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::OrderResponse))
+    }
+}
+
+impl ExchangeMessage for OrderResponse {
+    fn message_type_str(&self) -> &'static str {
+        "order_response"
+    }
+}
+
+/// Reply to a `BatchOrderRequest` with the outcome of each order, in request order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchOrderResponse {
+    /// One response per order in the request
+    #[serde(default)]
+    pub orders: Vec<OrderResponse>,
+}
+
+impl BatchOrderResponse {
+    /// Create a response reporting `orders`
+    pub fn new(orders: Vec<OrderResponse>) -> Self {
+        Self { orders }
+    }
+
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::BatchOrderResponse))
+    }
+}
+
+impl ExchangeMessage for BatchOrderResponse {
+    fn message_type_str(&self) -> &'static str {
+        "batch_order_response"
+    }
+}
+
+/// The outcome of one item of a batch, e.g. one order of a `BatchOrderRequest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemResult {
+    /// Position of the item in the request
+    pub index: usize,
+
+    /// Whether the item succeeded
+    pub ok: bool,
+
+    /// The exchange's order ID for the item, if it has one
+    #[serde(default)]
+    pub order_id: Option<u64>,
+
+    /// Why the item failed
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl ItemResult {
+    /// The item at `index` succeeded, with `order_id` if it has one
+    pub fn success(index: usize, order_id: Option<u64>) -> Self {
+        Self {
+            index,
+            ok: true,
+            order_id,
+            error: None,
+        }
+    }
+
+    /// The item at `index` failed with `error`
+    pub fn failure(index: usize, error: &str) -> Self {
+        Self {
+            index,
+            ok: false,
+            order_id: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Per-item outcomes of a batch of which some items failed, in request order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchResponse {
+    /// One result per item in the request
+    #[serde(default)]
+    pub results: Vec<ItemResult>,
+}
+
+impl BatchResponse {
+    /// Create a response reporting `results`
+    pub fn new(results: Vec<ItemResult>) -> Self {
+        Self { results }
+    }
+
+    /// Whether every item succeeded
+    pub fn all_ok(&self) -> bool {
+        self.results.iter().all(|result| result.ok)
+    }
+
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::BatchResponse))
+    }
+}
+
+impl ExchangeMessage for BatchResponse {
+    fn message_type_str(&self) -> &'static str {
+        "batch_response"
+    }
+}
+
+/// Reply to a `FlattenRequest`: how many resting orders were cancelled and
+/// the order that closed the position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlattenResponse {
+    /// Number of resting orders cancelled
+    #[serde(default)]
+    pub cancelled: usize,
+
+    /// The closing order, or `None` if there was no position to close or the
+    /// service is in dry-run mode
+    #[serde(default)]
+    pub close: Option<OrderResponse>,
+}
+
+impl FlattenResponse {
+    /// Create a response reporting `cancelled` orders and the `close`
+    pub fn new(cancelled: usize, close: Option<OrderResponse>) -> Self {
+        Self { cancelled, close }
+    }
+
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::FlattenResponse))
+    }
+}
+
+impl ExchangeMessage for FlattenResponse {
+    fn message_type_str(&self) -> &'static str {
+        "flatten_response"
+    }
+}
+
+/// Reply to a `ResolveCloidRequest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolveCloidResponse {
+    /// The order's exchange order ID, or `None` if the service does not know
+    /// the cloid or the order has since filled or been cancelled
+    #[serde(default)]
+    pub order_id: Option<u64>,
+}
+
+impl ResolveCloidResponse {
+    /// Create a response giving `order_id`
+    pub fn new(order_id: Option<u64>) -> Self {
+        Self { order_id }
+    }
+
+    /// Serialize this response as a reply to the request with header `request`
+    pub fn to_reply(&self, request: &MessageHeader) -> Result<Vec<u8>, MessageError> {
+        self.to_msgpack_with_header(request.reply(MessageType::ResolveCloidResponse))
+    }
+}
+
+impl ExchangeMessage for ResolveCloidResponse {
+    fn message_type_str(&self) -> &'static str {
+        "resolve_cloid_response"
+    }
+}
@@ -2,9 +2,7 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::messages::ExchangeMessage;
-
-use super::MessageType;
+use crate::messages::{ExchangeMessage, MessageError};
 
 /// Request to transfer funds
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,16 +26,40 @@ impl TransferRequest {
             destination: destination.to_string(),
         }
     }
+
+    /// Create a request sending `amount` USDC to `destination`, which must be a
+    /// 0x-prefixed 20-byte hex address
+    pub fn usdc(amount: &str, destination: &str) -> Result<Self, MessageError> {
+        let req = Self::new("USDC", amount, destination);
+        req.validate()?;
+        Ok(req)
+    }
+
+    /// Create a request sending `amount` of the spot token `asset` to
+    /// `destination`, which must be a 0x-prefixed 20-byte hex address
+    pub fn spot(amount: &str, destination: &str, asset: &str) -> Result<Self, MessageError> {
+        let req = Self::new(asset, amount, destination);
+        req.validate()?;
+        Ok(req)
+    }
+
+    /// Check that `destination` is a 0x-prefixed 20-byte hex address
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let digits = self.destination.strip_prefix("0x");
+        match digits.map(hex::decode) {
+            Some(Ok(bytes)) if bytes.len() == 20 => Ok(()),
+            _ => Err(MessageError::Validation(format!(
+                "Invalid destination address: {}",
+                self.destination
+            ))),
+        }
+    }
 }
 
 impl ExchangeMessage for TransferRequest {
     fn message_type_str(&self) -> &'static str {
         "transfer"
     }
-
-    fn message_type() -> MessageType {
-        MessageType::Transfer
-    }
 }
 
 /// Request to withdraw from the bridge
@@ -68,10 +90,6 @@ impl ExchangeMessage for WithdrawRequest {
     fn message_type_str(&self) -> &'static str {
         "withdraw"
     }
-
-    fn message_type() -> MessageType {
-        MessageType::Withdraw
-    }
 }
 
 /// Request to transfer between spot and perp accounts
@@ -95,11 +113,8 @@ impl ExchangeMessage for ClassTransferRequest {
     fn message_type_str(&self) -> &'static str {
         "class_transfer"
     }
-
-    fn message_type() -> MessageType {
-        MessageType::ClassTransfer
-    }
 }
+
 /// Request to transfer funds between vault and exchange
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VaultTransferRequest {
@@ -108,17 +123,55 @@ pub struct VaultTransferRequest {
     /// Amount in USD (integer, no decimals)
     pub usd: u64,
     /// Optional vault address in hex format
+    #[serde(default)]
     pub vault_address: Option<String>,
 }
 
+impl VaultTransferRequest {
+    /// Create a request depositing `usd` into the vault
+    pub fn deposit(usd: u64) -> Self {
+        Self {
+            is_deposit: true,
+            usd,
+            vault_address: None,
+        }
+    }
+
+    /// Create a request withdrawing `usd` from the vault
+    pub fn withdraw(usd: u64) -> Self {
+        Self {
+            is_deposit: false,
+            usd,
+            vault_address: None,
+        }
+    }
+
+    /// Set the vault address
+    pub fn with_vault_address(mut self, vault_address: &str) -> Self {
+        self.vault_address = Some(vault_address.to_string());
+        self
+    }
+
+    /// Check that `vault_address`, if set, is a 20-byte hex address
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let Some(address) = &self.vault_address else {
+            return Ok(());
+        };
+        let digits = address.strip_prefix("0x").unwrap_or(address);
+        match hex::decode(digits) {
+            Ok(bytes) if bytes.len() == 20 => Ok(()),
+            _ => Err(MessageError::Validation(format!(
+                "Invalid vault address: {}",
+                address
+            ))),
+        }
+    }
+}
+
 impl ExchangeMessage for VaultTransferRequest {
     fn message_type_str(&self) -> &'static str {
         "vault_transfer"
     }
-
-    fn message_type() -> MessageType {
-        MessageType::ClassTransfer
-    }
 }
 
 /// Request to transfer spot tokens
@@ -132,11 +185,32 @@ pub struct SpotTransferRequest {
     pub token: String,
 }
 
+impl SpotTransferRequest {
+    /// Create a new spot transfer request
+    pub fn new(amount: &str, destination: &str, token: &str) -> Self {
+        Self {
+            amount: amount.to_string(),
+            destination: destination.to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// Check that `destination` is a 20-byte hex address and `token` is set
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let mut problems = Vec::new();
+        let digits = self.destination.strip_prefix("0x").unwrap_or(&self.destination);
+        if !matches!(hex::decode(digits), Ok(bytes) if bytes.len() == 20) {
+            problems.push(format!("Invalid destination address: {}", self.destination));
+        }
+        if self.token.is_empty() {
+            problems.push("token must be set".to_string());
+        }
+        MessageError::from_problems(problems)
+    }
+}
+
 impl ExchangeMessage for SpotTransferRequest {
     fn message_type_str(&self) -> &'static str {
         "spot_transfer"
     }
-    fn message_type() -> MessageType {
-        MessageType::Transfer
-    }
 }
@@ -1,31 +1,103 @@
 //! Message types and serialization utilities
 
-use chrono::Utc;
 use rmp_serde::{decode, encode};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
-use uuid::Uuid;
+
+use super::codec::{BodyEncoding, Codec, MsgpackCodec};
+use super::header::MessageHeader;
+
+/// Current message schema version written into every header
+pub const SCHEMA_VERSION: u16 = 1;
+
+/// Default message lifetime in seconds applied by `MessageHeader::new`
+pub const DEFAULT_EXPIRATION_SECS: u64 = 60;
+
+/// Largest framed message accepted by default, in bytes
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 1024 * 1024;
+
+/// Largest encoded header accepted, in bytes
+pub const MAX_HEADER_SIZE: usize = 4 * 1024;
+
+/// Schema version assumed for headers written before versioning existed
+pub(crate) fn initial_schema_version() -> u16 {
+    1
+}
+
+/// Priority of ordinary messages, and of headers written before priorities existed
+pub const PRIORITY_NORMAL: u8 = 128;
+
+/// Priority given by default to risk-reducing messages (`CancelAll`,
+/// `ScheduleCancel` and `Flatten`) and to `Heartbeat`s, so queued orders cannot
+/// delay them
+pub const PRIORITY_HIGH: u8 = 192;
+
+pub(crate) fn normal_priority() -> u8 {
+    PRIORITY_NORMAL
+}
 
 /// Message type identifiers
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub enum MessageType {
     // Order messages (0x0-0x0F)
     Order = 0x01,
     CancelOrder = 0x02,
     ModifyOrder = 0x03,
     UpdateLeverage = 0x04,
+    BatchUpdateLeverage = 0x05,
+    BatchOrder = 0x06,
+    CancelAll = 0x07,
+    ScheduleCancel = 0x08,
+    BulkCancel = 0x09,
+    ClosePosition = 0x0A,
+    Flatten = 0x0B,
     
     // Transfer messages (0x10-0x1F)
     Transfer = 0x10,
     Withdraw = 0x11,
     ClassTransfer = 0x12,
+    VaultTransfer = 0x13,
+    SpotTransfer = 0x14,
     
     // Account messages (0x20-0x2F)
     UpdateIsolatedMargin = 0x20,
     ApproveAgent = 0x21,
     SetReferrer = 0x22,
     ApproveBuilderFee = 0x23,
+    Heartbeat = 0x24,
+    
+    // Response messages (0x30-0x3F)
+    Ack = 0x30,
+    PositionQueryResponse = 0x31,
+    BulkCancelResponse = 0x32,
+    OrderResponse = 0x33,
+    ApproveAgentResponse = 0x34,
+    BatchOrderResponse = 0x35,
+    FlattenResponse = 0x36,
+    BatchResponse = 0x37,
+    ResolveCloidResponse = 0x38,
+    
+    // Query messages (0x40-0x4F)
+    PositionQuery = 0x40,
+    ResolveCloid = 0x41,
+
+    // Event messages (0x50-0x5F)
+    StatusEvent = 0x50,
+
+    /// A type this build does not know, e.g. one added by a newer producer.
+    /// Headers naming one still decode, keeping the name in
+    /// `MessageHeader::unknown_type`, so the message can be rejected by id.
+    Unknown = 0xFF,
+}
+
+/// Types are read by the name `Serialize` writes. A name this build does not
+/// know reads as `Unknown` rather than failing the whole header.
+impl<'de> Deserialize<'de> for MessageType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(MessageType::from_name(&name).unwrap_or(MessageType::Unknown))
+    }
 }
 
 impl TryFrom<u8> for MessageType {
@@ -37,13 +109,36 @@ impl TryFrom<u8> for MessageType {
             0x02 => Ok(MessageType::CancelOrder),
             0x03 => Ok(MessageType::ModifyOrder),
             0x04 => Ok(MessageType::UpdateLeverage),
+            0x05 => Ok(MessageType::BatchUpdateLeverage),
+            0x06 => Ok(MessageType::BatchOrder),
+            0x07 => Ok(MessageType::CancelAll),
+            0x08 => Ok(MessageType::ScheduleCancel),
+            0x09 => Ok(MessageType::BulkCancel),
+            0x0A => Ok(MessageType::ClosePosition),
+            0x0B => Ok(MessageType::Flatten),
             0x10 => Ok(MessageType::Transfer),
             0x11 => Ok(MessageType::Withdraw),
             0x12 => Ok(MessageType::ClassTransfer),
+            0x13 => Ok(MessageType::VaultTransfer),
+            0x14 => Ok(MessageType::SpotTransfer),
             0x20 => Ok(MessageType::UpdateIsolatedMargin),
             0x21 => Ok(MessageType::ApproveAgent),
             0x22 => Ok(MessageType::SetReferrer),
             0x23 => Ok(MessageType::ApproveBuilderFee),
+            0x24 => Ok(MessageType::Heartbeat),
+            0x30 => Ok(MessageType::Ack),
+            0x31 => Ok(MessageType::PositionQueryResponse),
+            0x32 => Ok(MessageType::BulkCancelResponse),
+            0x33 => Ok(MessageType::OrderResponse),
+            0x34 => Ok(MessageType::ApproveAgentResponse),
+            0x35 => Ok(MessageType::BatchOrderResponse),
+            0x36 => Ok(MessageType::FlattenResponse),
+            0x37 => Ok(MessageType::BatchResponse),
+            0x38 => Ok(MessageType::ResolveCloidResponse),
+            0x40 => Ok(MessageType::PositionQuery),
+            0x41 => Ok(MessageType::ResolveCloid),
+            0x50 => Ok(MessageType::StatusEvent),
+            0xFF => Ok(MessageType::Unknown),
             _ => Err(MessageError::InvalidMessageType(value)),
         }
     }
@@ -62,63 +157,158 @@ impl std::fmt::Display for MessageType {
             MessageType::CancelOrder => write!(f, "CancelOrder"),
             MessageType::ModifyOrder => write!(f, "ModifyOrder"),
             MessageType::UpdateLeverage => write!(f, "UpdateLeverage"),
+            MessageType::BatchUpdateLeverage => write!(f, "BatchUpdateLeverage"),
+            MessageType::BatchOrder => write!(f, "BatchOrder"),
+            MessageType::CancelAll => write!(f, "CancelAll"),
+            MessageType::ScheduleCancel => write!(f, "ScheduleCancel"),
+            MessageType::BulkCancel => write!(f, "BulkCancel"),
+            MessageType::ClosePosition => write!(f, "ClosePosition"),
+            MessageType::Flatten => write!(f, "Flatten"),
             MessageType::Transfer => write!(f, "Transfer"),
             MessageType::Withdraw => write!(f, "Withdraw"),
             MessageType::ClassTransfer => write!(f, "ClassTransfer"),
+            MessageType::VaultTransfer => write!(f, "VaultTransfer"),
+            MessageType::SpotTransfer => write!(f, "SpotTransfer"),
             MessageType::UpdateIsolatedMargin => write!(f, "UpdateIsolatedMargin"),
             MessageType::ApproveAgent => write!(f, "ApproveAgent"),
             MessageType::SetReferrer => write!(f, "SetReferrer"),
             MessageType::ApproveBuilderFee => write!(f, "ApproveBuilderFee"),
+            MessageType::Heartbeat => write!(f, "Heartbeat"),
+            MessageType::Ack => write!(f, "Ack"),
+            MessageType::PositionQueryResponse => write!(f, "PositionQueryResponse"),
+            MessageType::BulkCancelResponse => write!(f, "BulkCancelResponse"),
+            MessageType::OrderResponse => write!(f, "OrderResponse"),
+            MessageType::ApproveAgentResponse => write!(f, "ApproveAgentResponse"),
+            MessageType::BatchOrderResponse => write!(f, "BatchOrderResponse"),
+            MessageType::FlattenResponse => write!(f, "FlattenResponse"),
+            MessageType::BatchResponse => write!(f, "BatchResponse"),
+            MessageType::ResolveCloidResponse => write!(f, "ResolveCloidResponse"),
+            MessageType::PositionQuery => write!(f, "PositionQuery"),
+            MessageType::ResolveCloid => write!(f, "ResolveCloid"),
+            MessageType::StatusEvent => write!(f, "StatusEvent"),
+            MessageType::Unknown => write!(f, "Unknown"),
         }
     }
 }
 
-/// Message header that will be prepended to all messages
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct MessageHeader {
-    /// Type of the message
-    pub msg_type: MessageType,
-    /// Unique message ID (UUID v4 as bytes)
-    pub msg_id: [u8; 16],
-    /// Optional correlation ID for request/response matching
-    pub correlation_id: Option<[u8; 16]>,
-    /// Timestamp in milliseconds since epoch
-    pub timestamp: u64,
-    /// Expiration time in milliseconds since epoch
-    pub expires_at: u64,
-}
-
-impl MessageHeader {
-    /// Create a new message header
-    pub(crate) fn new(msg_type: MessageType) -> Self {
-        let uuid = Uuid::new_v4();
-        let now = Utc::now().timestamp_millis() as u64;
-        
-        Self {
-            msg_type,
-            msg_id: *uuid.as_bytes(),
-            correlation_id: None,
-            timestamp: now,
-            expires_at: now + 60_000, // 1 minute expiration by default
+impl MessageType {
+    /// Priority `MessageHeader::new` gives messages of this type: `PRIORITY_HIGH`
+    /// for `CancelAll`, `ScheduleCancel`, `Flatten` and `Heartbeat`, so they
+    /// overtake queued orders, and `PRIORITY_NORMAL` otherwise
+    pub fn default_priority(&self) -> u8 {
+        match self {
+            MessageType::CancelAll
+            | MessageType::ScheduleCancel
+            | MessageType::Flatten
+            | MessageType::Heartbeat => PRIORITY_HIGH,
+            _ => PRIORITY_NORMAL,
         }
     }
 
-    /// Set the correlation ID
-    pub(crate) fn with_correlation_id(mut self, correlation_id: [u8; 16]) -> Self {
-        self.correlation_id = Some(correlation_id);
-        self
+    /// Every known message type, in wire value order (`Unknown` is left out)
+    pub fn all() -> &'static [MessageType] {
+        &[
+            MessageType::Order,
+            MessageType::CancelOrder,
+            MessageType::ModifyOrder,
+            MessageType::UpdateLeverage,
+            MessageType::BatchUpdateLeverage,
+            MessageType::BatchOrder,
+            MessageType::CancelAll,
+            MessageType::ScheduleCancel,
+            MessageType::BulkCancel,
+            MessageType::ClosePosition,
+            MessageType::Flatten,
+            MessageType::Transfer,
+            MessageType::Withdraw,
+            MessageType::ClassTransfer,
+            MessageType::VaultTransfer,
+            MessageType::SpotTransfer,
+            MessageType::UpdateIsolatedMargin,
+            MessageType::ApproveAgent,
+            MessageType::SetReferrer,
+            MessageType::ApproveBuilderFee,
+            MessageType::Heartbeat,
+            MessageType::Ack,
+            MessageType::PositionQueryResponse,
+            MessageType::BulkCancelResponse,
+            MessageType::OrderResponse,
+            MessageType::ApproveAgentResponse,
+            MessageType::BatchOrderResponse,
+            MessageType::FlattenResponse,
+            MessageType::BatchResponse,
+            MessageType::ResolveCloidResponse,
+            MessageType::PositionQuery,
+            MessageType::ResolveCloid,
+            MessageType::StatusEvent,
+        ]
     }
-    
-    /// Set the expiration time in seconds from now
-    pub(crate) fn with_expiration_secs(mut self, secs: u64) -> Self {
-        self.expires_at = self.timestamp + (secs * 1000);
-        self
+
+    /// Parse a message type from its `Display` name, e.g. `"CancelOrder"`
+    pub fn from_name(name: &str) -> Option<MessageType> {
+        Self::all()
+            .iter()
+            .copied()
+            .find(|msg_type| msg_type.to_string() == name)
     }
-    
-    /// Check if the message has expired
-    pub(crate) fn is_expired(&self) -> bool {
-        let now = Utc::now().timestamp_millis() as u64;
-        self.expires_at > 0 && now > self.expires_at
+
+    /// The category of this type, from the range its wire value falls in
+    pub fn category(&self) -> MessageCategory {
+        if *self == MessageType::Unknown {
+            return MessageCategory::Unknown;
+        }
+        match *self as u8 {
+            0x00..=0x0F => MessageCategory::Order,
+            0x10..=0x1F => MessageCategory::Transfer,
+            0x20..=0x2F => MessageCategory::Account,
+            0x30..=0x3F => MessageCategory::Response,
+            0x40..=0x4F => MessageCategory::Query,
+            _ => MessageCategory::Event,
+        }
+    }
+}
+
+/// Group of related message types, one per range of `MessageType` wire values,
+/// for applying a policy to a whole group at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageCategory {
+    /// Placing, changing and cancelling orders (0x00-0x0F)
+    Order,
+    /// Moving funds (0x10-0x1F)
+    Transfer,
+    /// Account settings and approvals (0x20-0x2F)
+    Account,
+    /// Replies sent by the service (0x30-0x3F)
+    Response,
+    /// Read-only requests (0x40-0x4F)
+    Query,
+    /// Events published by the service (0x50-0x5F)
+    Event,
+    /// Types this build does not know (`MessageType::Unknown`)
+    Unknown,
+}
+
+impl MessageCategory {
+    /// Lowercase plural name of the category, as used in subjects (e.g. `"orders"`)
+    pub fn subject_name(&self) -> &'static str {
+        match self {
+            MessageCategory::Order => "orders",
+            MessageCategory::Transfer => "transfers",
+            MessageCategory::Account => "accounts",
+            MessageCategory::Response => "responses",
+            MessageCategory::Query => "queries",
+            MessageCategory::Event => "events",
+            MessageCategory::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::str::FromStr for MessageType {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MessageType::from_name(s)
+            .ok_or_else(|| MessageError::InvalidFormat(format!("Unknown message type: {}", s)))
     }
 }
 
@@ -127,6 +317,37 @@ pub trait Message: Serialize + for<'de> Deserialize<'de> + std::fmt::Debug + Sen
     /// Get the message type
     fn message_type() -> MessageType;
 
+    /// Body size in bytes above which the body is zstd-compressed.
+    ///
+    /// `None` (the default) never compresses, so small messages are unaffected.
+    fn compression_threshold() -> Option<usize> {
+        None
+    }
+
+    /// Largest framed message, in bytes, that `from_msgpack` will decode.
+    ///
+    /// Compressed bodies are held to the same limit once decompressed.
+    fn max_payload_size() -> usize {
+        DEFAULT_MAX_PAYLOAD_SIZE
+    }
+
+    /// Codec framing the header and body on the wire (default: `MsgpackCodec`)
+    fn codec() -> &'static dyn Codec {
+        &MsgpackCodec
+    }
+
+    /// Decode a body written with an older schema version.
+    ///
+    /// Called by `default_from_msgpack` when `header.version < SCHEMA_VERSION`;
+    /// override it to upgrade older payloads. The default decodes the body as is.
+    fn migrate(header: &MessageHeader, body: &[u8]) -> Result<Self, MessageError>
+    where
+        Self: Sized + serde::de::DeserializeOwned,
+    {
+        let _ = header;
+        Ok(rmp_serde::from_slice(body)?)
+    }
+
     /// Serialize the message to a byte vector
     fn to_msgpack(&self) -> Result<Vec<u8>, MessageError> {
         self.default_to_msgpack()
@@ -134,25 +355,49 @@ pub trait Message: Serialize + for<'de> Deserialize<'de> + std::fmt::Debug + Sen
     
     /// Default implementation of to_msgpack
     fn default_to_msgpack(&self) -> Result<Vec<u8>, MessageError> {
-        // Serialize the header
-        let header = MessageHeader::new(Self::message_type());
-        let header_bytes = rmp_serde::to_vec_named(&header)?;
-
-        // Serialize the message body
-        let body_bytes = rmp_serde::to_vec_named(self)?;
+        encode_message(self, MessageHeader::new(Self::message_type()), None)
+    }
+    
+    /// Serialize the message with a header expiring `expiry_secs` from now
+    fn to_msgpack_with_expiry(&self, expiry_secs: u64) -> Result<Vec<u8>, MessageError> {
+        let header = MessageHeader::new(Self::message_type()).with_expiration_secs(expiry_secs);
+        encode_message(self, header, None)
+    }
+    
+    /// Serialize the message behind a caller-built header.
+    ///
+    /// Lets callers set the correlation id, expiry or version themselves. The
+    /// header's `msg_type` must match `Self::message_type()`.
+    fn to_msgpack_with_header(&self, header: MessageHeader) -> Result<Vec<u8>, MessageError> {
+        let expected = Self::message_type();
+        if header.msg_type != expected {
+            return Err(MessageError::MismatchedType {
+                expected,
+                actual: header.msg_type,
+            });
+        }
+        encode_message(self, header, None)
+    }
+    
+    /// Serialize the message as JSON, with a text header, for publishers and
+    /// tools that cannot easily produce msgpack
+    #[cfg(feature = "json")]
+    fn to_json(&self) -> Result<String, MessageError> {
+        super::json::encode_json(self, &MessageHeader::new(Self::message_type()))
+    }
 
-        // Combine header and body
-        let mut result = Vec::with_capacity(header_bytes.len() + body_bytes.len() + 4);
-        
-        // Write header length (4 bytes, big endian)
-        let header_len = header_bytes.len() as u32;
-        result.extend(&header_len.to_be_bytes());
-        
-        // Write header and body
-        result.extend(header_bytes);
-        result.extend(body_bytes);
-        
-        Ok(result)
+    /// Deserialize a message written by `to_json`, validating its header
+    #[cfg(feature = "json")]
+    fn from_json(data: &[u8]) -> Result<Self, MessageError>
+    where
+        Self: Sized + serde::de::DeserializeOwned,
+    {
+        super::json::decode_json(data)
+    }
+    
+    /// Serialize the message with a header signed using `secret`
+    fn to_msgpack_signed(&self, secret: &[u8]) -> Result<Vec<u8>, MessageError> {
+        encode_message(self, MessageHeader::new(Self::message_type()), Some(secret))
     }
     
     /// Deserialize a message from a byte slice
@@ -168,36 +413,35 @@ pub trait Message: Serialize + for<'de> Deserialize<'de> + std::fmt::Debug + Sen
     where
         Self: Sized + serde::de::DeserializeOwned,
     {
-        if data.len() < 4 {
-            return Err(MessageError::InvalidFormat("Message too short".to_string()));
-        }
-        
-        // First 4 bytes are the header length
-        let header_len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
-        
-        if data.len() < 4 + header_len {
-            return Err(MessageError::InvalidFormat("Invalid header length".to_string()));
-        }
-        
-        // Deserialize header
-        let header: MessageHeader = rmp_serde::from_slice(&data[4..4 + header_len])?;
-        
-        // Validate header
-        let expected_type = Self::message_type();
-        if header.msg_type != expected_type {
-            return Err(MessageError::MismatchedType {
-                expected: expected_type,
-                actual: header.msg_type,
-            });
-        }
-        
-        if header.is_expired() {
-            return Err(MessageError::Expired);
-        }
+        decode_message(data, None, true).map(|(_, msg)| msg)
+    }
 
-        // Deserialize body
-        let msg = rmp_serde::from_slice(&data[4 + header_len..])?;
-        Ok(msg)
+    /// Like `from_msgpack`, but also return the validated header, e.g. to read
+    /// the message's `msg_id` or `correlation_id`
+    fn from_msgpack_with_header(data: &[u8]) -> Result<(MessageHeader, Self), MessageError>
+    where
+        Self: Sized + serde::de::DeserializeOwned,
+    {
+        decode_message(data, None, true)
+    }
+
+    /// Like `from_msgpack`, but accept a message whose `expires_at` has passed,
+    /// e.g. when replaying recorded messages
+    fn from_msgpack_ignoring_expiry(data: &[u8]) -> Result<Self, MessageError>
+    where
+        Self: Sized + serde::de::DeserializeOwned,
+    {
+        decode_message(data, None, false).map(|(_, msg)| msg)
+    }
+    
+    /// Deserialize a message, verifying its signature when a secret is given.
+    ///
+    /// With no secret, unsigned messages are accepted as before.
+    fn from_msgpack_verified(data: &[u8], secret: Option<&[u8]>) -> Result<Self, MessageError>
+    where
+        Self: Sized + serde::de::DeserializeOwned,
+    {
+        decode_message(data, secret, true).map(|(_, msg)| msg)
     }
 
     /// Validate the message header
@@ -218,12 +462,192 @@ pub trait Message: Serialize + for<'de> Deserialize<'de> + std::fmt::Debug + Sen
     }
 }
 
+/// Serialize `msg` behind `header`, compressing and signing the body as needed
+pub(crate) fn encode_message<M: Message>(
+    msg: &M,
+    mut header: MessageHeader,
+    secret: Option<&[u8]>,
+) -> Result<Vec<u8>, MessageError> {
+    // Serialize the message body as the codec asks, compressing it if it is
+    // large enough
+    header.encoding = M::codec().body_encoding();
+    let mut body_bytes = match header.encoding {
+        BodyEncoding::Named => rmp_serde::to_vec_named(msg)?,
+        BodyEncoding::Compact => rmp_serde::to_vec(msg)?,
+    };
+    if M::compression_threshold().is_some_and(|threshold| body_bytes.len() > threshold) {
+        body_bytes = compress_body(&body_bytes)?;
+        header.compressed = true;
+    }
+
+    // Sign the bytes as they go on the wire
+    if let Some(secret) = secret {
+        header.sign(secret, &body_bytes);
+    }
+
+    M::codec().encode(&header, &body_bytes)
+}
+
+/// Parse the header of a message of at most `max_size` bytes with `codec`,
+/// returning it with the raw body bytes.
+///
+/// The size is checked before anything is deserialized, so an oversized or
+/// malicious payload is rejected without allocating for it.
+pub(crate) fn read_header<'a>(
+    codec: &dyn Codec,
+    data: &'a [u8],
+    max_size: usize,
+) -> Result<(MessageHeader, &'a [u8]), MessageError> {
+    if data.len() > max_size {
+        return Err(MessageError::InvalidFormat(format!(
+            "Message of {} bytes exceeds the {} byte limit",
+            data.len(),
+            max_size
+        )));
+    }
+    codec.decode(data)
+}
+
+/// Parse just the header of a message framed by `MsgpackCodec`, e.g. to route
+/// it before dispatch.
+///
+/// Only the header's size is limited here; the dispatcher and `from_msgpack`
+/// still apply their own payload limits.
+pub fn peek_header(data: &[u8]) -> Result<MessageHeader, MessageError> {
+    MsgpackCodec.decode(data).map(|(header, _)| header)
+}
+
+/// Split a message framed by `MsgpackCodec` into its header and the raw body
+/// bytes, without deserializing the body.
+///
+/// The body is returned as it is on the wire, so it may be compressed. Like
+/// `peek_header`, this does not check the message's type, expiry or signature.
+pub fn split_frame(data: &[u8]) -> Result<(MessageHeader, &[u8]), MessageError> {
+    MsgpackCodec.decode(data)
+}
+
+/// Namespace `subject` under `prefix`, as `{prefix}.{subject}`, so deployments
+/// sharing a NATS cluster do not see each other's messages. An empty prefix
+/// leaves the subject unchanged.
+pub fn prefixed_subject(prefix: &str, subject: &str) -> String {
+    match prefix.trim_end_matches('.') {
+        "" => subject.to_string(),
+        prefix => format!("{}.{}", prefix, subject),
+    }
+}
+
+/// Root of the subjects `ExchangeMessage::default_subject` suggests
+pub const DEFAULT_SUBJECT_ROOT: &str = "hyperliquid";
+
+/// Canonical subject under `root` for a message of `msg_type` about `asset`:
+/// the root, the type's category and, if there is one, the lowercased asset,
+/// e.g. `hyperliquid.orders.btc` or `hyperliquid.transfers`. An empty root
+/// leaves the subject starting at the category.
+pub fn message_subject(root: &str, msg_type: MessageType, asset: Option<&str>) -> String {
+    let category = msg_type.category().subject_name();
+    let subject = match asset {
+        Some(asset) => asset_subject(category, asset),
+        None => category.to_string(),
+    };
+    prefixed_subject(root, &subject)
+}
+
+/// `base` with the lowercased `asset` appended, e.g. `hyperliquid.orders.btc`
+pub(crate) fn asset_subject(base: &str, asset: &str) -> String {
+    format!("{}.{}", base, asset.to_lowercase())
+}
+
+/// Frame `header` and a raw body taken from `split_frame` back into a message,
+/// e.g. after relabelling or re-correlating it.
+///
+/// The header's signature covers its fields, so re-sign a changed header with
+/// `MessageHeader::sign` before reframing a signed message.
+pub fn reframe(header: &MessageHeader, body: &[u8]) -> Vec<u8> {
+    MsgpackCodec
+        .encode(header, body)
+        .expect("a MessageHeader always serializes to msgpack")
+}
+
+/// Validate the header of a framed message and deserialize its body,
+/// returning both
+fn decode_message<M: Message>(
+    data: &[u8],
+    secret: Option<&[u8]>,
+    check_expiry: bool,
+) -> Result<(MessageHeader, M), MessageError> {
+    let (header, body) = read_header(M::codec(), data, M::max_payload_size())?;
+    if check_expiry {
+        M::validate(&header)?;
+    } else {
+        // Validate as if the message never expired
+        M::validate(&MessageHeader {
+            expires_at: 0,
+            ..header.clone()
+        })?;
+    }
+    
+    if header.version > SCHEMA_VERSION {
+        return Err(MessageError::UnsupportedVersion {
+            got: header.version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    
+    if let Some(secret) = secret {
+        header.verify(secret, body)?;
+    }
+
+    // Deserialize body, migrating it if it was written by an older schema.
+    // rmp_serde reads structs written as maps or as arrays, so this handles
+    // either `BodyEncoding` the header declares.
+    let body = if header.compressed {
+        std::borrow::Cow::Owned(decompress_body(body, M::max_payload_size())?)
+    } else {
+        std::borrow::Cow::Borrowed(body)
+    };
+    let msg = if header.version < SCHEMA_VERSION {
+        M::migrate(&header, &body)?
+    } else {
+        rmp_serde::from_slice(&body)?
+    };
+    Ok((header, msg))
+}
+
+/// Compress a message body with zstd
+fn compress_body(body: &[u8]) -> Result<Vec<u8>, MessageError> {
+    zstd::encode_all(body, 0)
+        .map_err(|e| MessageError::InvalidFormat(format!("Compression failed: {}", e)))
+}
+
+/// Decompress a zstd-compressed message body of at most `max_size` bytes
+fn decompress_body(body: &[u8], max_size: usize) -> Result<Vec<u8>, MessageError> {
+    use std::io::Read;
+
+    let decoder = zstd::Decoder::new(body)
+        .map_err(|e| MessageError::InvalidFormat(format!("Decompression failed: {}", e)))?;
+    // Read one byte past the limit to tell a body at the limit from a larger one
+    let mut decompressed = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| MessageError::InvalidFormat(format!("Decompression failed: {}", e)))?;
+    if decompressed.len() > max_size {
+        return Err(MessageError::InvalidFormat(format!(
+            "Decompressed body exceeds the {} byte limit",
+            max_size
+        )));
+    }
+    Ok(decompressed)
+}
 
 /// Error type for message operations
 #[derive(Error, Debug)]
 pub enum MessageError {
     #[error("Invalid message type: {0}")]
     InvalidMessageType(u8),
+
+    #[error("Unknown message type: {0}")]
+    UnknownMessageType(String),
     
     #[error("MessagePack encode error: {0}")]
     Encode(#[from] encode::Error),
@@ -237,6 +661,15 @@ pub enum MessageError {
     #[error("Message expired")]
     Expired,
     
+    #[error("Invalid message signature")]
+    InvalidSignature,
+    
+    #[error("Unsupported message version: got {got}, supported up to {supported}")]
+    UnsupportedVersion {
+        got: u16,
+        supported: u16,
+    },
+    
     #[error("Invalid message type: expected {expected:?}, got {actual:?}")]
     MismatchedType {
         expected: MessageType,
@@ -245,4 +678,41 @@ pub enum MessageError {
     
     #[error("Invalid message: {0}")]
     Validation(String),
+
+    #[error("Invalid message: {}", .0.join("; "))]
+    ValidationMany(Vec<String>),
+
+    #[error("Handler timed out after {0:?}")]
+    HandlerTimeout(std::time::Duration),
+
+    #[error("Rate limited; retry after {0:?}")]
+    RateLimited(std::time::Duration),
+
+    #[error("Service busy: too many messages in progress")]
+    Busy,
+
+    #[cfg(feature = "json")]
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl MessageError {
+    /// Turn the problems found validating a message into a result: `Ok` if there
+    /// were none, `Validation` for one and `ValidationMany` for several
+    pub fn from_problems(mut problems: Vec<String>) -> Result<(), MessageError> {
+        match problems.len() {
+            0 => Ok(()),
+            1 => Err(MessageError::Validation(problems.remove(0))),
+            _ => Err(MessageError::ValidationMany(problems)),
+        }
+    }
+
+    /// Every problem this error describes, one per entry
+    pub fn problems(&self) -> Vec<String> {
+        match self {
+            MessageError::Validation(problem) => vec![problem.clone()],
+            MessageError::ValidationMany(problems) => problems.clone(),
+            other => vec![other.to_string()],
+        }
+    }
 }
@@ -0,0 +1,73 @@
+//! Metrics for messages processed by `MessageDispatcher`
+//!
+//! Recording is a no-op unless the `metrics` feature is enabled and an exporter
+//! has been installed with `install_exporter`.
+
+use std::time::Duration;
+
+use crate::messages::MessageType;
+
+/// Counter of processed messages, labelled by `type` and `status`
+pub const MESSAGES_TOTAL: &str = "messages_total";
+
+/// Histogram of handler latency in seconds, labelled by `type`
+pub const HANDLER_DURATION_SECONDS: &str = "message_handler_duration_seconds";
+
+/// Gauge of messages currently being dispatched
+pub const MESSAGES_IN_FLIGHT: &str = "messages_in_flight";
+
+/// Serve the recorded metrics in Prometheus text format on `addr`.
+///
+/// Must be called from within a Tokio runtime.
+#[cfg(feature = "metrics")]
+pub fn install_exporter(
+    addr: std::net::SocketAddr,
+) -> Result<(), metrics_exporter_prometheus::BuildError> {
+    use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .set_buckets_for_metric(
+            Matcher::Full(HANDLER_DURATION_SECONDS.to_string()),
+            &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0],
+        )?
+        .install()
+}
+
+/// Count a message with the given outcome; `None` means its header could not be read
+pub(crate) fn record_outcome(msg_type: Option<MessageType>, status: &'static str) {
+    #[cfg(feature = "metrics")]
+    {
+        let msg_type = msg_type.map_or_else(|| "unknown".to_string(), |t| t.to_string());
+        metrics::counter!(MESSAGES_TOTAL, "type" => msg_type, "status" => status).increment(1);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (msg_type, status);
+}
+
+/// Record how long the handler for `msg_type` took
+pub(crate) fn record_handler_latency(msg_type: MessageType, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(HANDLER_DURATION_SECONDS, "type" => msg_type.to_string())
+        .record(elapsed.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = (msg_type, elapsed);
+}
+
+/// Counts a message as in flight until dropped
+pub(crate) struct InFlight;
+
+impl InFlight {
+    pub(crate) fn start() -> Self {
+        #[cfg(feature = "metrics")]
+        metrics::gauge!(MESSAGES_IN_FLIGHT).increment(1.0);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        metrics::gauge!(MESSAGES_IN_FLIGHT).decrement(1.0);
+    }
+}
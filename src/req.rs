@@ -9,7 +9,7 @@ struct ErrorData {
     msg: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HttpClient {
     pub client: Client,
     pub base_url: String,
@@ -0,0 +1,52 @@
+//! Tracing spans around message dispatch
+//!
+//! Spans are only created when the `tracing` feature is enabled; without it the
+//! futures run unchanged.
+
+use std::future::Future;
+
+use crate::{dispatch::MessageContext, messages::MessageType};
+
+/// Run `future`, the dispatch of the message in `context`, inside a `message`
+/// span carrying its type, `msg_id` and `correlation_id`
+pub(crate) fn in_message_span<F: Future>(
+    context: &MessageContext,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+
+        let header = &context.header;
+        let span = tracing::info_span!(
+            "message",
+            msg_type = %header.msg_type,
+            msg_id = %hex::encode(header.msg_id),
+            correlation_id = header.correlation_id.map(hex::encode),
+        );
+        future.instrument(span)
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = context;
+        future
+    }
+}
+
+/// Run `future`, the handler for a `msg_type` message, inside a `handler` span
+pub(crate) fn in_handler_span<F: Future>(
+    msg_type: MessageType,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+
+        future.instrument(tracing::info_span!("handler", msg_type = %msg_type))
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = msg_type;
+        future
+    }
+}
@@ -1,8 +1,21 @@
 use hyperliquid_rust_sdk::messages::{
+    AckResponse, AnyMessage, BatchUpdateLeverageRequest, BulkCancelRequest, BulkCancelResponse,
     ApproveAgentRequest, ApproveBuilderFeeRequest, SetReferrerRequest, UpdateIsolatedMarginRequest,
-    CancelOrderRequest, ModifyOrderRequest, OrderRequest, UpdateLeverageRequest,
-    ClassTransferRequest, TransferRequest, WithdrawRequest,
-    ExchangeMessage, MessageType, MessageHeader
+    CancelAllRequest, CancelOrderRequest, ModifyOrderRequest, OrderRequest, UpdateLeverageRequest,
+    ScheduleCancelRequest, PositionQueryRequest, PositionQueryResponse, PositionSummary,
+    ClassTransferRequest, MarketKind, SpotTransferRequest, TimeInForce, TransferRequest,
+    TriggerSpec, VaultTransferRequest, WithdrawRequest, OrderResponse, OrderStatus,
+    ApproveAgentResponse, ClosePositionRequest, OrderStage, StatusEvent, HeartbeatRequest,
+    AssetPrecision, BatchOrderRequest, BatchOrderResponse, OrderGrouping, FlattenRequest,
+    FlattenResponse, BatchResponse, ItemResult, ResolveCloidRequest, ResolveCloidResponse,
+    BodyEncoding, Codec, CompactMsgpackCodec, ExchangeMessage, Message, MessageCategory,
+    ManualClock, MessageError, MessageType, MessageHeader, MsgpackCodec,
+    DEFAULT_EXPIRATION_SECS, DEFAULT_MAX_PAYLOAD_SIZE, PRIORITY_HIGH, PRIORITY_NORMAL,
+    SCHEMA_VERSION, prefixed_subject, reframe, split_frame, DEFAULT_SUBJECT_ROOT,
+};
+use hyperliquid_rust_sdk::{
+    ClientCancelRequest, ClientCancelRequestCloid, ClientOrder, ClientOrderRequest,
+    MarketOrderParams,
 };
 
 #[test]
@@ -43,14 +56,213 @@ fn test_cancel_order_request_serialization() {
     assert_eq!(cancel_by_cloid.cloid, deserialized.cloid);
 }
 
+#[test]
+fn test_cancel_all_request_serialization() {
+    let cancel_all = CancelAllRequest::for_asset("ETH");
+    let serialized = cancel_all.to_msgpack().unwrap();
+    let deserialized = CancelAllRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.asset.as_deref(), Some("ETH"));
+
+    let cancel_all = CancelAllRequest::all();
+    let serialized = cancel_all.to_msgpack().unwrap();
+    let deserialized = CancelAllRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.asset, None);
+}
+
+#[test]
+fn test_bulk_cancel_request_serialization() {
+    let cloid = "123e4567-e89b-12d3-a456-426614174000";
+    let cancel = BulkCancelRequest::by_cloids("BTC", vec![cloid.to_string()]);
+    assert!(cancel.validate().is_ok());
+    let serialized = cancel.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::BulkCancel);
+    let deserialized = BulkCancelRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.cloids, vec![cloid.to_string()]);
+    assert!(deserialized.order_ids.is_empty());
+
+    assert!(BulkCancelRequest::by_order_ids("BTC", vec![1, 2]).validate().is_ok());
+    assert!(BulkCancelRequest::by_order_ids("BTC", Vec::new()).validate().is_err());
+    let invalid = BulkCancelRequest::by_cloids("BTC", vec![cloid.to_string(), "abc".to_string()]);
+    assert!(invalid.validate().is_err());
+
+    let request = MessageHeader::new(MessageType::BulkCancel);
+    let response = BulkCancelResponse {
+        cancelled: vec!["1".to_string()],
+        not_found: vec![cloid.to_string()],
+    };
+    let reply = response.to_reply(&request).unwrap();
+    assert_eq!(header_of(&reply).msg_type, MessageType::BulkCancelResponse);
+    assert_eq!(BulkCancelResponse::from_msgpack(&reply).unwrap(), response);
+}
+
+#[test]
+fn test_order_response_serialization() {
+    let request = MessageHeader::new(MessageType::Order).with_correlation_id([3; 16]);
+    let response = OrderResponse::filled(42, "0.4", "65000.5").with_remaining_size("0.6");
+    assert!(response.is_partial_fill());
+    let reply = response.to_reply(&request).unwrap();
+    let header = header_of(&reply);
+    assert_eq!(header.msg_type, MessageType::OrderResponse);
+    assert_eq!(header.correlation_id, Some([3; 16]));
+    assert_eq!(OrderResponse::from_msgpack(&reply).unwrap(), response);
+
+    let resting = OrderResponse::resting(7);
+    assert_eq!(resting.status, OrderStatus::Resting);
+    assert!(!resting.is_partial_fill());
+    assert_eq!(resting.filled_size, None);
+    assert_eq!(resting.cloid, None);
+
+    let cloid = "1e60610f-0b3c-4f14-9e1c-3e2b4c9a1a2b";
+    let response = OrderResponse::resting(7).with_cloid(cloid);
+    let reply = response.to_reply(&request).unwrap();
+    assert_eq!(OrderResponse::from_msgpack(&reply).unwrap().cloid.as_deref(), Some(cloid));
+}
+
+#[test]
+fn test_status_event_serialization() {
+    let request = MessageHeader::new(MessageType::Order).with_correlation_id([5; 16]);
+    let cloid = Some("1e60610f-0b3c-4f14-9e1c-3e2b4c9a1a2b".to_string());
+    let submitted = StatusEvent::new(request.msg_id, "BTC", cloid.clone(), OrderStage::Submitted);
+    let encoded = submitted.to_event(&request).unwrap();
+    let header = header_of(&encoded);
+    assert_eq!(header.msg_type, MessageType::StatusEvent);
+    assert_eq!(header.correlation_id, Some([5; 16]));
+    assert_eq!(StatusEvent::from_msgpack(&encoded).unwrap(), submitted);
+
+    let response = OrderResponse::filled(42, "0.4", "65000.5")
+        .with_remaining_size("0.6")
+        .with_cloid(cloid.as_deref().unwrap());
+    let filled = StatusEvent::from_response(request.msg_id, "BTC", &response);
+    assert_eq!(filled.stage, OrderStage::Filled);
+    assert_eq!(filled.oid, Some(42));
+    assert_eq!(filled.cloid, cloid);
+    assert_eq!(filled.remaining_size.as_deref(), Some("0.6"));
+
+    let rejected = StatusEvent::rejected(request.msg_id, "BTC", None, "Insufficient margin");
+    assert_eq!(rejected.stage, OrderStage::Rejected);
+    assert_eq!(rejected.error.as_deref(), Some("Insufficient margin"));
+}
+
+/// Expected framing of the order in `test_fixed_header_encoding`: a 4-byte
+/// header length, the msgpack header, then the msgpack body. A change here
+/// breaks publishers built against the current wire format.
+const GOLDEN_ORDER: &str = concat!(
+    "0000008d8aa86d73675f74797065a54f72646572a66d73675f6964dc001001010101010101010101",
+    "010101010101ae636f7272656c6174696f6e5f6964c0a974696d657374616d70cf0000018bcfe568",
+    "00aa657870697265735f617400aa636f6d70726573736564c2a776657273696f6e01a97369676e61",
+    "74757265c0a76163636f756e74c0a87072696f72697479cc808ea56173736574a3425443a669735f",
+    "627579c3a473697a65a4302e3031ab6c696d69745f7072696365a53635303030a5636c6f6964c0ab",
+    "7265647563655f6f6e6c79c2ad74696d655f696e5f666f726365a3477463a774726967676572c0a6",
+    "6d61726b6574a450657270a76275696c646572c0b66275696c6465725f6665655f74656e7468735f",
+    "627073c0a8736c697070616765c0ad7661756c745f61646472657373c0ad657870697265735f6174",
+    "5f6d73c0",
+);
+
+#[test]
+fn test_fixed_header_encoding() {
+    let header = MessageHeader::with_fixed(MessageType::Order, [1; 16], 1_700_000_000_000, 0);
+    let order = OrderRequest::limit("BTC", true, "0.01", "65000");
+    let encoded = order.to_msgpack_with_header(header.clone()).unwrap();
+    assert_eq!(encoded, order.to_msgpack_with_header(header).unwrap());
+    assert_eq!(hex::encode(&encoded), GOLDEN_ORDER);
+}
+
+#[test]
+fn test_close_position_request_serialization() {
+    let close = ClosePositionRequest::new("ETH").with_slippage(0.01);
+    assert!(close.validate().is_ok());
+    let serialized = close.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::ClosePosition);
+    let deserialized = ClosePositionRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.asset, "ETH");
+    assert_eq!(deserialized.slippage, Some(0.01));
+
+    assert!(ClosePositionRequest::new("ETH").validate().is_ok());
+    assert!(ClosePositionRequest::new("ETH").with_slippage(1.5).validate().is_err());
+}
+
+#[test]
+fn test_flatten_request_serialization() {
+    let flatten = FlattenRequest::new("ETH").with_slippage(0.01);
+    assert!(flatten.cancel_resting);
+    let serialized = flatten.to_msgpack().unwrap();
+    let header = header_of(&serialized);
+    assert_eq!(header.msg_type, MessageType::Flatten);
+    assert_eq!(header.priority, PRIORITY_HIGH);
+    let deserialized = FlattenRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.asset, "ETH");
+    assert_eq!(deserialized.slippage, Some(0.01));
+    assert!(FlattenRequest::new("ETH").with_slippage(1.5).validate().is_err());
+
+    let response = FlattenResponse::new(3, Some(OrderResponse::filled(7, "1.5", "1800.5")));
+    let reply = response.to_reply(&header).unwrap();
+    assert_eq!(header_of(&reply).msg_type, MessageType::FlattenResponse);
+    assert_eq!(FlattenResponse::from_msgpack(&reply).unwrap(), response);
+}
+
+#[test]
+fn test_schedule_cancel_request_serialization() {
+    let schedule = ScheduleCancelRequest::at(1_700_000_000_000);
+    let serialized = schedule.to_msgpack().unwrap();
+    let deserialized = ScheduleCancelRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.time_ms, Some(1_700_000_000_000));
+
+    let clear = ScheduleCancelRequest::clear();
+    let deserialized = ScheduleCancelRequest::from_msgpack(&clear.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.time_ms, None);
+}
+
+#[test]
+fn test_batch_update_leverage_request_serialization() {
+    let batch = BatchUpdateLeverageRequest::new(vec![
+        UpdateLeverageRequest::new("BTC", 10, true),
+        UpdateLeverageRequest::new("ETH", 5, false),
+    ]);
+    let serialized = batch.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::BatchUpdateLeverage);
+    let deserialized = BatchUpdateLeverageRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.updates.len(), 2);
+    assert_eq!(deserialized.updates[1].asset, "ETH");
+    assert_eq!(deserialized.updates[1].leverage, 5);
+    assert!(!deserialized.updates[1].is_cross);
+}
+
+#[test]
+fn test_modify_order_request_serialization() {
+    let modify = ModifyOrderRequest::by_cloid("abc").with_size("2.0").with_price("51000");
+    assert!(modify.validate().is_ok());
+    let serialized = modify.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::ModifyOrder);
+    let deserialized = ModifyOrderRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.cloid.as_deref(), Some("abc"));
+    assert_eq!(deserialized.new_size.as_deref(), Some("2.0"));
+    assert_eq!(deserialized.new_price.as_deref(), Some("51000"));
+
+    assert!(ModifyOrderRequest::by_order_id(1).with_price("51000").validate().is_ok());
+    assert!(ModifyOrderRequest::by_order_id(1).validate().is_err());
+}
+
 #[test]
 fn test_transfer_request_serialization() {
-    let transfer = TransferRequest::new("USDC", "100.0", "0x1234...");
+    let destination = "0x1719884eb866cb12b2287399b15f7db5e7d775ea";
+    let transfer = TransferRequest::usdc("100.0", destination).unwrap();
     let serialized = transfer.to_msgpack().unwrap();
     let deserialized = TransferRequest::from_msgpack(&serialized).unwrap();
-    assert_eq!(transfer.asset, deserialized.asset);
+    assert_eq!(deserialized.asset, "USDC");
     assert_eq!(transfer.amount, deserialized.amount);
     assert_eq!(transfer.destination, deserialized.destination);
+
+    let spot = TransferRequest::spot("5.0", destination, "PURR").unwrap();
+    assert_eq!(spot.asset, "PURR");
+
+    // Destinations must be full 0x-prefixed addresses
+    for destination in ["0x1234...", "1719884eb866cb12b2287399b15f7db5e7d775ea", "0x1719"] {
+        assert!(matches!(
+            TransferRequest::usdc("100.0", destination),
+            Err(MessageError::Validation(_))
+        ));
+    }
+    assert!(TransferRequest::new("USDC", "100.0", "0x1234...").validate().is_err());
 }
 
 #[test]
@@ -72,6 +284,42 @@ fn test_class_transfer_request_serialization() {
     assert_eq!(class_transfer.to_perp, deserialized.to_perp);
 }
 
+#[test]
+fn test_vault_transfer_request_builders() {
+    let deposit = VaultTransferRequest::deposit(100)
+        .with_vault_address("0x1719884eb866cb12b2287399b15f7db5e7d775ea");
+    assert!(deposit.is_deposit);
+    assert_eq!(deposit.usd, 100);
+    assert!(deposit.validate().is_ok());
+
+    let withdraw = VaultTransferRequest::withdraw(50);
+    assert!(!withdraw.is_deposit);
+    assert!(withdraw.validate().is_ok());
+
+    // Vault and class transfers no longer share a message type
+    let serialized = deposit.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::VaultTransfer);
+    assert!(ClassTransferRequest::from_msgpack(&serialized).is_err());
+
+    assert!(withdraw.clone().with_vault_address("0x1234").validate().is_err());
+    assert!(withdraw.with_vault_address("not an address").validate().is_err());
+}
+
+#[test]
+fn test_spot_transfer_request_serialization() {
+    let spot = SpotTransferRequest::new("1.5", "0x1719884eb866cb12b2287399b15f7db5e7d775ea", "PURR");
+    let serialized = spot.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::SpotTransfer);
+    assert!(TransferRequest::from_msgpack(&serialized).is_err());
+    let deserialized = SpotTransferRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.amount, "1.5");
+    assert_eq!(deserialized.token, "PURR");
+    assert!(deserialized.validate().is_ok());
+
+    assert!(SpotTransferRequest::new("1.5", "0x1234", "PURR").validate().is_err());
+    assert!(SpotTransferRequest::new("1.5", &deserialized.destination, "").validate().is_err());
+}
+
 #[test]
 fn test_update_isolated_margin_request_serialization() {
     let update_margin = UpdateIsolatedMarginRequest::new("BTC", 1000.0);
@@ -88,6 +336,20 @@ fn test_approve_agent_request_serialization() {
     let deserialized = ApproveAgentRequest::from_msgpack(&serialized).unwrap();
     assert_eq!(approve_agent.agent_address, deserialized.agent_address);
     assert_eq!(approve_agent.agent_name, deserialized.agent_name);
+    assert!(approve_agent.validate().is_err());
+
+    let address = "0x00000000000000000000000000000000000000aa";
+    let named = ApproveAgentRequest::new(address).with_agent_name("bot");
+    assert!(named.validate().is_ok());
+
+    let request = MessageHeader::new(MessageType::ApproveAgent);
+    let response = ApproveAgentResponse {
+        agent_address: address.to_string(),
+        agent_name: named.agent_name,
+    };
+    let reply = response.to_reply(&request).unwrap();
+    assert_eq!(header_of(&reply).msg_type, MessageType::ApproveAgentResponse);
+    assert_eq!(ApproveAgentResponse::from_msgpack(&reply).unwrap(), response);
 }
 
 #[test]
@@ -107,6 +369,24 @@ fn test_approve_builder_fee_request_serialization() {
     assert_eq!(approve_builder_fee.max_fee_rate, deserialized.max_fee_rate);
 }
 
+#[test]
+fn test_from_msgpack_with_header() {
+    let order = OrderRequest::market("BTC", true, "1.0");
+    let header = MessageHeader::new(MessageType::Order).with_correlation_id([7; 16]);
+    let encoded = order.to_msgpack_with_header(header.clone()).unwrap();
+    let (decoded_header, decoded) = OrderRequest::from_msgpack_with_header(&encoded).unwrap();
+    assert_eq!(decoded_header.msg_id, header.msg_id);
+    assert_eq!(decoded_header.correlation_id, Some([7; 16]));
+    assert_eq!(decoded.asset, "BTC");
+}
+
+#[test]
+fn test_message_name() {
+    assert_eq!(OrderRequest::market("BTC", true, "1.0").message_name(), "Order");
+    assert_eq!(OrderRequest::limit("BTC", true, "1.0", "65000").message_name(), "Order");
+    assert_eq!(AckResponse::success().message_name(), MessageType::Ack.to_string());
+}
+
 #[test]
 fn test_message_type_values() {
     // Verify that message type values are as expected
@@ -114,11 +394,1031 @@ fn test_message_type_values() {
     assert_eq!(MessageType::CancelOrder as u8, 0x02);
     assert_eq!(MessageType::ModifyOrder as u8, 0x03);
     assert_eq!(MessageType::UpdateLeverage as u8, 0x04);
+    assert_eq!(MessageType::BatchUpdateLeverage as u8, 0x05);
+    assert_eq!(MessageType::BatchOrder as u8, 0x06);
+    assert_eq!(MessageType::CancelAll as u8, 0x07);
+    assert_eq!(MessageType::ScheduleCancel as u8, 0x08);
+    assert_eq!(MessageType::BulkCancel as u8, 0x09);
+    assert_eq!(MessageType::ClosePosition as u8, 0x0A);
+    assert_eq!(MessageType::Flatten as u8, 0x0B);
     assert_eq!(MessageType::Transfer as u8, 0x10);
     assert_eq!(MessageType::Withdraw as u8, 0x11);
     assert_eq!(MessageType::ClassTransfer as u8, 0x12);
+    assert_eq!(MessageType::VaultTransfer as u8, 0x13);
+    assert_eq!(MessageType::SpotTransfer as u8, 0x14);
     assert_eq!(MessageType::UpdateIsolatedMargin as u8, 0x20);
     assert_eq!(MessageType::ApproveAgent as u8, 0x21);
     assert_eq!(MessageType::SetReferrer as u8, 0x22);
     assert_eq!(MessageType::ApproveBuilderFee as u8, 0x23);
+    assert_eq!(MessageType::Heartbeat as u8, 0x24);
+    assert_eq!(MessageType::Ack as u8, 0x30);
+    assert_eq!(MessageType::PositionQueryResponse as u8, 0x31);
+    assert_eq!(MessageType::BulkCancelResponse as u8, 0x32);
+    assert_eq!(MessageType::OrderResponse as u8, 0x33);
+    assert_eq!(MessageType::ApproveAgentResponse as u8, 0x34);
+    assert_eq!(MessageType::BatchOrderResponse as u8, 0x35);
+    assert_eq!(MessageType::FlattenResponse as u8, 0x36);
+    assert_eq!(MessageType::BatchResponse as u8, 0x37);
+    assert_eq!(MessageType::ResolveCloidResponse as u8, 0x38);
+    assert_eq!(MessageType::PositionQuery as u8, 0x40);
+    assert_eq!(MessageType::ResolveCloid as u8, 0x41);
+    assert_eq!(MessageType::StatusEvent as u8, 0x50);
+}
+
+#[test]
+fn test_message_type_category() {
+    assert_eq!(MessageType::Order.category(), MessageCategory::Order);
+    assert_eq!(MessageType::ClosePosition.category(), MessageCategory::Order);
+    assert_eq!(MessageType::Transfer.category(), MessageCategory::Transfer);
+    assert_eq!(MessageType::SpotTransfer.category(), MessageCategory::Transfer);
+    assert_eq!(MessageType::ApproveAgent.category(), MessageCategory::Account);
+    assert_eq!(MessageType::Ack.category(), MessageCategory::Response);
+    assert_eq!(MessageType::PositionQuery.category(), MessageCategory::Query);
+    assert_eq!(MessageType::StatusEvent.category(), MessageCategory::Event);
+}
+
+#[test]
+fn test_message_type_names() {
+    for &msg_type in MessageType::all() {
+        assert_eq!(MessageType::from_name(&msg_type.to_string()), Some(msg_type));
+        assert_eq!(msg_type.to_string().parse::<MessageType>().unwrap(), msg_type);
+        assert_eq!(MessageType::try_from(msg_type as u8).unwrap(), msg_type);
+    }
+    assert_eq!(MessageType::from_name("NotAType"), None);
+    assert!("NotAType".parse::<MessageType>().is_err());
+}
+
+#[test]
+fn test_any_message() {
+    let messages: Vec<AnyMessage> = vec![
+        OrderRequest::limit("ETH", false, "1.0", "1800.5").into(),
+        CancelOrderRequest::by_order_id("BTC", 7).into(),
+        TransferRequest::new("USDC", "1.0", "0x1719884eb866cb12b2287399b15f7db5e7d775ea").into(),
+        AckResponse::success().into(),
+    ];
+    for msg in messages {
+        let serialized = msg.to_msgpack().unwrap();
+        let decoded = AnyMessage::from_msgpack(&serialized).unwrap();
+        assert_eq!(decoded.message_type(), msg.message_type());
+    }
+
+    let serialized = CancelOrderRequest::by_order_id("BTC", 7).to_msgpack().unwrap();
+    match AnyMessage::from_msgpack(&serialized).unwrap() {
+        AnyMessage::CancelOrder(cancel) => assert_eq!(cancel.order_id, Some(7)),
+        other => panic!("Decoded as {:?}", other.message_type()),
+    }
+
+    let signed = OrderRequest::market("BTC", true, "1.0").to_msgpack_signed(b"secret").unwrap();
+    assert!(AnyMessage::from_msgpack_verified(&signed, Some(b"secret")).is_ok());
+    assert!(AnyMessage::from_msgpack_verified(&signed, Some(b"other")).is_err());
+
+    let body =
+        serde_json::json!({"asset": "BTC", "is_buy": true, "size": "0", "time_in_force": "Ioc"});
+    let order = AnyMessage::from_json_body(MessageType::Order, body).unwrap();
+    assert!(order.validate().is_err());
+    let cancel_all = serde_json::json!({"asset": "BTC"});
+    let cancel_all = AnyMessage::from_json_body(MessageType::CancelAll, cancel_all).unwrap();
+    assert!(cancel_all.validate().is_ok());
+    assert!(AnyMessage::from_json_body(MessageType::Order, serde_json::json!({})).is_err());
+
+    let header = MessageHeader::new(MessageType::CancelAll).with_account("desk");
+    let framed = cancel_all.to_msgpack_with_header(header, Some(b"secret")).unwrap();
+    assert_eq!(header_of(&framed).account.as_deref(), Some("desk"));
+    assert!(AnyMessage::from_msgpack_verified(&framed, Some(b"secret")).is_ok());
+    let mismatched = MessageHeader::new(MessageType::Order);
+    assert!(cancel_all.to_msgpack_with_header(mismatched, None).is_err());
+}
+
+#[test]
+fn test_message_type_all() {
+    // Every wire value that decodes to a known type must be listed exactly once
+    let decodable: Vec<MessageType> = (0..=u8::MAX)
+        .filter_map(|value| MessageType::try_from(value).ok())
+        .filter(|msg_type| *msg_type != MessageType::Unknown)
+        .collect();
+    assert_eq!(MessageType::all(), decodable.as_slice());
+}
+
+#[test]
+fn test_heartbeat_request() {
+    let heartbeat = HeartbeatRequest::new("momentum-1");
+    let header = MessageHeader::new(MessageType::Heartbeat).with_account("sub1");
+    let serialized = heartbeat.to_msgpack_with_header(header).unwrap();
+    let header = header_of(&serialized);
+    assert_eq!(header.priority, PRIORITY_HIGH);
+    assert_eq!(header.account.as_deref(), Some("sub1"));
+    let deserialized = HeartbeatRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.strategy_id, "momentum-1");
+    assert!(deserialized.validate().is_ok());
+    assert!(HeartbeatRequest::new("").validate().is_err());
+}
+
+#[test]
+fn test_signed_message_verification() {
+    let secret = b"shared-secret";
+    let order = OrderRequest::limit("ETH", false, "2.0", "1800.0");
+    let signed = order.to_msgpack_signed(secret).unwrap();
+
+    // Correct secret verifies
+    let deserialized = OrderRequest::from_msgpack_verified(&signed, Some(secret)).unwrap();
+    assert_eq!(order.limit_price, deserialized.limit_price);
+
+    // Wrong secret is rejected
+    let err = OrderRequest::from_msgpack_verified(&signed, Some(b"other-secret")).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidSignature));
+
+    // Tampered body is rejected
+    let mut tampered = signed.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 0x01;
+    let err = OrderRequest::from_msgpack_verified(&tampered, Some(secret)).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidSignature));
+
+    // The expiry and correlation id are covered, so neither can be changed in transit
+    let mut header = MessageHeader::new(MessageType::Order).with_correlation_id([7u8; 16]);
+    header.sign(secret, b"body");
+    assert!(header.verify(secret, b"body").is_ok());
+    let mut extended = header.clone();
+    extended.expires_at += 60_000;
+    assert!(extended.verify(secret, b"body").is_err());
+    header.correlation_id = Some([8u8; 16]);
+    assert!(header.verify(secret, b"body").is_err());
+
+    // Unsigned messages are rejected when a secret is configured, accepted otherwise
+    let unsigned = order.to_msgpack().unwrap();
+    assert!(OrderRequest::from_msgpack_verified(&unsigned, Some(secret)).is_err());
+    assert!(OrderRequest::from_msgpack_verified(&unsigned, None).is_ok());
+}
+
+#[test]
+fn test_header_account() {
+    let order = OrderRequest::market("BTC", true, "1.0");
+    let header = MessageHeader::new(MessageType::Order).with_account("sub1");
+    let serialized = order.to_msgpack_with_header(header).unwrap();
+    assert_eq!(header_of(&serialized).account.as_deref(), Some("sub1"));
+    assert!(OrderRequest::from_msgpack(&serialized).is_ok());
+
+    // Headers without an account decode with none
+    let serialized = order.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).account, None);
+
+    // The signature covers the account, so it cannot be redirected
+    let secret = b"secret";
+    let mut header = MessageHeader::new(MessageType::Order).with_account("sub1");
+    header.sign(secret, b"body");
+    assert!(header.verify(secret, b"body").is_ok());
+    header.account = Some("sub2".to_string());
+    assert!(header.verify(secret, b"body").is_err());
+}
+
+#[test]
+fn test_header_priority() {
+    assert_eq!(MessageHeader::new(MessageType::Order).priority, PRIORITY_NORMAL);
+    assert_eq!(MessageHeader::new(MessageType::CancelAll).priority, PRIORITY_HIGH);
+    assert_eq!(MessageHeader::new(MessageType::ScheduleCancel).priority, PRIORITY_HIGH);
+
+    let order = OrderRequest::market("BTC", true, "1.0");
+    let header = MessageHeader::new(MessageType::Order).with_priority(PRIORITY_HIGH + 1);
+    let serialized = order.to_msgpack_with_header(header).unwrap();
+    assert_eq!(header_of(&serialized).priority, PRIORITY_HIGH + 1);
+
+    // The signature covers the priority, so it cannot be lowered in transit
+    let secret = b"secret";
+    let mut header = MessageHeader::new(MessageType::CancelAll);
+    header.sign(secret, b"body");
+    assert!(header.verify(secret, b"body").is_ok());
+    header.priority = PRIORITY_NORMAL;
+    assert!(header.verify(secret, b"body").is_err());
+}
+
+#[test]
+fn test_split_frame_and_reframe() {
+    let order = OrderRequest::limit("ETH", false, "2.0", "1800.0");
+    let serialized = order.to_msgpack().unwrap();
+
+    // Splitting and reframing untouched is lossless
+    let (header, body) = split_frame(&serialized).unwrap();
+    assert_eq!(header.msg_type, MessageType::Order);
+    assert_eq!(reframe(&header, body), serialized);
+
+    // A re-correlated message keeps its body
+    let correlation_id = [7u8; 16];
+    let relabelled = reframe(&header.with_correlation_id(correlation_id), body);
+    let (header, decoded) = OrderRequest::from_msgpack_with_header(&relabelled).unwrap();
+    assert_eq!(header.correlation_id, Some(correlation_id));
+    assert_eq!(decoded.limit_price, order.limit_price);
+
+    // A signed message must be re-signed after its header changes
+    let secret = b"shared-secret";
+    let signed = order.to_msgpack_signed(secret).unwrap();
+    let (header, body) = split_frame(&signed).unwrap();
+    let mut header = header.with_account("sub1");
+    let unsigned = reframe(&header, body);
+    assert!(OrderRequest::from_msgpack_verified(&unsigned, Some(secret)).is_err());
+    header.sign(secret, body);
+    let resigned = reframe(&header, body);
+    assert!(OrderRequest::from_msgpack_verified(&resigned, Some(secret)).is_ok());
+
+    assert!(split_frame(&[0, 0]).is_err());
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct LargeMessage {
+    payload: Vec<String>,
+}
+
+impl Message for LargeMessage {
+    fn message_type() -> MessageType {
+        MessageType::Order
+    }
+
+    fn compression_threshold() -> Option<usize> {
+        Some(256)
+    }
+}
+
+fn header_of(data: &[u8]) -> MessageHeader {
+    let header_len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    rmp_serde::from_slice(&data[4..4 + header_len]).unwrap()
+}
+
+#[test]
+fn test_large_message_compression() {
+    let large = LargeMessage {
+        payload: vec!["BTC".to_string(); 500],
+    };
+    let serialized = Message::to_msgpack(&large).unwrap();
+    assert!(header_of(&serialized).compressed);
+    assert!(serialized.len() < rmp_serde::to_vec_named(&large).unwrap().len());
+    let deserialized = LargeMessage::from_msgpack(&serialized).unwrap();
+    assert_eq!(large.payload, deserialized.payload);
+
+    // Bodies below the threshold stay uncompressed
+    let small = LargeMessage {
+        payload: vec!["BTC".to_string()],
+    };
+    let serialized = Message::to_msgpack(&small).unwrap();
+    assert!(!header_of(&serialized).compressed);
+
+    // Corrupt compressed bodies surface as a format error
+    let mut corrupt = Message::to_msgpack(&large).unwrap();
+    let last = corrupt.len() - 4;
+    corrupt.truncate(last);
+    let err = LargeMessage::from_msgpack(&corrupt).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidFormat(_)));
+}
+
+/// `CancelOrderRequest`'s fields, framed with compact bodies
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct CompactCancel {
+    asset: String,
+    order_id: Option<u64>,
+    cloid: Option<String>,
+}
+
+impl Message for CompactCancel {
+    fn message_type() -> MessageType {
+        MessageType::CancelOrder
+    }
+
+    fn codec() -> &'static dyn Codec {
+        &CompactMsgpackCodec
+    }
+}
+
+#[test]
+fn test_compact_body_encoding() {
+    let compact = CompactCancel {
+        asset: "BTC".to_string(),
+        order_id: Some(42),
+        cloid: None,
+    };
+    let serialized = Message::to_msgpack(&compact).unwrap();
+    assert_eq!(header_of(&serialized).encoding, BodyEncoding::Compact);
+    let named = CancelOrderRequest::by_order_id("BTC", 42).to_msgpack().unwrap();
+    assert_eq!(header_of(&named).encoding, BodyEncoding::Named);
+    let (_, compact_body) = split_frame(&serialized).unwrap();
+    let (_, named_body) = split_frame(&named).unwrap();
+    assert!(compact_body.len() < named_body.len());
+
+    // Either encoding decodes into either type, so they can share a subject
+    let decoded = CancelOrderRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!((decoded.asset.as_str(), decoded.order_id), ("BTC", Some(42)));
+    assert_eq!(CompactCancel::from_msgpack(&named).unwrap(), compact);
+}
+
+/// A compressed message that only accepts small payloads
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct LimitedMessage {
+    payload: Vec<String>,
+}
+
+impl Message for LimitedMessage {
+    fn message_type() -> MessageType {
+        MessageType::Order
+    }
+
+    fn compression_threshold() -> Option<usize> {
+        Some(256)
+    }
+
+    fn max_payload_size() -> usize {
+        1024
+    }
+}
+
+#[test]
+fn test_payload_size_limits() {
+    // A header length claiming more than the limit is rejected before parsing
+    let mut frame = u32::MAX.to_be_bytes().to_vec();
+    frame.extend_from_slice(&[0; 16]);
+    let err = OrderRequest::from_msgpack(&frame).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidFormat(_)));
+
+    let mut oversized = OrderRequest::market("BTC", true, "1.0").to_msgpack().unwrap();
+    oversized.resize(DEFAULT_MAX_PAYLOAD_SIZE + 1, 0);
+    let err = OrderRequest::from_msgpack(&oversized).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidFormat(_)));
+
+    // Compressed bodies are limited by their decompressed size
+    let small = LimitedMessage {
+        payload: vec!["BTC".to_string(); 100],
+    };
+    assert!(LimitedMessage::from_msgpack(&Message::to_msgpack(&small).unwrap()).is_ok());
+    let bomb = LimitedMessage {
+        payload: vec!["BTC".to_string(); 5000],
+    };
+    let serialized = Message::to_msgpack(&bomb).unwrap();
+    assert!(serialized.len() < 1024);
+    let err = LimitedMessage::from_msgpack(&serialized).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidFormat(_)));
+}
+
+/// `data` as a newer producer would frame it with a type named `name` that this
+/// build does not know, in place of the header's `Order`
+fn rename_order_type(data: &[u8], name: &[u8; 5]) -> Vec<u8> {
+    let at = data.windows(6).position(|w| w == b"\xa5Order").unwrap();
+    let mut renamed = data.to_vec();
+    renamed[at + 1..at + 6].copy_from_slice(name);
+    renamed
+}
+
+#[test]
+fn test_unknown_message_type() {
+    let order = OrderRequest::market("BTC", true, "1.0");
+    let serialized = order.to_msgpack().unwrap();
+    let msg_id = header_of(&serialized).msg_id;
+    let unknown = rename_order_type(&serialized, b"Quote");
+
+    // The header still reads, with the type's name kept
+    let (header, _) = split_frame(&unknown).unwrap();
+    assert_eq!(header.msg_type, MessageType::Unknown);
+    assert_eq!(header.unknown_type.as_deref(), Some("Quote"));
+    assert_eq!(header.msg_id, msg_id);
+    assert_eq!(header_of(&serialized).unknown_type, None);
+
+    // but the message does not decode as any known type
+    let err = AnyMessage::from_msgpack(&unknown).unwrap_err();
+    assert!(matches!(err, MessageError::UnknownMessageType(name) if name == "Quote"));
+    assert!(OrderRequest::from_msgpack(&unknown).is_err());
+    assert!(!MessageType::all().contains(&MessageType::Unknown));
+    assert_eq!(MessageType::from_name("Unknown"), None);
+    assert_eq!(header.msg_type.category(), MessageCategory::Unknown);
+    assert_eq!(MessageType::try_from(0xFF).unwrap(), MessageType::Unknown);
+    assert_eq!(header.type_name(), "Quote");
+
+    // Reframing keeps the name it was sent with
+    let (header, body) = split_frame(&unknown).unwrap();
+    let reframed = reframe(&header.clone().with_correlation_id([9; 16]), body);
+    let (reread, _) = split_frame(&reframed).unwrap();
+    assert_eq!(reread.unknown_type.as_deref(), Some("Quote"));
+    assert_eq!(reread.correlation_id, Some([9; 16]));
+}
+
+#[test]
+fn test_malformed_input_never_panics() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // Seeded so a failure reproduces
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let mut random_bytes = |len: usize| (0..len).map(|_| rng.gen::<u8>()).collect::<Vec<_>>();
+
+    // Every truncation of a valid message is an error
+    let order = OrderRequest::limit("BTC", true, "1.0", "50000").to_msgpack().unwrap();
+    for len in 0..order.len() {
+        assert!(OrderRequest::from_msgpack(&order[..len]).is_err());
+    }
+
+    for len in 0..512 {
+        // Random bytes, then random bytes behind a header length that fits
+        let mut data = random_bytes(len % 64 + len / 8);
+        let _ = AnyMessage::from_msgpack(&data);
+        let _ = OrderRequest::from_msgpack(&data);
+        if data.len() >= 4 {
+            let header_len = (data.len() as u32 - 4).min(len as u32);
+            data[..4].copy_from_slice(&header_len.to_be_bytes());
+            let _ = AnyMessage::from_msgpack(&data);
+        }
+    }
+
+    // A valid header of every type in front of a random, possibly
+    // "compressed", body reaches each message type's decoder
+    for &msg_type in MessageType::all() {
+        for len in 0..64 {
+            let mut header = MessageHeader::new(msg_type);
+            header.compressed = len % 2 == 1;
+            let data = reframe(&header, &random_bytes(len));
+            let _ = AnyMessage::from_msgpack(&data);
+        }
+    }
+}
+
+/// Frames messages like `MsgpackCodec` behind a two-byte magic prefix
+#[derive(Debug)]
+struct PrefixedCodec;
+
+impl Codec for PrefixedCodec {
+    fn encode(&self, header: &MessageHeader, body: &[u8]) -> Result<Vec<u8>, MessageError> {
+        let mut data = b"HL".to_vec();
+        data.extend(MsgpackCodec.encode(header, body)?);
+        Ok(data)
+    }
+
+    fn decode<'a>(&self, data: &'a [u8]) -> Result<(MessageHeader, &'a [u8]), MessageError> {
+        match data.strip_prefix(b"HL") {
+            Some(data) => MsgpackCodec.decode(data),
+            None => Err(MessageError::InvalidFormat("Missing prefix".to_string())),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct PrefixedMessage {
+    asset: String,
+}
+
+impl Message for PrefixedMessage {
+    fn message_type() -> MessageType {
+        MessageType::Order
+    }
+
+    fn codec() -> &'static dyn Codec {
+        &PrefixedCodec
+    }
+}
+
+#[test]
+fn test_custom_codec() {
+    let msg = PrefixedMessage {
+        asset: "BTC".to_string(),
+    };
+    let serialized = msg.to_msgpack_signed(b"secret").unwrap();
+    assert!(serialized.starts_with(b"HL"));
+    let deserialized =
+        PrefixedMessage::from_msgpack_verified(&serialized, Some(b"secret")).unwrap();
+    assert_eq!(deserialized.asset, "BTC");
+
+    // Payloads framed by the default codec are rejected
+    let order = OrderRequest::market("BTC", true, "1").to_msgpack().unwrap();
+    let err = PrefixedMessage::from_msgpack(&order).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidFormat(_)));
+}
+
+#[test]
+fn test_newer_schema_version_rejected() {
+    let order = OrderRequest::market("BTC", true, "1.0");
+    let serialized = order.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).version, SCHEMA_VERSION);
+
+    let mut header = MessageHeader::new(MessageType::Order);
+    header.version = SCHEMA_VERSION + 1;
+    let header_bytes = rmp_serde::to_vec_named(&header).unwrap();
+    let mut data = (header_bytes.len() as u32).to_be_bytes().to_vec();
+    data.extend(header_bytes);
+    data.extend(rmp_serde::to_vec_named(&order).unwrap());
+
+    let err = OrderRequest::from_msgpack(&data).unwrap_err();
+    assert!(matches!(err, MessageError::UnsupportedVersion { .. }));
+}
+
+#[test]
+fn test_trigger_order_serialization() {
+    let order = OrderRequest::limit("BTC", false, "0.5", "60000.0")
+        .with_reduce_only(true)
+        .with_trigger(TriggerSpec::new("60500.0", true, "tp"));
+    let serialized = order.to_msgpack().unwrap();
+    let deserialized = OrderRequest::from_msgpack(&serialized).unwrap();
+    let trigger = deserialized.trigger.unwrap();
+    assert_eq!(trigger.trigger_px, "60500.0");
+    assert!(trigger.is_market);
+    assert!(trigger.validate().is_ok());
+
+    assert!(TriggerSpec::new("60500.0", true, "stop").validate().is_err());
+    assert!(TriggerSpec::new("abc", true, "sl").validate().is_err());
+}
+
+#[test]
+fn test_batch_order_normal_tpsl() {
+    let entry = OrderRequest::limit("BTC", true, "0.5", "60000.0");
+    let tp = OrderRequest::limit("BTC", false, "0.5", "66000.0")
+        .with_reduce_only(true)
+        .with_trigger(TriggerSpec::new("66000.0", true, "tp"));
+    let sl = OrderRequest::limit("BTC", false, "0.5", "57000.0")
+        .with_reduce_only(true)
+        .with_trigger(TriggerSpec::new("57000.0", true, "sl"));
+    let bracket = BatchOrderRequest::bracket(entry.clone(), vec![tp.clone(), sl.clone()]);
+    assert!(bracket.validate().is_ok());
+    assert_eq!(bracket.grouping.as_str(), "normalTpsl");
+    assert_eq!(bracket.asset(), Some("BTC"));
+
+    let serialized = bracket.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::BatchOrder);
+    let deserialized = BatchOrderRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.orders.len(), 3);
+    assert_eq!(deserialized.grouping, OrderGrouping::NormalTpsl);
+
+    // An exit on the same side that isn't reduce-only, and a second take profit
+    let bad_exit = OrderRequest::limit("BTC", true, "0.5", "66000.0")
+        .with_trigger(TriggerSpec::new("66000.0", true, "tp"));
+    let err = BatchOrderRequest::bracket(entry.clone(), vec![tp.clone(), bad_exit])
+        .validate()
+        .unwrap_err();
+    assert_eq!(err.problems().len(), 3);
+    // No exits, or a triggered entry
+    assert!(BatchOrderRequest::bracket(entry.clone(), Vec::new()).validate().is_err());
+    assert!(BatchOrderRequest::bracket(tp.clone(), vec![sl.clone()]).validate().is_err());
+    // A market order can't be batched
+    let market = OrderRequest::market("BTC", true, "0.5");
+    assert!(BatchOrderRequest::new(vec![market]).validate().is_err());
+    assert!(BatchOrderRequest::new(vec![entry])
+        .with_grouping(OrderGrouping::PositionTpsl)
+        .validate()
+        .is_err());
+    assert!(BatchOrderRequest::new(vec![tp, sl])
+        .with_grouping(OrderGrouping::PositionTpsl)
+        .validate()
+        .is_ok());
+
+    let request = MessageHeader::new(MessageType::BatchOrder);
+    let response = BatchOrderResponse::new(vec![
+        OrderResponse::resting(1),
+        OrderResponse::new(OrderStatus::WaitingForTrigger),
+    ]);
+    let reply = response.to_reply(&request).unwrap();
+    assert_eq!(header_of(&reply).msg_type, MessageType::BatchOrderResponse);
+    assert_eq!(BatchOrderResponse::from_msgpack(&reply).unwrap(), response);
+
+    let partial = BatchResponse::new(vec![
+        ItemResult::success(0, Some(1)),
+        ItemResult::failure(1, "Insufficient margin"),
+    ]);
+    assert!(!partial.all_ok());
+    let reply = partial.to_reply(&request).unwrap();
+    assert_eq!(header_of(&reply).msg_type, MessageType::BatchResponse);
+    let deserialized = BatchResponse::from_msgpack(&reply).unwrap();
+    assert_eq!(deserialized, partial);
+    assert_eq!(deserialized.results[1].error.as_deref(), Some("Insufficient margin"));
+}
+
+/// An order as published before `OrderRequest` had a `market` field
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct LegacyOrderRequest {
+    asset: String,
+    is_buy: bool,
+    size: String,
+    limit_price: Option<String>,
+    cloid: Option<String>,
+    reduce_only: bool,
+    time_in_force: String,
+}
+
+impl Message for LegacyOrderRequest {
+    fn message_type() -> MessageType {
+        MessageType::Order
+    }
+}
+
+/// An order carrying only the fields that have no default
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct MinimalOrderRequest {
+    asset: String,
+    is_buy: bool,
+    size: String,
+    time_in_force: String,
+}
+
+impl Message for MinimalOrderRequest {
+    fn message_type() -> MessageType {
+        MessageType::Order
+    }
+}
+
+#[test]
+fn test_order_missing_fields_default() {
+    let minimal = MinimalOrderRequest {
+        asset: "BTC".to_string(),
+        is_buy: false,
+        size: "0.5".to_string(),
+        time_in_force: "Gtc".to_string(),
+    };
+    let order = OrderRequest::from_msgpack(&minimal.to_msgpack().unwrap()).unwrap();
+    assert_eq!(order.limit_price, None);
+    assert_eq!(order.cloid, None);
+    assert!(!order.reduce_only);
+    assert_eq!(order.time_in_force, TimeInForce::Gtc);
+    assert_eq!(order.market, MarketKind::Perp);
+}
+
+#[test]
+fn test_order_slippage() {
+    let order = OrderRequest::market_with_slippage("BTC", true, "0.1", 0.01);
+    assert_eq!(order.time_in_force, TimeInForce::Ioc);
+    assert!(order.validate_slippage().is_ok());
+    let deserialized = OrderRequest::from_msgpack(&order.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.slippage, Some(0.01));
+
+    assert!(OrderRequest::market("BTC", true, "0.1").validate_slippage().is_ok());
+    assert!(OrderRequest::market_with_slippage("BTC", true, "0.1", 2.0)
+        .validate_slippage()
+        .is_err());
+    let mut limit = OrderRequest::limit("BTC", true, "0.1", "65000");
+    limit.slippage = Some(0.01);
+    assert!(limit.validate_slippage().is_err());
+}
+
+#[test]
+fn test_order_validation_collects_problems() {
+    assert!(OrderRequest::limit("BTC", true, "0.1", "65000").validate().is_ok());
+
+    let mut order = OrderRequest::limit("BTC", true, "-1", "abc");
+    order.cloid = Some("not a uuid".to_string());
+    let err = order.validate().unwrap_err();
+    assert!(matches!(err, MessageError::ValidationMany(_)));
+    assert_eq!(err.problems().len(), 3);
+
+    let err = OrderRequest::market("BTC", true, "0").validate().unwrap_err();
+    assert!(matches!(err, MessageError::Validation(_)));
+    assert_eq!(err.problems().len(), 1);
+
+    let err = ModifyOrderRequest::by_order_id(1).validate().unwrap_err();
+    assert_eq!(err.problems().len(), 1);
+    let err = SpotTransferRequest::new("1.5", "0x1234", "").validate().unwrap_err();
+    assert_eq!(err.problems().len(), 2);
+}
+
+#[test]
+fn test_order_subject() {
+    let order = OrderRequest::market("BTC", true, "0.1");
+    assert_eq!(order.subject("hyperliquid.orders"), "hyperliquid.orders.btc");
+    let spot = OrderRequest::limit("PURR/USDC", true, "10", "0.2");
+    assert_eq!(spot.subject("orders"), "orders.purr/usdc");
+
+    let subject = order.subject("hyperliquid.orders");
+    assert_eq!(prefixed_subject("team-a", &subject), "team-a.hyperliquid.orders.btc");
+    assert_eq!(prefixed_subject("team-a.", &subject), "team-a.hyperliquid.orders.btc");
+    assert_eq!(prefixed_subject("", &subject), subject);
+}
+
+#[test]
+fn test_default_subject() {
+    let order = OrderRequest::market("BTC", true, "0.1");
+    assert_eq!(order.default_subject(), "hyperliquid.orders.btc");
+    assert_eq!(order.subject_under("desk"), "desk.orders.btc");
+    assert_eq!(order.subject_under(""), "orders.btc");
+    let transfer = TransferRequest::new("USDC", "10", "0x1234");
+    assert_eq!(transfer.default_subject(), "hyperliquid.transfers");
+    let cancel = CancelAllRequest::all();
+    assert_eq!(cancel.default_subject(), "hyperliquid.orders");
+
+    // Both ways of naming an order's subject agree
+    assert_eq!(order.subject("hyperliquid.orders"), order.default_subject());
+
+    let any = AnyMessage::Order(order);
+    assert_eq!(any.subject_under(DEFAULT_SUBJECT_ROOT), "hyperliquid.orders.btc");
+}
+
+#[test]
+fn test_client_request_conversions() {
+    let cloid = "1e60610f-0b3c-4f14-9e1c-3e2b4c9a1a2b";
+    let limit = OrderRequest::limit("ETH", false, "2.0", "1800.5").with_cloid(cloid);
+    let order = ClientOrderRequest::try_from(&limit).unwrap();
+    assert_eq!((order.sz, order.limit_px), (2.0, 1800.5));
+    assert_eq!(order.cloid.unwrap().to_string(), cloid);
+    assert!(matches!(order.order_type, ClientOrder::Limit(ref limit) if limit.tif == "Gtc"));
+    assert!(MarketOrderParams::try_from(&limit).is_err());
+
+    // A trigger without a limit price executes at the trigger price
+    let stop = OrderRequest::market("BTC", false, "0.1")
+        .with_trigger(TriggerSpec::new("60000", true, "sl"));
+    let order = ClientOrderRequest::try_from(&stop).unwrap();
+    assert_eq!(order.limit_px, 60000.0);
+    assert!(matches!(order.order_type, ClientOrder::Trigger(ref trigger) if trigger.tpsl == "sl"));
+
+    let market = OrderRequest::market_with_slippage("BTC", true, "0.5", 0.01);
+    let params = MarketOrderParams::try_from(&market).unwrap();
+    assert_eq!((params.asset, params.sz, params.slippage), ("BTC", 0.5, Some(0.01)));
+    assert!(matches!(
+        ClientOrderRequest::try_from(&market),
+        Err(MessageError::Validation(_))
+    ));
+    let bad_size = OrderRequest::limit("ETH", true, "lots", "1800");
+    assert!(matches!(
+        ClientOrderRequest::try_from(&bad_size),
+        Err(MessageError::Validation(_))
+    ));
+
+    let by_id = CancelOrderRequest::by_order_id("BTC", 42);
+    assert_eq!(ClientCancelRequest::try_from(&by_id).unwrap().oid, 42);
+    assert!(ClientCancelRequestCloid::try_from(&by_id).is_err());
+    let by_cloid = CancelOrderRequest::by_cloid("BTC", cloid);
+    assert_eq!(ClientCancelRequestCloid::try_from(&by_cloid).unwrap().cloid.to_string(), cloid);
+    let bad_cloid = CancelOrderRequest::by_cloid("BTC", "abc");
+    assert!(ClientCancelRequestCloid::try_from(&bad_cloid).is_err());
+}
+
+#[test]
+fn test_order_market_kind() {
+    let legacy = LegacyOrderRequest {
+        asset: "BTC".to_string(),
+        is_buy: true,
+        size: "1.0".to_string(),
+        limit_price: None,
+        cloid: None,
+        reduce_only: false,
+        time_in_force: "Ioc".to_string(),
+    };
+    let deserialized = OrderRequest::from_msgpack(&legacy.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.market, MarketKind::Perp);
+    assert_eq!(deserialized.time_in_force, TimeInForce::Ioc);
+    assert_eq!(deserialized.slippage, None);
+
+    let spot = OrderRequest::limit("PURR/USDC", true, "10", "0.2").with_market(MarketKind::Spot);
+    let deserialized = OrderRequest::from_msgpack(&spot.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.market, MarketKind::Spot);
+
+    assert!(MarketKind::Spot.validate_asset("PURR/USDC").is_ok());
+    assert!(MarketKind::Spot.validate_asset("@107").is_ok());
+    assert!(MarketKind::Spot.validate_asset("BTC").is_err());
+    assert!(MarketKind::Perp.validate_asset("BTC").is_ok());
+    assert!(MarketKind::Perp.validate_asset("PURR/USDC").is_err());
+}
+
+#[test]
+fn test_order_builder() {
+    // Orders published before the builder fields existed route without one
+    let legacy = LegacyOrderRequest {
+        asset: "BTC".to_string(),
+        is_buy: true,
+        size: "1.0".to_string(),
+        limit_price: Some("50000".to_string()),
+        cloid: None,
+        reduce_only: false,
+        time_in_force: "Gtc".to_string(),
+    };
+    let deserialized = OrderRequest::from_msgpack(&legacy.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.builder, None);
+    assert_eq!(deserialized.builder_fee_tenths_bps, None);
+    assert!(deserialized.validate_builder().is_ok());
+
+    let builder = "0x8c967e73e7b15087c42a10d344cff4c96d877f1d";
+    let order = OrderRequest::limit("BTC", true, "1.0", "50000").with_builder(builder, 10);
+    let deserialized = OrderRequest::from_msgpack(&order.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.builder.as_deref(), Some(builder));
+    assert_eq!(deserialized.builder_fee_tenths_bps, Some(10));
+    assert!(deserialized.validate_builder().is_ok());
+
+    let invalid = OrderRequest::limit("BTC", true, "1.0", "50000").with_builder("0x1234", 10);
+    assert!(invalid.validate_builder().is_err());
+    let mut missing_fee = order.clone();
+    missing_fee.builder_fee_tenths_bps = None;
+    assert!(missing_fee.validate_builder().is_err());
+}
+
+#[test]
+fn test_vault_address() {
+    let vault = "0x8c967e73e7b15087c42a10d344cff4c96d877f1d";
+    let order = OrderRequest::limit("BTC", true, "1.0", "50000").with_vault_address(vault);
+    let deserialized = OrderRequest::from_msgpack(&order.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.vault_address.as_deref(), Some(vault));
+    assert!(deserialized.validate().is_ok());
+    let invalid = OrderRequest::limit("BTC", true, "1.0", "50000").with_vault_address("0x12");
+    assert!(invalid.validate().is_err());
+
+    let cancel = CancelOrderRequest::by_order_id("BTC", 7).with_vault_address(vault);
+    let deserialized = CancelOrderRequest::from_msgpack(&cancel.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.vault_address.as_deref(), Some(vault));
+    assert!(deserialized.validate().is_ok());
+    let invalid = CancelOrderRequest::by_order_id("BTC", 7).with_vault_address("vault");
+    assert!(invalid.validate().is_err());
+
+    let mixed = BatchOrderRequest::new(vec![
+        OrderRequest::limit("BTC", true, "1.0", "50000").with_vault_address(vault),
+        OrderRequest::limit("ETH", true, "1.0", "3000"),
+    ]);
+    assert!(mixed.validate().is_err());
+}
+
+#[test]
+fn test_order_expiry() {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let order = OrderRequest::limit("BTC", true, "1.0", "50000").with_expiry(now + 60_000);
+    let deserialized = OrderRequest::from_msgpack(&order.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.expires_at_ms, Some(now + 60_000));
+    assert!(deserialized.validate().is_ok());
+
+    let past = OrderRequest::limit("BTC", true, "1.0", "50000").with_expiry(now - 1);
+    assert!(past.validate_expiry().is_err());
+    let ioc = order.clone().with_time_in_force(TimeInForce::Ioc);
+    assert!(ioc.validate_expiry().is_err());
+    let market = OrderRequest::market("BTC", true, "1.0").with_expiry(now + 60_000);
+    assert!(market.validate_expiry().is_err());
+    assert!(BatchOrderRequest::new(vec![order.clone()]).validate().is_err());
+
+    // Judged by the given clock rather than the system's
+    let clock = ManualClock::new(now + 30_000);
+    assert!(order.validate_expiry_with(&clock).is_ok());
+    clock.advance(std::time::Duration::from_secs(30));
+    assert!(order.validate_expiry_with(&clock).is_err());
+    assert!(order.validate_with(&clock).is_err());
+}
+
+#[test]
+fn test_order_precision() {
+    // BTC trades at 5 size decimals, leaving 1 price decimal
+    let btc = AssetPrecision::perp(5);
+    assert_eq!(btc.price_decimals, 1);
+    assert_eq!(AssetPrecision::spot(2).price_decimals, 6);
+
+    let mut order = OrderRequest::limit("BTC", true, "0.0123456", "50123.47")
+        .with_trigger(TriggerSpec::new("49999.95", false, "sl"));
+    assert_eq!(order.check_precision(btc).unwrap_err().problems().len(), 3);
+    order.normalize(btc).unwrap();
+    assert_eq!(order.size, "0.01235");
+    assert_eq!(order.limit_price.as_deref(), Some("50123"));
+    assert_eq!(order.trigger.as_ref().unwrap().trigger_px, "50000");
+    assert!(order.check_precision(btc).is_ok());
+
+    // Whole-number prices are allowed past five significant figures
+    let order = OrderRequest::limit("BTC", true, "1", "123456");
+    assert!(order.check_precision(btc).is_ok());
+
+    let mut dust = OrderRequest::limit("BTC", true, "0.000001", "50000");
+    assert!(dust.normalize(btc).is_err());
+}
+
+#[test]
+fn test_time_in_force() {
+    for tif in [TimeInForce::Gtc, TimeInForce::Ioc, TimeInForce::Fok, TimeInForce::Alo] {
+        assert_eq!(tif.to_string().parse::<TimeInForce>().unwrap(), tif);
+    }
+    assert!("gtc".parse::<TimeInForce>().is_err());
+
+    let order =
+        OrderRequest::limit("ETH", false, "1.0", "1800.5").with_time_in_force(TimeInForce::Alo);
+    let deserialized = OrderRequest::from_msgpack(&order.to_msgpack().unwrap()).unwrap();
+    assert_eq!(deserialized.time_in_force, TimeInForce::Alo);
+}
+
+#[test]
+fn test_message_expiry() {
+    let order = OrderRequest::market("BTC", true, "1.0");
+
+    let header = header_of(&order.to_msgpack().unwrap());
+    assert_eq!(header.expires_at - header.timestamp, DEFAULT_EXPIRATION_SECS * 1000);
+
+    let header = header_of(&order.to_msgpack_with_expiry(2).unwrap());
+    assert_eq!(header.expires_at - header.timestamp, 2000);
+    assert!(!header.is_expired());
+
+    let header = MessageHeader::new(MessageType::Order).with_expiration_ms(250);
+    assert_eq!(header.expires_at - header.timestamp, 250);
+    assert!(!header.is_expired());
+
+    let header = MessageHeader::new(MessageType::Order);
+    assert!(header.clone().with_deadline(header.timestamp - 1).is_expired());
+    assert!(!header.clone().with_deadline(header.timestamp + 60_000).is_expired());
+    // A zero deadline never expires, on the header and through decoding
+    let header = header.with_deadline(0);
+    assert!(!header.is_expired());
+    let serialized = order.to_msgpack_with_header(header).unwrap();
+    assert!(OrderRequest::from_msgpack(&serialized).is_ok());
+
+    // A manual clock steps through expiry deterministically
+    let clock = ManualClock::new(1_000);
+    let header = MessageHeader::new_with_clock(MessageType::Order, &clock);
+    assert_eq!(header.timestamp, 1_000);
+    let header = header.with_expiration_ms(500);
+    assert!(!header.is_expired_with(&clock));
+    clock.advance(std::time::Duration::from_millis(500));
+    assert!(!header.is_expired_with(&clock));
+    clock.set(1_501);
+    assert!(header.is_expired_with(&clock));
+}
+
+#[test]
+fn test_to_msgpack_with_header() {
+    let order = OrderRequest::market("BTC", true, "1.0");
+    let header = MessageHeader::new(MessageType::Order)
+        .with_correlation_id([9; 16])
+        .with_expiration_secs(5);
+    let serialized = order.to_msgpack_with_header(header.clone()).unwrap();
+    let written = header_of(&serialized);
+    assert_eq!(written.msg_id, header.msg_id);
+    assert_eq!(written.correlation_id, Some([9; 16]));
+    assert_eq!(written.expires_at, header.expires_at);
+    assert!(OrderRequest::from_msgpack(&serialized).is_ok());
+
+    let wrong_type = MessageHeader::new(MessageType::Withdraw);
+    assert!(matches!(
+        order.to_msgpack_with_header(wrong_type),
+        Err(MessageError::MismatchedType { .. })
+    ));
+}
+
+#[test]
+fn test_ack_response_reply() {
+    let request = MessageHeader::new(MessageType::Transfer).with_correlation_id([3; 16]);
+    let reply = AckResponse::failure("insufficient balance").to_reply(&request).unwrap();
+    let header = header_of(&reply);
+    assert_eq!(header.msg_type, MessageType::Ack);
+    assert_eq!(header.correlation_id, Some([3; 16]));
+    let deserialized = AckResponse::from_msgpack(&reply).unwrap();
+    assert!(!deserialized.ok);
+    assert_eq!(deserialized.message.as_deref(), Some("insufficient balance"));
+
+    assert_eq!(request.reply(MessageType::Ack).correlation_id, Some([3; 16]));
+
+    // Requests without a correlation id are correlated by their msg_id
+    let request = MessageHeader::new(MessageType::Transfer);
+    let reply = AckResponse::success().to_reply(&request).unwrap();
+    assert_eq!(header_of(&reply).correlation_id, Some(request.msg_id));
+    assert_eq!(AckResponse::from_msgpack(&reply).unwrap(), AckResponse::success());
+}
+
+#[test]
+fn test_position_query_serialization() {
+    let query = PositionQueryRequest::for_address("0x8c967e73e7b15087c42a10d344cff4c96d877f1d");
+    let serialized = query.to_msgpack().unwrap();
+    assert_eq!(header_of(&serialized).msg_type, MessageType::PositionQuery);
+    let deserialized = PositionQueryRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.address, query.address);
+
+    let request = MessageHeader::new(MessageType::PositionQuery).with_correlation_id([4; 16]);
+    let response = PositionQueryResponse::new(vec![PositionSummary {
+        asset: "BTC".to_string(),
+        szi: "-0.5".to_string(),
+        entry_px: Some("50000".to_string()),
+        unrealized_pnl: "12.5".to_string(),
+    }]);
+    let reply = response.to_reply(&request).unwrap();
+    let header = header_of(&reply);
+    assert_eq!(header.msg_type, MessageType::PositionQueryResponse);
+    assert_eq!(header.correlation_id, Some([4; 16]));
+    assert_eq!(PositionQueryResponse::from_msgpack(&reply).unwrap(), response);
+}
+
+#[test]
+fn test_resolve_cloid_serialization() {
+    let query = ResolveCloidRequest::new("550e8400-e29b-41d4-a716-446655440000");
+    assert!(query.validate().is_ok());
+    assert!(ResolveCloidRequest::new("abc").validate().is_err());
+    let serialized = query.to_msgpack().unwrap();
+    let header = header_of(&serialized);
+    assert_eq!(header.msg_type, MessageType::ResolveCloid);
+    assert_eq!(header.msg_type.category(), MessageCategory::Query);
+    let deserialized = ResolveCloidRequest::from_msgpack(&serialized).unwrap();
+    assert_eq!(deserialized.cloid, query.cloid);
+
+    for response in [ResolveCloidResponse::new(Some(42)), ResolveCloidResponse::new(None)] {
+        let reply = response.to_reply(&header).unwrap();
+        assert_eq!(header_of(&reply).msg_type, MessageType::ResolveCloidResponse);
+        assert_eq!(ResolveCloidResponse::from_msgpack(&reply).unwrap(), response);
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_round_trip() {
+    let order = OrderRequest::limit("ETH", false, "1.0", "1800.5").with_cloid("abc");
+    let json = order.to_json().unwrap();
+
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["header"]["msg_type"], "Order");
+    assert_eq!(value["header"]["msg_id"].as_str().unwrap().len(), 32);
+    assert_eq!(value["body"]["asset"], "ETH");
+
+    let deserialized = OrderRequest::from_json(json.as_bytes()).unwrap();
+    assert_eq!(deserialized.limit_price, order.limit_price);
+    assert_eq!(deserialized.cloid, order.cloid);
+
+    // The header is validated as for msgpack messages
+    let err = CancelOrderRequest::from_json(json.as_bytes()).unwrap_err();
+    assert!(matches!(err, MessageError::MismatchedType { .. }));
+    let mut unknown = value.clone();
+    unknown["header"]["msg_type"] = "Unknown".into();
+    let err = OrderRequest::from_json(unknown.to_string().as_bytes()).unwrap_err();
+    assert!(matches!(err, MessageError::InvalidFormat(_)));
 }